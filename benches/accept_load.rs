@@ -0,0 +1,247 @@
+//! Accept load benchmark
+//!
+//! Opens many WebTransport sessions against `N` sharded endpoints, each
+//! drained by its own `accept::spawn_accept_loop`, and reports sessions per
+//! second for each shard count. Every session does what a client does first:
+//! open the control stream, send a `PING` and read the reply. Accepted
+//! connections are registered in one shared `ConnectionStore` with ids from
+//! one shared counter, as the server does.
+//!
+//! The server side always runs on a runtime with `--threads` worker threads
+//! (one per CPU by default), so only the shard count changes between runs.
+//! Clients run on their own runtime, one client endpoint per concurrent
+//! client, spread round-robin over the shards' ports.
+//!
+//! ```text
+//! cargo bench --bench accept_load -- --shards 1,2,4,8 --sessions 5000 --concurrency 128
+//! ```
+
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::Semaphore;
+use wtransport::endpoint::{endpoint_side, IncomingSession};
+use wtransport::tls::Sha256Digest;
+use wtransport::{ClientConfig, Endpoint, Identity, ServerConfig};
+
+use wmtp_server::accept::{self, ConnectionIds, SharedConnectionIds};
+use wmtp_server::connection::{create_connection_store, make_connection_info, ConnectionStore};
+
+const PING: &[u8] = b"{\"cmd\":\"PING\",\"data\":{}}\n";
+const PONG: &[u8] = b"{\"status\":\"OK\",\"cmd\":\"PING\"}\n";
+
+struct Options {
+    shards: Vec<usize>,
+    threads: usize,
+    sessions: usize,
+    concurrency: usize,
+}
+
+fn cpus() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+fn parse_options() -> Result<Options> {
+    let mut options = Options {
+        shards: vec![1, 2, 4, 8],
+        threads: cpus(),
+        sessions: 2000,
+        concurrency: 64,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("{arg} needs a value"));
+        match arg.as_str() {
+            "--shards" => {
+                options.shards = value()?
+                    .split(',')
+                    .map(|n| n.trim().parse())
+                    .collect::<Result<_, _>>()?
+            }
+            "--threads" => options.threads = value()?.parse()?,
+            "--sessions" => options.sessions = value()?.parse()?,
+            "--concurrency" => options.concurrency = value()?.parse()?,
+            // Passed by `cargo bench`
+            "--bench" => {}
+            other => return Err(anyhow!("unknown argument `{other}`")),
+        }
+    }
+    options.shards.retain(|&n| n > 0);
+    options.shards.sort_unstable();
+    options.shards.dedup();
+    if options.shards.is_empty() || options.threads == 0 {
+        return Err(anyhow!("--shards and --threads need values of at least 1"));
+    }
+    Ok(options)
+}
+
+/// One served session: answer the PING on the control stream, then wait
+/// for the client to go away
+async fn serve(incoming: IncomingSession, conn_id: u64, store: ConnectionStore) {
+    let Ok(request) = incoming.await else { return };
+    let remote = request.remote_address();
+    let Ok(connection) = request.accept().await else {
+        return;
+    };
+    store
+        .lock()
+        .unwrap()
+        .insert(conn_id, make_connection_info(conn_id, Some(remote)));
+    if let Ok((mut send, recv)) = connection.accept_bi().await {
+        let mut line = String::new();
+        if BufReader::new(recv).read_line(&mut line).await.is_ok() {
+            let _ = send.write_all(PONG).await;
+        }
+    }
+    connection.closed().await;
+    store.lock().unwrap().remove(&conn_id);
+}
+
+/// One client session; returns the time from connect to the PING reply
+async fn session(client: &Endpoint<endpoint_side::Client>, url: &str) -> Result<Duration> {
+    let started = Instant::now();
+    let connection = client.connect(url).await?;
+    let (mut send, recv) = connection.open_bi().await?.await?;
+    send.write_all(PING).await?;
+    let mut line = String::new();
+    BufReader::new(recv).read_line(&mut line).await?;
+    let elapsed = started.elapsed();
+    connection.close(0u32.into(), b"done");
+    Ok(elapsed)
+}
+
+fn client_config(hash: &Sha256Digest) -> ClientConfig {
+    ClientConfig::builder()
+        .with_bind_default()
+        .with_server_certificate_hashes([hash.clone()])
+        .build()
+}
+
+struct Run {
+    elapsed: Duration,
+    failures: usize,
+    latencies: Vec<Duration>,
+    peak_connections: usize,
+}
+
+fn run(shards: usize, options: &Options, identity: &Identity) -> Result<Run> {
+    let server_rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(options.threads)
+        .enable_all()
+        .build()?;
+    let store = create_connection_store();
+    let (ports, loops) = server_rt.block_on(async {
+        let ids: SharedConnectionIds = Arc::new(ConnectionIds::default());
+        let mut ports = Vec::with_capacity(shards);
+        let mut loops = Vec::with_capacity(shards);
+        for _ in 0..shards {
+            let config = ServerConfig::builder()
+                .with_bind_address("127.0.0.1:0".parse()?)
+                .with_identity(identity)
+                .build();
+            let endpoint = Arc::new(Endpoint::server(config)?);
+            ports.push(endpoint.local_addr()?.port());
+            let store = store.clone();
+            loops.push(accept::spawn_accept_loop(
+                endpoint,
+                ids.clone(),
+                move |incoming, conn_id| serve(incoming, conn_id, store.clone()),
+            ));
+        }
+        anyhow::Ok((ports, loops))
+    })?;
+
+    let client_rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let hash = identity.certificate_chain().as_slice()[0].hash();
+    let result = client_rt.block_on(async {
+        let remaining = Arc::new(Semaphore::new(options.sessions));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let started = Instant::now();
+        let mut tasks = Vec::new();
+        for client_no in 0..options.concurrency.max(1) {
+            let client = Endpoint::client(client_config(&hash))?;
+            let url = format!("https://127.0.0.1:{}", ports[client_no % ports.len()]);
+            let remaining = remaining.clone();
+            let store = store.clone();
+            let peak = peak.clone();
+            tasks.push(tokio::spawn(async move {
+                let mut latencies = Vec::new();
+                let mut failures = 0;
+                while let Ok(permit) = remaining.try_acquire() {
+                    permit.forget();
+                    match session(&client, &url).await {
+                        Ok(latency) => latencies.push(latency),
+                        Err(_) => failures += 1,
+                    }
+                    let open = store.lock().unwrap().len();
+                    peak.fetch_max(open, Ordering::Relaxed);
+                }
+                (latencies, failures)
+            }));
+        }
+        let mut run = Run {
+            elapsed: Duration::ZERO,
+            failures: 0,
+            latencies: Vec::with_capacity(options.sessions),
+            peak_connections: 0,
+        };
+        for task in tasks {
+            let (latencies, failures) = task.await?;
+            run.latencies.extend(latencies);
+            run.failures += failures;
+        }
+        run.elapsed = started.elapsed();
+        run.peak_connections = peak.load(Ordering::Relaxed);
+        anyhow::Ok(run)
+    });
+
+    loops.iter().for_each(tokio::task::JoinHandle::abort);
+    server_rt.shutdown_timeout(Duration::from_secs(1));
+    result
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+fn main() -> Result<()> {
+    let options = parse_options()?;
+    let identity = Identity::self_signed(["localhost", "127.0.0.1"])?;
+    println!(
+        "{} sessions, {} concurrent clients, {} server thread(s), {} CPU(s)",
+        options.sessions,
+        options.concurrency,
+        options.threads,
+        cpus()
+    );
+    println!(
+        "{:>8} {:>12} {:>10} {:>10} {:>10} {:>9}",
+        "shards", "sessions/s", "p50 ms", "p99 ms", "failures", "peak open"
+    );
+    let mut baseline = None;
+    for &shards in &options.shards {
+        let mut run = run(shards, &options, &identity)?;
+        run.latencies.sort_unstable();
+        let rate = run.latencies.len() as f64 / run.elapsed.as_secs_f64();
+        let speedup = rate / *baseline.get_or_insert(rate);
+        println!(
+            "{:>8} {:>12.0} {:>10.2} {:>10.2} {:>10} {:>9}   x{speedup:.2}",
+            shards,
+            rate,
+            percentile(&run.latencies, 0.5).as_secs_f64() * 1000.0,
+            percentile(&run.latencies, 0.99).as_secs_f64() * 1000.0,
+            run.failures,
+            run.peak_connections,
+        );
+    }
+    Ok(())
+}
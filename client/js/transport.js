@@ -26,6 +26,25 @@ class WMTPTransport {
         this.certHash = hash;
     }
 
+    /**
+     * Fetch the development certificate hash from the server's local endpoint
+     * (the certificate is rotated, so fetch before every connect)
+     * @param {string} endpoint - Hash endpoint (e.g., "http://localhost:4480/cert-hash")
+     * @returns {Promise<string>} Base64 encoded SHA-256 hash
+     */
+    async fetchCertificateHash(endpoint) {
+        const response = await fetch(endpoint, { cache: 'no-store' });
+        if (!response.ok) {
+            throw new Error(`Certificate hash endpoint returned ${response.status}`);
+        }
+        const info = await response.json();
+        if (info.algorithm !== 'sha-256' || !info.value) {
+            throw new Error('Unexpected certificate hash response');
+        }
+        this.setCertificateHash(info.value);
+        return info.value;
+    }
+
     /**
      * Connect to WMTP server
      * @param {string} url - Server URL (e.g., "https://localhost:4433")
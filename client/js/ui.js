@@ -8,6 +8,7 @@ document.addEventListener('DOMContentLoaded', () => {
     const elements = {
         // Connection
         serverUrl: document.getElementById('serverUrl'),
+        certHashUrl: document.getElementById('certHashUrl'),
         connectBtn: document.getElementById('connectBtn'),
         disconnectBtn: document.getElementById('disconnectBtn'),
         connectionStatus: document.getElementById('connectionStatus'),
@@ -169,10 +170,18 @@ document.addEventListener('DOMContentLoaded', () => {
     log(`Connecting to ${url}...`, 'info');
     
     try {
-        // Set certificate hash for self-signed cert (localhost)
+        // Development server (localhost): trust its self-signed cert by hash,
+        // served at the server's dev_cert_addr (port 4480 unless configured)
         if (url.includes('localhost') || url.includes('127.0.0.1')) {
-            wmtpTransport.setCertificateHash('NC0Zf64kkMJWUTeL6U+88xPHt6NHQMZ37Wuk/keMG2c=');
-            log('Using self-signed certificate hash', 'info');
+            const host = new URL(url).hostname;
+            const hashUrl = elements.certHashUrl.value.trim() || `http://${host}:4480/cert-hash`;
+            try {
+                await wmtpTransport.fetchCertificateHash(hashUrl);
+                log('Using self-signed certificate hash', 'info');
+            } catch (error) {
+                wmtpTransport.setCertificateHash(null);
+                log(`No certificate hash (${error.message}); trying the system trust store`, 'info');
+            }
         }
         
         await wmtpTransport.connect(url);
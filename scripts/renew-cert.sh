@@ -1,12 +1,13 @@
 #!/bin/bash
 
-# Renew Let's Encrypt certificates and restart WMTP
+# Renew Let's Encrypt certificates and reload them into WMTP
+# (SIGHUP: new handshakes use the new certificate, connected users stay online)
 
 certbot renew --quiet
 
 if [ $? -eq 0 ]; then
-    systemctl restart wmtp
-    echo "Certificates renewed and WMTP restarted"
+    systemctl reload wmtp
+    echo "Certificates renewed and WMTP reloaded"
 else
     echo "Certificate renewal failed"
     exit 1
@@ -51,15 +51,23 @@ echo -e "${YELLOW}Getting SSL certificate...${NC}"
 read -p "Enter domain (e.g., api.wmtp.online): " DOMAIN
 certbot certonly --standalone -d $DOMAIN
 
+# Server secret, handed to the service as a systemd credential
+echo -e "${YELLOW}Creating server secret...${NC}"
+mkdir -p /etc/wmtp
+if [ ! -f /etc/wmtp/server_secret ]; then
+    (umask 077 && openssl rand -hex 32 > /etc/wmtp/server_secret)
+fi
+
 # Create .env
 echo -e "${YELLOW}Creating configuration...${NC}"
 cat > .env << EOF
+WMTP_PRODUCTION=true
 WMTP_HOST=0.0.0.0
 WMTP_PORT=443
 WMTP_DOMAIN=${DOMAIN}
-WMTP_SERVER_SECRET=$(openssl rand -hex 32)
 WMTP_CERT_PATH=/etc/letsencrypt/live/${DOMAIN}/fullchain.pem
 WMTP_KEY_PATH=/etc/letsencrypt/live/${DOMAIN}/privkey.pem
+WMTP_STORAGE_URI=mongodb://localhost:27017
 WMTP_SESSION_TIMEOUT=3600
 WMTP_HEARTBEAT_INTERVAL=5
 RUST_LOG=info,wmtp_server=debug
@@ -77,6 +85,9 @@ Type=simple
 User=root
 WorkingDirectory=/opt/wmtp/server
 ExecStart=/opt/wmtp/server/target/release/wmtp-server
+ExecReload=/bin/kill -HUP \$MAINPID
+LoadCredential=server_secret:/etc/wmtp/server_secret
+Environment=WMTP_SERVER_SECRET_FILE=%d/server_secret
 Restart=always
 RestartSec=5
 Environment=RUST_LOG=info
@@ -93,7 +104,7 @@ systemctl start wmtp
 
 # Setup auto-renewal
 echo -e "${YELLOW}Setting up certificate renewal...${NC}"
-echo "0 0 1 * * certbot renew --quiet && systemctl restart wmtp" | crontab -
+echo "0 0 1 * * certbot renew --quiet && systemctl reload wmtp" | crontab -
 
 # Firewall
 echo -e "${YELLOW}Configuring firewall...${NC}"
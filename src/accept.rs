@@ -0,0 +1,140 @@
+//! Sharded accept
+//!
+//! wtransport 0.1 binds each endpoint's UDP socket itself and offers no way
+//! to set `SO_REUSEPORT` or to pass in a socket, so several endpoints cannot
+//! share one port. A listener is sharded across ports instead: with
+//! `accept_shards = N` it runs N endpoints on consecutive ports starting at
+//! its own, each with its own socket and its own accept task that hands
+//! sessions straight to the connection handler. Nothing funnels sessions
+//! through a single task; clients spread themselves over the ports.
+//!
+//! Connection ids come from one counter, and sessions and connections live in
+//! the shared stores, so every command sees them whichever shard accepted
+//! them.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::task::JoinHandle;
+use wtransport::endpoint::{endpoint_side, IncomingSession};
+use wtransport::Endpoint;
+
+/// WebTransport server endpoint
+pub type ServerEndpoint = Endpoint<endpoint_side::Server>;
+
+/// Connection ids, unique across listeners and shards
+#[derive(Debug)]
+pub struct ConnectionIds(AtomicU64);
+
+/// Thread-safe connection id counter type
+pub type SharedConnectionIds = Arc<ConnectionIds>;
+
+impl Default for ConnectionIds {
+    fn default() -> Self {
+        Self(AtomicU64::new(1))
+    }
+}
+
+impl ConnectionIds {
+    /// Id for the next accepted connection
+    pub fn next(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// Run the accept loop of one endpoint
+///
+/// Every accepted session is passed to `handle` with a fresh connection id,
+/// in a task of its own. The loop runs until its handle is aborted.
+pub fn spawn_accept_loop<F, Fut>(
+    endpoint: Arc<ServerEndpoint>,
+    ids: SharedConnectionIds,
+    handle: F,
+) -> JoinHandle<()>
+where
+    F: Fn(IncomingSession, u64) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let incoming = endpoint.accept().await;
+            tokio::spawn(handle(incoming, ids.next()));
+        }
+    })
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use wtransport::{ClientConfig, Identity, ServerConfig};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_shards_accept_with_shared_ids() {
+        let identity = Identity::self_signed(["localhost"]).unwrap();
+        let hash = identity.certificate_chain().as_slice()[0].hash();
+        let ids: SharedConnectionIds = Arc::new(ConnectionIds::default());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+
+        let mut ports = Vec::new();
+        let mut loops = Vec::new();
+        for shard in 0..2 {
+            let server_config = ServerConfig::builder()
+                .with_bind_address("127.0.0.1:0".parse().unwrap())
+                .with_identity(&identity)
+                .build();
+            let endpoint = Arc::new(Endpoint::server(server_config).unwrap());
+            ports.push(endpoint.local_addr().unwrap().port());
+            let seen = seen.clone();
+            loops.push(spawn_accept_loop(
+                endpoint,
+                ids.clone(),
+                move |incoming, conn_id| {
+                    let seen = seen.clone();
+                    async move {
+                        let connection = incoming.await.unwrap().accept().await.unwrap();
+                        seen.lock().unwrap().push((shard, conn_id));
+                        connection.closed().await;
+                    }
+                },
+            ));
+        }
+
+        let client = Endpoint::client(
+            ClientConfig::builder()
+                .with_bind_default()
+                .with_server_certificate_hashes([hash])
+                .build(),
+        )
+        .unwrap();
+        let mut connections = Vec::new();
+        for i in 0..6 {
+            let port = ports[i % 2];
+            connections.push(
+                client
+                    .connect(format!("https://127.0.0.1:{port}"))
+                    .await
+                    .unwrap(),
+            );
+        }
+        for _ in 0..50 {
+            if seen.lock().unwrap().len() == 6 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        let seen = seen.lock().unwrap().clone();
+        assert_eq!(seen.iter().filter(|(shard, _)| *shard == 0).count(), 3);
+        assert_eq!(seen.iter().filter(|(shard, _)| *shard == 1).count(), 3);
+        let mut conn_ids: Vec<u64> = seen.iter().map(|(_, id)| *id).collect();
+        conn_ids.sort_unstable();
+        assert_eq!(conn_ids, vec![1, 2, 3, 4, 5, 6]);
+        loops.iter().for_each(JoinHandle::abort);
+    }
+}
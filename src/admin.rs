@@ -0,0 +1,1020 @@
+//! Local admin socket
+//!
+//! Operators manage a running server through a Unix domain socket
+//! (`admin_socket`), normally with the `wmtpctl` binary. The socket is
+//! created with mode 0600, so only the user running the server can use it;
+//! there is no further authentication.
+//!
+//! The protocol is newline-delimited JSON: one `{"cmd": ..., "data": {...}}`
+//! request per line, answered by one response line in the same format as
+//! client responses. Sessions are identified by their `session_id` (see
+//! `logging::session_id`), never by token. Every state-changing command is
+//! recorded in the audit log with the actor `admin`.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::audit::{AuditEntry, AuditEvent, SharedAuditWriter};
+use crate::commands::{notices, Request, Response};
+use crate::connection::{unbind_connection_info, ConnectionStore, SharedConnectionIndex};
+use crate::error::{codes, WmtpError, WmtpResult};
+use crate::lockout::SharedLockouts;
+use crate::logging;
+use crate::maintenance::{self, SharedMaintenance};
+use crate::reload::{ConfigReloader, SharedLiveConfig};
+use crate::session::{SessionCause, SessionManager, WmtpSession};
+
+/// Admin command names
+pub mod admin_cmd {
+    pub const SESSION_LIST: &str = "SESSION_LIST";
+    pub const SESSION_KILL: &str = "SESSION_KILL";
+    pub const CONNECTION_LIST: &str = "CONNECTION_LIST";
+    pub const CONNECTION_CLOSE: &str = "CONNECTION_CLOSE";
+    pub const LOCKOUT_LIST: &str = "LOCKOUT_LIST";
+    pub const LOCKOUT_CLEAR: &str = "LOCKOUT_CLEAR";
+    pub const SECRET_ROTATE: &str = "SECRET_ROTATE";
+    pub const CONFIG_RELOAD: &str = "CONFIG_RELOAD";
+    pub const MAINTENANCE: &str = "MAINTENANCE";
+    pub const USER_CREATE: &str = "USER_CREATE";
+    pub const USER_DISABLE: &str = "USER_DISABLE";
+    pub const USER_ENABLE: &str = "USER_ENABLE";
+    pub const USER_DELETE: &str = "USER_DELETE";
+    pub const MAILBOX_CREATE: &str = "MAILBOX_CREATE";
+    pub const MAILBOX_DELETE: &str = "MAILBOX_DELETE";
+}
+
+/// Actor recorded in the audit log for admin commands
+pub const ADMIN_ACTOR: &str = "admin";
+
+/// Boxed future returned by [`Directory`] methods
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// User and mailbox storage, as far as operators manage it
+///
+/// Methods return `Ok(false)` when the target does not exist (or, for the
+/// create methods, already exists).
+pub trait Directory: Send + Sync {
+    fn create_user<'a>(
+        &'a self,
+        email: &'a str,
+        username: Option<&'a str>,
+    ) -> BoxFuture<'a, WmtpResult<bool>>;
+    fn set_user_disabled<'a>(
+        &'a self,
+        email: &'a str,
+        disabled: bool,
+    ) -> BoxFuture<'a, WmtpResult<bool>>;
+    /// Whether `email` exists and is disabled
+    fn is_user_disabled<'a>(&'a self, email: &'a str) -> BoxFuture<'a, WmtpResult<bool>>;
+    fn delete_user<'a>(&'a self, email: &'a str) -> BoxFuture<'a, WmtpResult<bool>>;
+    fn create_mailbox<'a>(
+        &'a self,
+        email: &'a str,
+        name: &'a str,
+    ) -> BoxFuture<'a, WmtpResult<bool>>;
+    fn delete_mailbox<'a>(
+        &'a self,
+        email: &'a str,
+        name: &'a str,
+    ) -> BoxFuture<'a, WmtpResult<bool>>;
+}
+
+/// Commands that sign a user in, refused while the user is disabled
+pub const LOGIN_COMMANDS: &[&str] = &["AUTH", "RESUME", "LINK_APPROVE"];
+
+/// Refusal for a login command (see [`LOGIN_COMMANDS`]) by a disabled user
+///
+/// Fails closed: if the user's state cannot be read, the login is refused.
+pub async fn disabled_refusal(
+    directory: &dyn Directory,
+    command: &str,
+    email: &str,
+) -> Option<Response> {
+    if !LOGIN_COMMANDS.contains(&command) {
+        return None;
+    }
+    match directory
+        .is_user_disabled(&email.trim().to_lowercase())
+        .await
+    {
+        Ok(false) => None,
+        Ok(true) => Some(Response::err(
+            command,
+            "Account disabled",
+            codes::ACCOUNT_DISABLED,
+        )),
+        Err(e) => {
+            tracing::error!("Cannot check whether {email} is disabled: {e}");
+            Some(Response::err(
+                command,
+                "Account state unavailable",
+                codes::SERVICE_UNAVAILABLE,
+            ))
+        }
+    }
+}
+
+/// Everything admin commands act on
+pub struct AdminContext {
+    pub sessions: Arc<SessionManager>,
+    pub index: SharedConnectionIndex,
+    pub connections: ConnectionStore,
+    pub lockouts: SharedLockouts,
+    pub maintenance: SharedMaintenance,
+    pub live: SharedLiveConfig,
+    pub reloader: Arc<ConfigReloader>,
+    /// User and mailbox storage (user commands are unavailable if `None`)
+    pub directory: Option<Arc<dyn Directory>>,
+    pub audit_log: Option<SharedAuditWriter>,
+}
+
+fn missing(command: &str, field: &str) -> Response {
+    Response::err(
+        command,
+        &format!("Missing field: {field}"),
+        codes::MISSING_FIELD,
+    )
+}
+
+fn session_json(session: &WmtpSession, connections: usize) -> Value {
+    json!({
+        "session_id": logging::session_id(&session.token),
+        "email": session.email,
+        "authenticated": session.authenticated,
+        "suspended": session.suspended,
+        "remote_ip": session.remote_ip.map(|ip| ip.to_string()),
+        "age_secs": session.age_secs(),
+        "idle_secs": session.idle_secs(),
+        "connections": connections,
+    })
+}
+
+impl AdminContext {
+    /// Handle one request line
+    pub async fn handle(&self, line: &str) -> Response {
+        let req = match Request::from_json(line) {
+            Ok(req) => req,
+            Err(e) => {
+                return Response::err(
+                    "PARSE",
+                    &format!("Invalid JSON: {e}"),
+                    codes::MALFORMED_JSON,
+                )
+            }
+        };
+        let command = req.cmd.to_uppercase();
+        let response = self.dispatch(&command, &req).await;
+
+        let read_only = matches!(
+            command.as_str(),
+            admin_cmd::SESSION_LIST | admin_cmd::CONNECTION_LIST | admin_cmd::LOCKOUT_LIST
+        ) || (command == admin_cmd::MAINTENANCE
+            && req.get_bool("enabled").is_none());
+        if response.status == "OK" && !read_only {
+            if let Some(audit) = &self.audit_log {
+                let mut detail = req.data.clone().unwrap_or(Value::Null);
+                logging::redact_value(&mut detail);
+                audit.record_or_log(
+                    AuditEntry::new(AuditEvent::AdminAction)
+                        .with_actor(Some(ADMIN_ACTOR))
+                        .with_detail(json!({ "command": command, "data": detail })),
+                );
+            }
+        }
+        response
+    }
+
+    async fn dispatch(&self, command: &str, req: &Request) -> Response {
+        match command {
+            admin_cmd::SESSION_LIST => self.session_list(command, req),
+            admin_cmd::SESSION_KILL => self.session_kill(command, req),
+            admin_cmd::CONNECTION_LIST => self.connection_list(command),
+            admin_cmd::CONNECTION_CLOSE => self.connection_close(command, req),
+            admin_cmd::LOCKOUT_LIST => {
+                Response::ok(command).with_data(json!({ "lockouts": self.lockouts.list() }))
+            }
+            admin_cmd::LOCKOUT_CLEAR => match req.get_str("email") {
+                Some(email) => {
+                    let cleared = self.lockouts.clear(&email);
+                    Response::ok(command).with_data(json!({ "email": email, "cleared": cleared }))
+                }
+                None => missing(command, "email"),
+            },
+            admin_cmd::SECRET_ROTATE => self.secret_rotate(command),
+            admin_cmd::CONFIG_RELOAD => match self.reloader.reload() {
+                Ok(outcome) => Response::ok(command).with_data(json!(outcome)),
+                Err(e) => Response::err(command, &e.to_string(), codes::INVALID_FORMAT),
+            },
+            admin_cmd::MAINTENANCE => self.maintenance(command, req),
+            admin_cmd::USER_CREATE
+            | admin_cmd::USER_DISABLE
+            | admin_cmd::USER_ENABLE
+            | admin_cmd::USER_DELETE
+            | admin_cmd::MAILBOX_CREATE
+            | admin_cmd::MAILBOX_DELETE => self.directory_command(command, req).await,
+            _ => Response::err(
+                command,
+                &format!("Unknown admin command: {command}"),
+                codes::UNKNOWN_COMMAND,
+            ),
+        }
+    }
+
+    fn session_list(&self, command: &str, req: &Request) -> Response {
+        let email = req.get_str("email").map(|e| e.to_lowercase());
+        let sessions: Vec<Value> = self
+            .sessions
+            .list_all()
+            .iter()
+            .filter(|s| email.is_none() || s.email.as_deref().map(str::to_lowercase) == email)
+            .map(|s| session_json(s, self.index.connections_of_session(&s.token).len()))
+            .collect();
+        Response::ok(command).with_data(json!({ "count": sessions.len(), "sessions": sessions }))
+    }
+
+    /// Sessions matching a `session_id` or every session of an `email`
+    fn select_sessions(&self, req: &Request) -> Option<Vec<WmtpSession>> {
+        let all = self.sessions.list_all();
+        if let Some(id) = req.get_str("session_id") {
+            return Some(
+                all.into_iter()
+                    .filter(|s| logging::session_id(&s.token) == id)
+                    .collect(),
+            );
+        }
+        let email = req.get_str("email")?.to_lowercase();
+        Some(
+            all.into_iter()
+                .filter(|s| {
+                    s.email.as_deref().map(str::to_lowercase).as_deref() == Some(email.as_str())
+                })
+                .collect(),
+        )
+    }
+
+    /// Remove a session and close the connections holding it
+    fn kill_session(&self, token: &str) -> bool {
+        if self
+            .sessions
+            .remove_with_cause(token, SessionCause::Server(ADMIN_ACTOR.to_string()))
+            .is_none()
+        {
+            return false;
+        }
+        let notice = notices::session_killed(token).to_json();
+        self.index.close_session(token, Some(&notice));
+        self.index.unbind_session(token);
+        unbind_connection_info(&self.connections, token);
+        true
+    }
+
+    fn kill_user_sessions(&self, email: &str) -> usize {
+        let email = email.to_lowercase();
+        self.sessions
+            .list_all()
+            .iter()
+            .filter(|s| {
+                s.email.as_deref().map(str::to_lowercase).as_deref() == Some(email.as_str())
+            })
+            .filter(|s| self.kill_session(&s.token))
+            .count()
+    }
+
+    fn session_kill(&self, command: &str, req: &Request) -> Response {
+        let Some(targets) = self.select_sessions(req) else {
+            return missing(command, "session_id or email");
+        };
+        if targets.is_empty() {
+            return Response::err(command, "No matching session", codes::SESSION_NOT_FOUND);
+        }
+        let killed = targets
+            .iter()
+            .filter(|s| self.kill_session(&s.token))
+            .count();
+        Response::ok(command).with_data(json!({ "killed": killed }))
+    }
+
+    fn connection_list(&self, command: &str) -> Response {
+        let mut connections: Vec<Value> = self
+            .connections
+            .lock()
+            .unwrap()
+            .values()
+            .map(|info| {
+                json!({
+                    "id": info.id,
+                    "remote_addr": info.remote_addr,
+                    "email": info.email,
+                    "session_ids": info.session_tokens.iter().map(|t| logging::session_id(t)).collect::<Vec<_>>(),
+                    "client_version": info.client_version,
+                    "connected_at": info.connected_at,
+                    "stats": info.stats.snapshot(),
+                })
+            })
+            .collect();
+        connections.sort_by_key(|c| c["id"].as_u64());
+        Response::ok(command)
+            .with_data(json!({ "count": connections.len(), "connections": connections }))
+    }
+
+    fn connection_close(&self, command: &str, req: &Request) -> Response {
+        let Some(id) = req.get_int("id").and_then(|id| u64::try_from(id).ok()) else {
+            return missing(command, "id");
+        };
+        let notice = notices::connection_closed().to_json();
+        if !self.index.close_connection(id, Some(&notice)) {
+            return Response::err(command, "Connection not found", codes::CONNECTION_NOT_FOUND);
+        }
+        Response::ok(command).with_data(json!({ "id": id, "closed": true }))
+    }
+
+    fn secret_rotate(&self, command: &str) -> Response {
+        let Some(path) = self.live.config().server_secret_file.clone() else {
+            return Response::err(
+                command,
+                "server_secret_file is not configured; rotate the secret where it is set",
+                codes::SERVICE_UNAVAILABLE,
+            );
+        };
+        match rotate_secret(&path) {
+            Ok(previous) => Response::ok(command)
+                .with_msg("New server secret written; it takes effect when the server restarts")
+                .with_data(json!({
+                    "path": path.display().to_string(),
+                    "previous": previous.display().to_string(),
+                    "requires_restart": true,
+                })),
+            Err(e) => Response::err(command, &e.to_string(), codes::INTERNAL_ERROR),
+        }
+    }
+
+    fn maintenance(&self, command: &str, req: &Request) -> Response {
+        match req.get_bool("enabled") {
+            Some(true) => {
+                let reason = req
+                    .get_str("reason")
+                    .unwrap_or_else(|| maintenance::DEFAULT_REASON.to_string());
+                let eta = match req
+                    .get_str("eta")
+                    .map(|eta| DateTime::parse_from_rfc3339(&eta))
+                {
+                    None => None,
+                    Some(Ok(eta)) => Some(eta.with_timezone(&Utc)),
+                    Some(Err(e)) => {
+                        return Response::err(
+                            command,
+                            &format!("eta: not an RFC 3339 time: {e}"),
+                            codes::INVALID_FORMAT,
+                        )
+                    }
+                };
+                self.maintenance.enable(&reason, eta);
+            }
+            Some(false) => {
+                self.maintenance.disable();
+            }
+            None => {}
+        }
+        let window = self.maintenance.current();
+        Response::ok(command).with_data(json!({
+            "enabled": window.is_some(),
+            "window": window.map(|w| w.to_json()),
+        }))
+    }
+
+    async fn directory_command(&self, command: &str, req: &Request) -> Response {
+        let Some(directory) = &self.directory else {
+            return Response::err(
+                command,
+                "User storage is not available",
+                codes::SERVICE_UNAVAILABLE,
+            );
+        };
+        let Some(email) = req.get_str("email").map(|e| e.trim().to_lowercase()) else {
+            return missing(command, "email");
+        };
+        let mailbox = req.get_str("name");
+        let needs_mailbox = matches!(
+            command,
+            admin_cmd::MAILBOX_CREATE | admin_cmd::MAILBOX_DELETE
+        );
+        if needs_mailbox && mailbox.is_none() {
+            return missing(command, "name");
+        }
+        let name = mailbox.as_deref().unwrap_or_default();
+
+        let result = match command {
+            admin_cmd::USER_CREATE => {
+                directory
+                    .create_user(&email, req.get_str("username").as_deref())
+                    .await
+            }
+            admin_cmd::USER_DISABLE => directory.set_user_disabled(&email, true).await,
+            admin_cmd::USER_ENABLE => directory.set_user_disabled(&email, false).await,
+            admin_cmd::USER_DELETE => directory.delete_user(&email).await,
+            admin_cmd::MAILBOX_CREATE => directory.create_mailbox(&email, name).await,
+            _ => directory.delete_mailbox(&email, name).await,
+        };
+
+        match result {
+            Ok(true) => {
+                // A disabled or deleted user is signed out everywhere
+                let sessions_killed = match command {
+                    admin_cmd::USER_DISABLE | admin_cmd::USER_DELETE => {
+                        self.kill_user_sessions(&email)
+                    }
+                    _ => 0,
+                };
+                Response::ok(command).with_data(json!({
+                    "email": email,
+                    "name": mailbox,
+                    "sessions_killed": sessions_killed,
+                }))
+            }
+            Ok(false) => match command {
+                admin_cmd::USER_CREATE => {
+                    Response::err(command, "User already exists", codes::ALREADY_EXISTS)
+                }
+                admin_cmd::MAILBOX_CREATE => {
+                    Response::err(command, "Mailbox already exists", codes::ALREADY_EXISTS)
+                }
+                admin_cmd::MAILBOX_DELETE => {
+                    Response::err(command, "Mailbox not found", codes::MAILBOX_NOT_FOUND)
+                }
+                _ => Response::err(command, "User not found", codes::USER_NOT_FOUND),
+            },
+            Err(e) => Response::err(command, &e.to_string(), codes::INTERNAL_ERROR),
+        }
+    }
+}
+
+/// Fresh random server secret (64 hex characters)
+pub fn generate_secret() -> String {
+    format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// Write a new secret to `path`, keeping the old one in `<path>.previous`
+///
+/// Returns the path of the previous secret.
+pub fn rotate_secret(path: &Path) -> WmtpResult<PathBuf> {
+    let previous = PathBuf::from(format!("{}.previous", path.display()));
+    let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+    std::fs::copy(path, &previous)?;
+    write_private(&tmp, &format!("{}\n", generate_secret()))?;
+    std::fs::rename(&tmp, path)?;
+    Ok(previous)
+}
+
+fn write_private(path: &Path, contents: &str) -> WmtpResult<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    std::io::Write::write_all(&mut options.open(path)?, contents.as_bytes())?;
+    Ok(())
+}
+
+/// Serve admin requests on a Unix socket until the task is dropped
+#[cfg(unix)]
+pub async fn serve(path: PathBuf, context: Arc<AdminContext>) -> WmtpResult<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // A socket left behind by a previous run; anything else is not ours to remove
+    if let Ok(meta) = std::fs::symlink_metadata(&path) {
+        if !meta.file_type().is_socket() {
+            return Err(WmtpError::Config(format!(
+                "admin_socket: {} exists and is not a socket",
+                path.display()
+            )));
+        }
+        std::fs::remove_file(&path)?;
+    }
+    let listener = bind_private(&path)?;
+    tracing::info!("Admin socket: {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let context = context.clone();
+        tokio::spawn(async move {
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let mut out = context.handle(&line).await.to_json();
+                out.push('\n');
+                if write.write_all(out.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Bind a Unix socket at `path` that only the owner can ever connect to
+///
+/// The socket is bound inside a fresh 0700 directory next to `path`,
+/// restricted to 0600 and only then renamed into place, so there is no
+/// window where it is reachable with the default umask.
+#[cfg(unix)]
+fn bind_private(path: &Path) -> WmtpResult<tokio::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use tokio::net::UnixListener;
+
+    let dir = PathBuf::from(format!(
+        "{}.{}.d",
+        path.display(),
+        uuid::Uuid::new_v4().simple()
+    ));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let staged = dir.join("admin.sock");
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&dir);
+    Ok(bound?)
+}
+
+/// Send one admin request and wait for its response
+#[cfg(unix)]
+pub async fn call(path: &Path, command: &str, data: Value) -> WmtpResult<Response> {
+    let stream = tokio::net::UnixStream::connect(path)
+        .await
+        .map_err(|e| WmtpError::Connection(format!("{}: {e}", path.display())))?;
+    let (read, mut write) = stream.into_split();
+    let mut line = json!({ "cmd": command, "data": data }).to_string();
+    line.push('\n');
+    write.write_all(line.as_bytes()).await?;
+
+    let mut response = String::new();
+    BufReader::new(read).read_line(&mut response).await?;
+    if response.is_empty() {
+        return Err(WmtpError::Connection(
+            "admin socket closed without a response".to_string(),
+        ));
+    }
+    Ok(serde_json::from_str(&response)?)
+}
+
+// ============================================================================
+// WMTPCTL
+// ============================================================================
+
+/// `wmtpctl` usage text
+pub fn ctl_usage() -> &'static str {
+    "Usage: wmtpctl [--socket <path>] [--config <file>] [--json] <command> [args]
+
+Commands:
+  sessions [<email>]                  List sessions (of one user)
+  kill-session <session_id>           Kill a session and close its connections
+  kill-user <email>                   Kill every session of a user
+  connections                         List live connections with traffic stats
+  close-connection <id>               Close one connection (sessions stay valid)
+  lockouts                            List addresses and IPs with failed logins
+  clear-lockout <email>               Clear failed logins and lockouts from every IP
+  rotate-secret                       Write a new server secret (applied on restart)
+  reload                              Reload the configuration
+  maintenance on [<reason>] [--eta <rfc3339>]
+  maintenance off | status
+  user create <email> [<username>]
+  user disable | enable | delete <email>
+  mailbox create | delete <email> <name>
+
+The socket defaults to `admin_socket` from the server configuration.
+"
+}
+
+/// Translate `wmtpctl` arguments (after the options) into an admin request
+pub fn parse_ctl_command(args: &[String]) -> Result<(&'static str, Value), String> {
+    let words: Vec<&str> = args.iter().map(String::as_str).collect();
+    let request = match words.as_slice() {
+        ["sessions"] => (admin_cmd::SESSION_LIST, Value::Null),
+        ["sessions", email] => (admin_cmd::SESSION_LIST, json!({ "email": email })),
+        ["kill-session", id] => (admin_cmd::SESSION_KILL, json!({ "session_id": id })),
+        ["kill-user", email] => (admin_cmd::SESSION_KILL, json!({ "email": email })),
+        ["connections"] => (admin_cmd::CONNECTION_LIST, Value::Null),
+        ["close-connection", id] => {
+            let id: u64 = id
+                .parse()
+                .map_err(|_| format!("close-connection: `{id}` is not a connection id"))?;
+            (admin_cmd::CONNECTION_CLOSE, json!({ "id": id }))
+        }
+        ["lockouts"] => (admin_cmd::LOCKOUT_LIST, Value::Null),
+        ["clear-lockout", email] => (admin_cmd::LOCKOUT_CLEAR, json!({ "email": email })),
+        ["rotate-secret"] => (admin_cmd::SECRET_ROTATE, Value::Null),
+        ["reload"] => (admin_cmd::CONFIG_RELOAD, Value::Null),
+        ["maintenance", "status"] => (admin_cmd::MAINTENANCE, Value::Null),
+        ["maintenance", "off"] => (admin_cmd::MAINTENANCE, json!({ "enabled": false })),
+        ["maintenance", "on", rest @ ..] => {
+            let mut data = json!({ "enabled": true });
+            let mut reason = Vec::new();
+            let mut rest = rest.iter();
+            while let Some(word) = rest.next() {
+                if *word == "--eta" {
+                    let eta = rest.next().ok_or("--eta needs a value")?;
+                    data["eta"] = json!(eta);
+                } else {
+                    reason.push(*word);
+                }
+            }
+            if !reason.is_empty() {
+                data["reason"] = json!(reason.join(" "));
+            }
+            (admin_cmd::MAINTENANCE, data)
+        }
+        ["user", "create", email] => (admin_cmd::USER_CREATE, json!({ "email": email })),
+        ["user", "create", email, username] => (
+            admin_cmd::USER_CREATE,
+            json!({ "email": email, "username": username }),
+        ),
+        ["user", "disable", email] => (admin_cmd::USER_DISABLE, json!({ "email": email })),
+        ["user", "enable", email] => (admin_cmd::USER_ENABLE, json!({ "email": email })),
+        ["user", "delete", email] => (admin_cmd::USER_DELETE, json!({ "email": email })),
+        ["mailbox", "create", email, name] => (
+            admin_cmd::MAILBOX_CREATE,
+            json!({ "email": email, "name": name }),
+        ),
+        ["mailbox", "delete", email, name] => (
+            admin_cmd::MAILBOX_DELETE,
+            json!({ "email": email, "name": name }),
+        ),
+        [] => return Err("no command given".to_string()),
+        _ => return Err(format!("unknown command: {}", words.join(" "))),
+    };
+    Ok(request)
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::connection::{
+        create_connection_index, create_connection_store, make_connection_info, set_client_version,
+    };
+    use crate::lockout::{create_lockouts, LockoutPolicy};
+    use crate::maintenance::create_maintenance;
+    use crate::outbound::{self, OutboundConfig};
+    use crate::reload::{LiveConfig, RuntimeHooks};
+    use crate::session::create_session_store;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Email -> disabled
+    #[derive(Default)]
+    struct MemoryDirectory {
+        users: Mutex<HashMap<String, bool>>,
+    }
+
+    impl Directory for MemoryDirectory {
+        fn create_user<'a>(
+            &'a self,
+            email: &'a str,
+            _: Option<&'a str>,
+        ) -> BoxFuture<'a, WmtpResult<bool>> {
+            Box::pin(async move {
+                let mut users = self.users.lock().unwrap();
+                Ok(users.insert(email.to_string(), false).is_none())
+            })
+        }
+        fn set_user_disabled<'a>(
+            &'a self,
+            email: &'a str,
+            disabled: bool,
+        ) -> BoxFuture<'a, WmtpResult<bool>> {
+            Box::pin(async move {
+                let mut users = self.users.lock().unwrap();
+                Ok(users.get_mut(email).map(|d| *d = disabled).is_some())
+            })
+        }
+        fn is_user_disabled<'a>(&'a self, email: &'a str) -> BoxFuture<'a, WmtpResult<bool>> {
+            Box::pin(async move { Ok(self.users.lock().unwrap().get(email) == Some(&true)) })
+        }
+        fn delete_user<'a>(&'a self, email: &'a str) -> BoxFuture<'a, WmtpResult<bool>> {
+            Box::pin(async move { Ok(self.users.lock().unwrap().remove(email).is_some()) })
+        }
+        fn create_mailbox<'a>(&'a self, _: &'a str, _: &'a str) -> BoxFuture<'a, WmtpResult<bool>> {
+            Box::pin(async { Ok(true) })
+        }
+        fn delete_mailbox<'a>(&'a self, _: &'a str, _: &'a str) -> BoxFuture<'a, WmtpResult<bool>> {
+            Box::pin(async { Ok(false) })
+        }
+    }
+
+    fn context() -> AdminContext {
+        let sessions = Arc::new(SessionManager::new(create_session_store(), 3600));
+        let live = Arc::new(LiveConfig::new(Config::default()));
+        let reloader = Arc::new(ConfigReloader::new(
+            live.clone(),
+            sessions.clone(),
+            RuntimeHooks::default(),
+        ));
+        AdminContext {
+            sessions,
+            index: create_connection_index(),
+            connections: create_connection_store(),
+            lockouts: create_lockouts(LockoutPolicy::default()),
+            maintenance: create_maintenance(),
+            live,
+            reloader,
+            directory: Some(Arc::new(MemoryDirectory::default())),
+            audit_log: None,
+        }
+    }
+
+    async fn call_ok(ctx: &AdminContext, line: &str) -> Value {
+        let resp = ctx.handle(line).await;
+        assert_eq!(resp.status, "OK", "{}", resp.to_json());
+        resp.data.unwrap_or(Value::Null)
+    }
+
+    #[tokio::test]
+    async fn test_session_list_and_kill() {
+        let ctx = context();
+        ctx.sessions.insert(WmtpSession::new_authenticated(
+            "tok-a".to_string(),
+            "a@test.com".to_string(),
+        ));
+        ctx.sessions.insert(WmtpSession::new_ephemeral("tok-b".to_string()));
+        let (tx, mut rx) = outbound::channel(OutboundConfig::default());
+        ctx.index.register(1, tx);
+        ctx.index.bind("tok-a", 1, Some("a@test.com"));
+
+        let data = call_ok(&ctx, r#"{"cmd":"session_list"}"#).await;
+        assert_eq!(data["count"], 2);
+        assert!(
+            !data.to_string().contains("tok-a"),
+            "tokens must not be listed"
+        );
+
+        let data = call_ok(
+            &ctx,
+            r#"{"cmd":"SESSION_KILL","data":{"email":"A@test.com"}}"#,
+        )
+        .await;
+        assert_eq!(data["killed"], 1);
+        assert!(!ctx.sessions.exists("tok-a"));
+        assert!(matches!(
+            rx.try_recv().unwrap(),
+            crate::connection::PushFrame::Close(Some(_))
+        ));
+
+        let id = logging::session_id("tok-b");
+        call_ok(
+            &ctx,
+            &format!(r#"{{"cmd":"SESSION_KILL","data":{{"session_id":"{id}"}}}}"#),
+        )
+        .await;
+        let resp = ctx
+            .handle(r#"{"cmd":"SESSION_KILL","data":{"session_id":"nope"}}"#)
+            .await;
+        assert_eq!(resp.code, Some(codes::SESSION_NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn test_connection_list_and_close() {
+        let ctx = context();
+        let info = make_connection_info(3, None);
+        info.stats.frame_received(42);
+        ctx.connections.lock().unwrap().insert(3, info);
+        set_client_version(&ctx.connections, 3, "wmtp-js/1.0");
+        let (tx, mut rx) = outbound::channel(OutboundConfig::default());
+        ctx.index.register(3, tx);
+
+        let data = call_ok(&ctx, r#"{"cmd":"CONNECTION_LIST"}"#).await;
+        assert_eq!(data["connections"][0]["client_version"], "wmtp-js/1.0");
+        assert_eq!(data["connections"][0]["stats"]["bytes_in"], 42);
+
+        call_ok(&ctx, r#"{"cmd":"CONNECTION_CLOSE","data":{"id":3}}"#).await;
+        assert!(matches!(
+            rx.try_recv().unwrap(),
+            crate::connection::PushFrame::Close(Some(frame)) if frame.contains("CONNECTION_CLOSED")
+        ));
+        let resp = ctx
+            .handle(r#"{"cmd":"CONNECTION_CLOSE","data":{"id":4}}"#)
+            .await;
+        assert_eq!(resp.code, Some(codes::CONNECTION_NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn test_lockouts_and_maintenance() {
+        let ctx = context();
+        let ip = "10.0.0.1".parse().unwrap();
+        for _ in 0..5 {
+            ctx.lockouts.record_failure("a@test.com", ip);
+        }
+        let data = call_ok(&ctx, r#"{"cmd":"LOCKOUT_LIST"}"#).await;
+        assert_eq!(data["lockouts"][0]["locked"], true);
+        let data = call_ok(
+            &ctx,
+            r#"{"cmd":"LOCKOUT_CLEAR","data":{"email":"a@test.com"}}"#,
+        )
+        .await;
+        assert_eq!(data["cleared"], true);
+        assert!(ctx.lockouts.check("a@test.com", ip).is_none());
+
+        let data = call_ok(
+            &ctx,
+            r#"{"cmd":"MAINTENANCE","data":{"enabled":true,"reason":"upgrade","eta":"2030-01-01T00:00:00Z"}}"#,
+        )
+        .await;
+        assert_eq!(data["enabled"], true);
+        assert_eq!(data["window"]["reason"], "upgrade");
+        assert!(ctx.maintenance.is_active());
+        let resp = ctx
+            .handle(r#"{"cmd":"MAINTENANCE","data":{"enabled":true,"eta":"soon"}}"#)
+            .await;
+        assert_eq!(resp.code, Some(codes::INVALID_FORMAT));
+        call_ok(&ctx, r#"{"cmd":"MAINTENANCE","data":{"enabled":false}}"#).await;
+        assert!(!ctx.maintenance.is_active());
+    }
+
+    #[tokio::test]
+    async fn test_directory_commands() {
+        let ctx = context();
+        ctx.sessions.insert(WmtpSession::new_authenticated(
+            "tok-a".to_string(),
+            "a@test.com".to_string(),
+        ));
+
+        call_ok(
+            &ctx,
+            r#"{"cmd":"USER_CREATE","data":{"email":"A@test.com"}}"#,
+        )
+        .await;
+        let resp = ctx
+            .handle(r#"{"cmd":"USER_CREATE","data":{"email":"a@test.com"}}"#)
+            .await;
+        assert_eq!(resp.code, Some(codes::ALREADY_EXISTS));
+
+        let data = call_ok(
+            &ctx,
+            r#"{"cmd":"USER_DISABLE","data":{"email":"a@test.com"}}"#,
+        )
+        .await;
+        assert_eq!(data["sessions_killed"], 1);
+        assert!(!ctx.sessions.exists("tok-a"));
+
+        let resp = ctx
+            .handle(r#"{"cmd":"MAILBOX_CREATE","data":{"email":"a@test.com"}}"#)
+            .await;
+        assert_eq!(resp.code, Some(codes::MISSING_FIELD));
+        let resp = ctx
+            .handle(r#"{"cmd":"MAILBOX_DELETE","data":{"email":"a@test.com","name":"X"}}"#)
+            .await;
+        assert_eq!(resp.code, Some(codes::MAILBOX_NOT_FOUND));
+
+        call_ok(
+            &ctx,
+            r#"{"cmd":"USER_DELETE","data":{"email":"a@test.com"}}"#,
+        )
+        .await;
+        let resp = ctx
+            .handle(r#"{"cmd":"USER_DELETE","data":{"email":"a@test.com"}}"#)
+            .await;
+        assert_eq!(resp.code, Some(codes::USER_NOT_FOUND));
+
+        let resp = ctx.handle(r#"{"cmd":"BOGUS"}"#).await;
+        assert_eq!(resp.code, Some(codes::UNKNOWN_COMMAND));
+    }
+
+    #[tokio::test]
+    async fn test_disabled_user_cannot_log_in() {
+        let ctx = context();
+        let directory = ctx.directory.clone().unwrap();
+        call_ok(
+            &ctx,
+            r#"{"cmd":"USER_CREATE","data":{"email":"a@test.com"}}"#,
+        )
+        .await;
+        assert!(disabled_refusal(directory.as_ref(), "AUTH", "a@test.com")
+            .await
+            .is_none());
+
+        call_ok(
+            &ctx,
+            r#"{"cmd":"USER_DISABLE","data":{"email":"a@test.com"}}"#,
+        )
+        .await;
+        for command in LOGIN_COMMANDS {
+            let refused = disabled_refusal(directory.as_ref(), command, "A@test.com")
+                .await
+                .unwrap();
+            assert_eq!(refused.code, Some(codes::ACCOUNT_DISABLED));
+        }
+        // Other commands are not this check's business
+        assert!(disabled_refusal(directory.as_ref(), "PING", "a@test.com")
+            .await
+            .is_none());
+
+        call_ok(
+            &ctx,
+            r#"{"cmd":"USER_ENABLE","data":{"email":"a@test.com"}}"#,
+        )
+        .await;
+        assert!(disabled_refusal(directory.as_ref(), "AUTH", "a@test.com")
+            .await
+            .is_none());
+    }
+
+    #[test]
+    fn test_parse_ctl_command() {
+        let args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+        assert_eq!(
+            parse_ctl_command(&args("kill-user a@test.com")),
+            Ok((admin_cmd::SESSION_KILL, json!({ "email": "a@test.com" })))
+        );
+        assert_eq!(
+            parse_ctl_command(&args(
+                "maintenance on db upgrade --eta 2030-01-01T00:00:00Z"
+            )),
+            Ok((
+                admin_cmd::MAINTENANCE,
+                json!({ "enabled": true, "reason": "db upgrade", "eta": "2030-01-01T00:00:00Z" })
+            ))
+        );
+        assert_eq!(
+            parse_ctl_command(&args("mailbox delete a@test.com Archive"))
+                .unwrap()
+                .0,
+            admin_cmd::MAILBOX_DELETE
+        );
+        assert_eq!(
+            parse_ctl_command(&args("close-connection 12")),
+            Ok((admin_cmd::CONNECTION_CLOSE, json!({ "id": 12 })))
+        );
+        assert!(parse_ctl_command(&args("close-connection x")).is_err());
+        assert!(parse_ctl_command(&args("maintenance on --eta")).is_err());
+        assert!(parse_ctl_command(&args("user frobnicate a@test.com")).is_err());
+        assert!(parse_ctl_command(&[]).is_err());
+    }
+
+    #[test]
+    fn test_rotate_secret() {
+        let path = std::env::temp_dir().join(format!("wmtp-admin-secret-{}", std::process::id()));
+        std::fs::write(&path, "old-secret-old-secret-old-secret\n").unwrap();
+
+        let previous = rotate_secret(&path).unwrap();
+        let new = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(new.trim().len(), 64);
+        assert_eq!(
+            std::fs::read_to_string(&previous).unwrap(),
+            "old-secret-old-secret-old-secret\n"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+                0o600
+            );
+        }
+        std::fs::remove_file(&path).ok();
+        std::fs::remove_file(&previous).ok();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_socket_round_trip() {
+        let path = std::env::temp_dir().join(format!("wmtp-admin-{}.sock", std::process::id()));
+        let server = tokio::spawn(serve(path.clone(), Arc::new(context())));
+        for _ in 0..50 {
+            if path.exists() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let resp = call(&path, admin_cmd::SESSION_LIST, Value::Null)
+            .await
+            .unwrap();
+        assert_eq!(resp.status, "OK");
+        assert_eq!(resp.data.unwrap()["count"], 0);
+        server.abort();
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,486 @@
+//! Connection admission control
+//!
+//! Caps applied before a WebTransport session is used: total concurrent
+//! connections, connections per remote IP, new sessions per second
+//! (token bucket) and concurrently open streams per connection. Refused
+//! sessions are accepted and immediately closed with a close code from
+//! `error::close_codes`, so the client can tell why; refused streams are
+//! reset with `STREAM_LIMIT`.
+//!
+//! Permits are RAII guards: dropping a connection or stream permit frees
+//! its slot.
+//!
+//! Admission control can be nested: a listener's caps are checked first,
+//! then the server-wide caps of its parent, and a permit holds a slot in
+//! both.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::close_codes;
+use crate::ratelimit::TokenBucket;
+
+/// Admission caps (0 = unlimited)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct AdmissionLimits {
+    pub max_connections: usize,
+    pub max_connections_per_ip: usize,
+    pub max_streams_per_connection: usize,
+    /// New sessions per second, sustained
+    pub handshake_rate: u32,
+    /// New sessions accepted in a burst
+    pub handshake_burst: u32,
+}
+
+/// Why a session was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    ConnectionLimit,
+    IpConnectionLimit,
+    HandshakeRate,
+}
+
+impl Refusal {
+    /// WebTransport close code sent to the client
+    pub fn close_code(&self) -> u32 {
+        match self {
+            Refusal::ConnectionLimit => close_codes::CONNECTION_LIMIT,
+            Refusal::IpConnectionLimit => close_codes::IP_CONNECTION_LIMIT,
+            Refusal::HandshakeRate => close_codes::HANDSHAKE_RATE,
+        }
+    }
+
+    /// Close reason sent to the client
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Refusal::ConnectionLimit => "server connection limit reached",
+            Refusal::IpConnectionLimit => "too many connections from your address",
+            Refusal::HandshakeRate => "too many new connections, retry shortly",
+        }
+    }
+}
+
+/// Admission counters (reported by `CONNECTION_LIST`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AdmissionStats {
+    pub open_connections: usize,
+    pub distinct_ips: usize,
+    pub limits: AdmissionLimits,
+    pub refused_connection_limit: u64,
+    pub refused_ip_limit: u64,
+    pub refused_handshake_rate: u64,
+    pub refused_streams: u64,
+
+    /// Counters of the server-wide parent, for nested admission control
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<Box<AdmissionStats>>,
+}
+
+struct State {
+    open: usize,
+    per_ip: HashMap<IpAddr, usize>,
+    handshakes: TokenBucket,
+}
+
+/// Shared admission state for one server
+pub struct AdmissionControl {
+    limits: AdmissionLimits,
+    parent: Option<SharedAdmission>,
+    state: Mutex<State>,
+    refused_connection_limit: AtomicU64,
+    refused_ip_limit: AtomicU64,
+    refused_handshake_rate: AtomicU64,
+    refused_streams: AtomicU64,
+}
+
+/// Thread-safe admission control type
+pub type SharedAdmission = Arc<AdmissionControl>;
+
+/// Create admission control with the given caps
+pub fn create_admission(limits: AdmissionLimits) -> SharedAdmission {
+    Arc::new(AdmissionControl::new(limits))
+}
+
+/// Create admission control whose caps apply on top of `parent`'s
+pub fn create_nested_admission(limits: AdmissionLimits, parent: SharedAdmission) -> SharedAdmission {
+    let mut admission = AdmissionControl::new(limits);
+    admission.parent = Some(parent);
+    Arc::new(admission)
+}
+
+impl AdmissionControl {
+    /// New admission state, nothing admitted yet
+    pub fn new(limits: AdmissionLimits) -> Self {
+        let burst = limits.handshake_burst.max(limits.handshake_rate) as f64;
+        Self {
+            limits,
+            parent: None,
+            state: Mutex::new(State {
+                open: 0,
+                per_ip: HashMap::new(),
+                handshakes: TokenBucket::new(limits.handshake_rate as f64, burst, Instant::now()),
+            }),
+            refused_connection_limit: AtomicU64::new(0),
+            refused_ip_limit: AtomicU64::new(0),
+            refused_handshake_rate: AtomicU64::new(0),
+            refused_streams: AtomicU64::new(0),
+        }
+    }
+
+    /// Current caps
+    pub fn limits(&self) -> AdmissionLimits {
+        self.limits
+    }
+
+    /// Admit a new session from `ip`, or say why not
+    pub fn admit(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionPermit, Refusal> {
+        self.admit_at(ip, Instant::now())
+    }
+
+    fn admit_at(self: &Arc<Self>, ip: IpAddr, now: Instant) -> Result<ConnectionPermit, Refusal> {
+        let limits = self.limits;
+        let mut state = self.state.lock().unwrap();
+
+        let refusal = if limits.max_connections > 0 && state.open >= limits.max_connections {
+            Some(Refusal::ConnectionLimit)
+        } else if limits.max_connections_per_ip > 0
+            && state.per_ip.get(&ip).copied().unwrap_or(0) >= limits.max_connections_per_ip
+        {
+            Some(Refusal::IpConnectionLimit)
+        } else if limits.handshake_rate > 0 && state.handshakes.try_take(1.0, now).is_err() {
+            Some(Refusal::HandshakeRate)
+        } else {
+            None
+        };
+
+        if let Some(refusal) = refusal {
+            let counter = match refusal {
+                Refusal::ConnectionLimit => &self.refused_connection_limit,
+                Refusal::IpConnectionLimit => &self.refused_ip_limit,
+                Refusal::HandshakeRate => &self.refused_handshake_rate,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+            return Err(refusal);
+        }
+
+        state.open += 1;
+        *state.per_ip.entry(ip).or_insert(0) += 1;
+        drop(state);
+
+        let mut permit = ConnectionPermit {
+            admission: self.clone(),
+            ip,
+            streams: Arc::new(AtomicUsize::new(0)),
+            parent: None,
+        };
+        // Refused by the parent: give our handshake token back; dropping
+        // `permit` frees our slot again
+        if let Some(parent) = &self.parent {
+            match parent.admit_at(ip, now) {
+                Ok(parent_permit) => permit.parent = Some(Box::new(parent_permit)),
+                Err(refusal) => {
+                    if limits.handshake_rate > 0 {
+                        self.state.lock().unwrap().handshakes.refund(1.0);
+                    }
+                    return Err(refusal);
+                }
+            }
+        }
+        Ok(permit)
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut state = self.state.lock().unwrap();
+        state.open = state.open.saturating_sub(1);
+        if let Some(n) = state.per_ip.get_mut(&ip) {
+            *n -= 1;
+            if *n == 0 {
+                state.per_ip.remove(&ip);
+            }
+        }
+    }
+
+    /// Snapshot of the counters
+    pub fn stats(&self) -> AdmissionStats {
+        let state = self.state.lock().unwrap();
+        AdmissionStats {
+            open_connections: state.open,
+            distinct_ips: state.per_ip.len(),
+            limits: self.limits,
+            refused_connection_limit: self.refused_connection_limit.load(Ordering::Relaxed),
+            refused_ip_limit: self.refused_ip_limit.load(Ordering::Relaxed),
+            refused_handshake_rate: self.refused_handshake_rate.load(Ordering::Relaxed),
+            refused_streams: self.refused_streams.load(Ordering::Relaxed),
+            server: self.parent.as_ref().map(|parent| Box::new(parent.stats())),
+        }
+    }
+}
+
+/// An admitted connection; frees its slot when dropped
+pub struct ConnectionPermit {
+    admission: SharedAdmission,
+    ip: IpAddr,
+    streams: Arc<AtomicUsize>,
+    parent: Option<Box<ConnectionPermit>>,
+}
+
+impl ConnectionPermit {
+    /// Reserve a stream slot, or `None` if the connection is at its cap
+    pub fn open_stream(&self) -> Option<StreamPermit> {
+        let max = self.admission.limits.max_streams_per_connection;
+        let reserved = self
+            .streams
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (max == 0 || n < max).then_some(n + 1)
+            });
+        match reserved {
+            Ok(_) => {
+                let mut permit = StreamPermit {
+                    streams: self.streams.clone(),
+                    parent: None,
+                };
+                if let Some(parent) = &self.parent {
+                    permit.parent = Some(Box::new(parent.open_stream()?));
+                }
+                Some(permit)
+            }
+            Err(_) => {
+                self.admission
+                    .refused_streams
+                    .fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Streams currently open on this connection
+    pub fn open_streams(&self) -> usize {
+        self.streams.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.admission.release(self.ip);
+    }
+}
+
+/// An open stream; frees its slot when dropped
+pub struct StreamPermit {
+    streams: Arc<AtomicUsize>,
+    parent: Option<Box<StreamPermit>>,
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        self.streams.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Add admission counters to a `CONNECTION_LIST` response as `data.admission`
+pub fn with_stats(response: String, stats: &AdmissionStats) -> String {
+    let Ok(mut value) = serde_json::from_str::<Value>(&response) else {
+        return response;
+    };
+    if value.get("status").and_then(Value::as_str) != Some("OK") {
+        return response;
+    }
+    let Some(obj) = value.as_object_mut() else {
+        return response;
+    };
+    let data = obj
+        .entry("data")
+        .or_insert_with(|| Value::Object(Default::default()));
+    if let Some(data) = data.as_object_mut() {
+        data.insert(
+            "admission".to_string(),
+            serde_json::to_value(stats).unwrap_or_default(),
+        );
+    }
+    value.to_string()
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last])
+    }
+
+    #[test]
+    fn test_global_and_per_ip_caps() {
+        let admission = create_admission(AdmissionLimits {
+            max_connections: 3,
+            max_connections_per_ip: 2,
+            ..AdmissionLimits::default()
+        });
+
+        let a1 = admission.admit(ip(1)).unwrap();
+        let _a2 = admission.admit(ip(1)).unwrap();
+        assert_eq!(
+            admission.admit(ip(1)).err(),
+            Some(Refusal::IpConnectionLimit)
+        );
+
+        let _b1 = admission.admit(ip(2)).unwrap();
+        assert_eq!(admission.admit(ip(3)).err(), Some(Refusal::ConnectionLimit));
+
+        drop(a1);
+        assert!(admission.admit(ip(3)).is_ok());
+
+        let stats = admission.stats();
+        assert_eq!(stats.refused_ip_limit, 1);
+        assert_eq!(stats.refused_connection_limit, 1);
+        // The ip(3) permit above was dropped straight away
+        assert_eq!(stats.open_connections, 2);
+        assert_eq!(stats.distinct_ips, 2);
+    }
+
+    #[test]
+    fn test_handshake_rate() {
+        let admission = create_admission(AdmissionLimits {
+            handshake_rate: 2,
+            handshake_burst: 2,
+            ..AdmissionLimits::default()
+        });
+        let start = Instant::now();
+        assert!(admission.admit_at(ip(1), start).is_ok());
+        assert!(admission.admit_at(ip(2), start).is_ok());
+        assert_eq!(
+            admission.admit_at(ip(3), start).err(),
+            Some(Refusal::HandshakeRate)
+        );
+        assert!(admission
+            .admit_at(ip(3), start + Duration::from_secs(1))
+            .is_ok());
+        assert_eq!(admission.stats().refused_handshake_rate, 1);
+    }
+
+    #[test]
+    fn test_stream_cap() {
+        let admission = create_admission(AdmissionLimits {
+            max_streams_per_connection: 2,
+            ..AdmissionLimits::default()
+        });
+        let conn = admission.admit(ip(1)).unwrap();
+        let s1 = conn.open_stream().unwrap();
+        let _s2 = conn.open_stream().unwrap();
+        assert!(conn.open_stream().is_none());
+        assert_eq!(conn.open_streams(), 2);
+
+        drop(s1);
+        assert!(conn.open_stream().is_some());
+        assert_eq!(admission.stats().refused_streams, 1);
+    }
+
+    #[test]
+    fn test_nested_caps() {
+        let server = create_admission(AdmissionLimits {
+            max_connections: 2,
+            max_streams_per_connection: 2,
+            ..AdmissionLimits::default()
+        });
+        let public = create_nested_admission(AdmissionLimits::default(), server.clone());
+        let ops = create_nested_admission(
+            AdmissionLimits {
+                max_connections: 1,
+                max_streams_per_connection: 1,
+                ..AdmissionLimits::default()
+            },
+            server.clone(),
+        );
+
+        // The listener's own cap, then the server-wide one
+        let o1 = ops.admit(ip(1)).unwrap();
+        assert_eq!(ops.admit(ip(2)).err(), Some(Refusal::ConnectionLimit));
+        let _p1 = public.admit(ip(3)).unwrap();
+        assert_eq!(public.admit(ip(4)).err(), Some(Refusal::ConnectionLimit));
+        assert_eq!(public.stats().open_connections, 1);
+        assert_eq!(server.stats().open_connections, 2);
+
+        // A refusal by the parent frees the listener's slot again
+        assert_eq!(public.stats().refused_connection_limit, 0);
+        assert_eq!(server.stats().refused_connection_limit, 1);
+
+        let s1 = o1.open_stream().unwrap();
+        assert!(o1.open_stream().is_none());
+        drop(s1);
+        drop(o1);
+        assert_eq!(server.stats().open_connections, 1);
+        assert!(ops.admit(ip(2)).is_ok());
+
+        let stats = ops.stats();
+        assert_eq!(stats.server.as_ref().unwrap().open_connections, 1);
+        assert_eq!(server.stats().server, None);
+    }
+
+    #[test]
+    fn test_parent_refusal_refunds_handshake() {
+        let server = create_admission(AdmissionLimits {
+            max_connections: 1,
+            ..AdmissionLimits::default()
+        });
+        let public = create_nested_admission(
+            AdmissionLimits {
+                handshake_rate: 1,
+                handshake_burst: 1,
+                ..AdmissionLimits::default()
+            },
+            server.clone(),
+        );
+        let start = Instant::now();
+        let held = server.admit_at(ip(1), start).unwrap();
+
+        // The server is at its cap: refused there, without spending the
+        // listener's only token
+        for _ in 0..3 {
+            assert_eq!(
+                public.admit_at(ip(2), start).err(),
+                Some(Refusal::ConnectionLimit)
+            );
+        }
+        assert_eq!(public.stats().refused_handshake_rate, 0);
+
+        drop(held);
+        assert!(public.admit_at(ip(2), start).is_ok());
+    }
+
+    #[test]
+    fn test_refusal_close_codes() {
+        assert_eq!(
+            Refusal::ConnectionLimit.close_code(),
+            close_codes::CONNECTION_LIMIT
+        );
+        assert_eq!(
+            Refusal::HandshakeRate.close_code(),
+            close_codes::HANDSHAKE_RATE
+        );
+    }
+
+    #[test]
+    fn test_with_stats() {
+        let stats = create_admission(AdmissionLimits::default()).stats();
+        let out = with_stats(
+            r#"{"status":"OK","cmd":"CONNECTION_LIST","data":{"connections":[]}}"#.to_string(),
+            &stats,
+        );
+        let v: Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(v["data"]["admission"]["open_connections"], 0);
+        assert!(v["data"]["connections"].is_array());
+
+        let err = r#"{"status":"ERR","code":2002}"#.to_string();
+        assert_eq!(with_stats(err.clone(), &stats), err);
+    }
+}
@@ -0,0 +1,815 @@
+//! Tamper-evident audit log
+//!
+//! Security-relevant actions (logins, logouts, session kills and suspends,
+//! profile changes, mailbox purges, message deletions, operator actions on
+//! the admin socket) are appended to a JSON-lines file, one record per
+//! line. Each record carries the hash of
+//! the previous one and its own HMAC-SHA256 over its contents and that link,
+//! so editing, reordering or removing a record breaks the chain and cannot
+//! be repaired without the key.
+//!
+//! The key is random and kept in `audit_key_file` (mode 0600), created with
+//! the log. It must live outside the log's directory, so whoever can rewrite
+//! the log cannot also re-sign it, and it is unrelated to `server_secret`:
+//! rotating the secret leaves the log verifying. A log with records is never
+//! continued or verified without its key file.
+//!
+//! The latest sequence number and hash are also kept in `<file>.head` and
+//! logged via `tracing` on every append; a log that ends before that head,
+//! or whose head file is gone, has been truncated. `wmtp-server
+//! --verify-audit` checks both.
+//!
+//! Appending fsyncs, so the server does not write from its async tasks:
+//! entries go through an [`AuditWriter`] queue to a dedicated thread.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::error::{WmtpError, WmtpResult};
+use crate::logging;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// `prev` of the first record
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Audited actions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEvent {
+    AuthSuccess,
+    AuthFailure,
+    Logout,
+    SessionKill,
+    SessionSuspend,
+    ProfileChange,
+    MailboxPurge,
+    MessageDelete,
+    AdminAction,
+}
+
+impl AuditEvent {
+    /// Name written to the log
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEvent::AuthSuccess => "auth_success",
+            AuditEvent::AuthFailure => "auth_failure",
+            AuditEvent::Logout => "logout",
+            AuditEvent::SessionKill => "session_kill",
+            AuditEvent::SessionSuspend => "session_suspend",
+            AuditEvent::ProfileChange => "profile_change",
+            AuditEvent::MailboxPurge => "mailbox_purge",
+            AuditEvent::MessageDelete => "message_delete",
+            AuditEvent::AdminAction => "admin_action",
+        }
+    }
+}
+
+/// An action to record: what happened, who did it and from where
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub event: AuditEvent,
+    pub actor: Option<String>,
+    pub session_id: Option<String>,
+    pub remote: Option<SocketAddr>,
+    pub detail: Value,
+}
+
+impl AuditEntry {
+    /// New entry with no actor, address or detail
+    pub fn new(event: AuditEvent) -> Self {
+        Self {
+            event,
+            actor: None,
+            session_id: None,
+            remote: None,
+            detail: Value::Null,
+        }
+    }
+
+    /// Set the acting user (email)
+    pub fn with_actor(mut self, actor: Option<&str>) -> Self {
+        self.actor = actor.map(String::from);
+        self
+    }
+
+    /// Set the session (recorded as its non-reversible id)
+    pub fn with_session(mut self, token: &str) -> Self {
+        if !token.is_empty() {
+            self.session_id = Some(logging::session_id(token));
+        }
+        self
+    }
+
+    /// Set the client address
+    pub fn with_remote(mut self, remote: SocketAddr) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
+    /// Set event-specific detail
+    pub fn with_detail(mut self, detail: Value) -> Self {
+        self.detail = detail;
+        self
+    }
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub seq: u64,
+    pub ts: String,
+    pub event: String,
+    pub actor: Option<String>,
+    pub session_id: Option<String>,
+    pub remote: Option<String>,
+    #[serde(default)]
+    pub detail: Value,
+    pub prev: String,
+    #[serde(default)]
+    pub hash: String,
+}
+
+/// Last record written (`<file>.head`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainHead {
+    pub seq: u64,
+    pub hash: String,
+}
+
+impl ChainHead {
+    fn genesis() -> Self {
+        Self {
+            seq: 0,
+            hash: GENESIS_HASH.to_string(),
+        }
+    }
+}
+
+/// Hash of a record: HMAC over every field except `hash` (which links via `prev`)
+fn record_hash(key: &[u8], record: &AuditRecord) -> String {
+    let unsigned = AuditRecord {
+        hash: String::new(),
+        ..record.clone()
+    };
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(
+        serde_json::to_string(&unsigned)
+            .unwrap_or_default()
+            .as_bytes(),
+    );
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn head_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".head");
+    PathBuf::from(name)
+}
+
+/// Why `key_file` may not key the log at `path`, if it may not
+///
+/// The key has to be kept outside the log's directory (and anything below it).
+pub fn key_location_problem(path: &Path, key_file: &Path) -> Option<String> {
+    let dir = |file: &Path| {
+        let parent = file
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        std::fs::canonicalize(parent).unwrap_or_else(|_| parent.to_path_buf())
+    };
+    let log_dir = dir(path);
+    dir(key_file).starts_with(&log_dir).then(|| {
+        format!(
+            "audit_key_file: {} must be outside the audit log's directory {}",
+            key_file.display(),
+            log_dir.display()
+        )
+    })
+}
+
+fn read_key(key_file: &Path) -> WmtpResult<Option<Vec<u8>>> {
+    let unreadable = |what: String| {
+        WmtpError::Parse(format!(
+            "audit key {} is unreadable: {what}",
+            key_file.display()
+        ))
+    };
+    match std::fs::read_to_string(key_file) {
+        Ok(raw) => match hex::decode(raw.trim()) {
+            Ok(key) if key.is_empty() => Err(unreadable("empty".to_string())),
+            Ok(key) => Ok(Some(key)),
+            Err(e) => Err(unreadable(e.to_string())),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Key of the log at `path`, creating `key_file` for a new log
+///
+/// A log that already has records is never continued under a new key.
+fn load_or_create_key(path: &Path, key_file: &Path) -> WmtpResult<Vec<u8>> {
+    if let Some(key) = read_key(key_file)? {
+        return Ok(key);
+    }
+    if std::fs::metadata(path).is_ok_and(|meta| meta.len() > 0) {
+        return Err(WmtpError::Config(format!(
+            "audit_key_file: {} is missing; {} cannot be continued without it",
+            key_file.display(),
+            path.display()
+        )));
+    }
+    let key: Vec<u8> = [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()]
+        .iter()
+        .flat_map(|id| id.into_bytes())
+        .collect();
+
+    let mut tmp_name = key_file.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp = PathBuf::from(tmp_name);
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(&tmp)?
+        .write_all(hex::encode(&key).as_bytes())?;
+    std::fs::rename(&tmp, key_file)?;
+    Ok(key)
+}
+
+fn read_head(path: &Path) -> WmtpResult<Option<ChainHead>> {
+    match std::fs::read_to_string(head_path(path)) {
+        Ok(raw) => Ok(Some(serde_json::from_str(&raw)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_head(path: &Path, head: &ChainHead) -> WmtpResult<()> {
+    let head_path = head_path(path);
+    let mut tmp_name = head_path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp = PathBuf::from(tmp_name);
+    std::fs::write(&tmp, serde_json::to_string(head)?)?;
+    std::fs::rename(&tmp, &head_path)?;
+    Ok(())
+}
+
+struct Chain {
+    file: File,
+    head: ChainHead,
+}
+
+/// Append-only, hash-chained audit log
+pub struct AuditLog {
+    path: PathBuf,
+    key: Vec<u8>,
+    chain: Mutex<Chain>,
+}
+
+/// Thread-safe audit log type
+pub type SharedAuditLog = Arc<AuditLog>;
+
+impl AuditLog {
+    /// Open (or create) the log keyed by `key_file` and continue its chain
+    pub fn open(path: &Path, key_file: &Path) -> WmtpResult<Self> {
+        if let Some(problem) = key_location_problem(path, key_file) {
+            return Err(WmtpError::Config(problem));
+        }
+        let key = load_or_create_key(path, key_file)?;
+        let head = match std::fs::File::open(path) {
+            Ok(file) => last_record(file)?.map_or_else(ChainHead::genesis, |r| ChainHead {
+                seq: r.seq,
+                hash: r.hash,
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ChainHead::genesis(),
+            Err(e) => return Err(e.into()),
+        };
+        match read_head(path)? {
+            Some(saved) if saved != head => tracing::error!(
+                "Audit log {} ends at seq {} but its head file says {}: the log may have been truncated; run --verify-audit",
+                path.display(),
+                head.seq,
+                saved.seq
+            ),
+            Some(_) => {}
+            None if head.seq > 0 => tracing::error!(
+                "Audit log {} has no head file: the log may have been truncated; run --verify-audit",
+                path.display()
+            ),
+            // A new log verifies before its first record
+            None => write_head(path, &head)?,
+        }
+
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(path)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            key,
+            chain: Mutex::new(Chain { file, head }),
+        })
+    }
+
+    /// Append an entry; returns its sequence number
+    pub fn record(&self, entry: AuditEntry) -> WmtpResult<u64> {
+        let mut chain = self.chain.lock().unwrap();
+        let mut record = AuditRecord {
+            seq: chain.head.seq + 1,
+            ts: Utc::now().to_rfc3339(),
+            event: entry.event.as_str().to_string(),
+            actor: entry.actor,
+            session_id: entry.session_id,
+            remote: entry.remote.map(|r| r.to_string()),
+            detail: entry.detail,
+            prev: chain.head.hash.clone(),
+            hash: String::new(),
+        };
+        record.hash = record_hash(&self.key, &record);
+
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        chain.file.write_all(line.as_bytes())?;
+        chain.file.sync_data()?;
+
+        chain.head = ChainHead {
+            seq: record.seq,
+            hash: record.hash,
+        };
+        write_head(&self.path, &chain.head)?;
+        tracing::info!(
+            target: "wmtp_server::audit",
+            seq = chain.head.seq,
+            hash = %chain.head.hash,
+            "audit: {}",
+            record.event
+        );
+        Ok(chain.head.seq)
+    }
+
+    /// Record an entry, logging (not propagating) failures
+    pub fn record_or_log(&self, entry: AuditEntry) {
+        let event = entry.event;
+        if let Err(e) = self.record(entry) {
+            tracing::error!("Failed to write audit record ({}): {e}", event.as_str());
+        }
+    }
+}
+
+enum WriterMessage {
+    Record(AuditEntry),
+    Flush(mpsc::SyncSender<()>),
+}
+
+/// Queue in front of an `AuditLog`, appended to by a dedicated thread
+///
+/// `record_or_log` never blocks, so it is safe to call from async code.
+pub struct AuditWriter {
+    queue: mpsc::Sender<WriterMessage>,
+}
+
+/// Thread-safe audit writer type
+pub type SharedAuditWriter = Arc<AuditWriter>;
+
+/// Start the writer thread for `log`
+pub fn spawn_writer(log: AuditLog) -> WmtpResult<SharedAuditWriter> {
+    let (queue, pending) = mpsc::channel();
+    thread::Builder::new()
+        .name("audit-writer".to_string())
+        .spawn(move || {
+            for message in pending {
+                match message {
+                    WriterMessage::Record(entry) => log.record_or_log(entry),
+                    WriterMessage::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        })?;
+    Ok(Arc::new(AuditWriter { queue }))
+}
+
+impl AuditWriter {
+    /// Queue an entry, logging (not propagating) failures
+    pub fn record_or_log(&self, entry: AuditEntry) {
+        let event = entry.event;
+        if self.queue.send(WriterMessage::Record(entry)).is_err() {
+            tracing::error!("Audit writer stopped; dropped record ({})", event.as_str());
+        }
+    }
+
+    /// Wait until every entry queued so far has been written
+    ///
+    /// Blocks; call it from `spawn_blocking` or outside the runtime.
+    pub fn flush(&self) {
+        let (done, written) = mpsc::sync_channel(1);
+        if self.queue.send(WriterMessage::Flush(done)).is_ok() {
+            let _ = written.recv();
+        }
+    }
+}
+
+fn last_record(file: File) -> WmtpResult<Option<AuditRecord>> {
+    let mut last = None;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            last = Some(line);
+        }
+    }
+    match last {
+        Some(line) => serde_json::from_str(&line)
+            .map(Some)
+            .map_err(|e| WmtpError::Parse(format!("last audit record is unreadable: {e}"))),
+        None => Ok(None),
+    }
+}
+
+/// Result of a successful verification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Records checked
+    pub records: u64,
+
+    /// Last record (compare with an externally kept copy to rule out truncation)
+    pub head: ChainHead,
+}
+
+/// Check every record and link of an audit log
+///
+/// Fails on the first modified, reordered or missing record, and if the log
+/// ends before the sequence number in its head file, or the head file is
+/// missing (truncation). Requires the log's key file.
+pub fn verify(path: &Path, key_file: &Path) -> WmtpResult<VerifyReport> {
+    if let Some(problem) = key_location_problem(path, key_file) {
+        return Err(WmtpError::Config(problem));
+    }
+    let key = read_key(key_file)?.ok_or_else(|| {
+        WmtpError::Config(format!("audit_key_file: {} is missing", key_file.display()))
+    })?;
+    let file = File::open(path)?;
+    let mut head = ChainHead::genesis();
+
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let line_no = n + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let bad = |what: String| WmtpError::Parse(format!("audit log line {line_no}: {what}"));
+
+        let record: AuditRecord =
+            serde_json::from_str(&line).map_err(|e| bad(format!("unreadable record: {e}")))?;
+        if record.seq != head.seq + 1 {
+            return Err(bad(format!(
+                "expected seq {}, found {}",
+                head.seq + 1,
+                record.seq
+            )));
+        }
+        if record.prev != head.hash {
+            return Err(bad(format!(
+                "seq {}: does not link to the previous record",
+                record.seq
+            )));
+        }
+        if record_hash(&key, &record) != record.hash {
+            return Err(bad(format!(
+                "seq {}: record was modified (hash mismatch)",
+                record.seq
+            )));
+        }
+        head = ChainHead {
+            seq: record.seq,
+            hash: record.hash,
+        };
+    }
+
+    let saved = read_head(path)?.ok_or_else(|| {
+        WmtpError::Parse(format!(
+            "audit log head file {} is missing: the log may have been truncated",
+            head_path(path).display()
+        ))
+    })?;
+    if saved.seq > head.seq {
+        return Err(WmtpError::Parse(format!(
+            "audit log truncated: ends at seq {} but head file records seq {}",
+            head.seq, saved.seq
+        )));
+    }
+    if saved != head {
+        return Err(WmtpError::Parse(format!(
+            "audit log head mismatch at seq {}: log and head file disagree",
+            head.seq
+        )));
+    }
+
+    Ok(VerifyReport {
+        records: head.seq,
+        head,
+    })
+}
+
+// ============================================================================
+// COMMAND MAPPING
+// ============================================================================
+
+fn is_ok(response: &Value) -> bool {
+    response.get("status").and_then(Value::as_str) == Some("OK")
+}
+
+/// Audit entry for a processed command, if it is an audited action
+///
+/// `actor` is the email of the session that issued the command (if any);
+/// only identifiers are recorded, never message contents or profile values.
+pub fn entry_for_command(
+    command: &str,
+    data: &Value,
+    response: &str,
+    actor: Option<&str>,
+) -> Option<AuditEntry> {
+    let response: Value = serde_json::from_str(response).ok()?;
+    let ok = is_ok(&response);
+    let field = |name: &str| data.get(name).cloned().unwrap_or(Value::Null);
+
+    let entry = match command {
+        "AUTH" if ok => {
+            let email = response.get("email").and_then(Value::as_str).or(actor);
+            AuditEntry::new(AuditEvent::AuthSuccess).with_actor(email)
+        }
+        "AUTH" => {
+            let email = data.get("email").and_then(Value::as_str);
+            AuditEntry::new(AuditEvent::AuthFailure)
+                .with_actor(email)
+                .with_detail(serde_json::json!({ "code": response.get("code") }))
+        }
+        // The approver's login is extended to a new device
+        "LINK_APPROVE" if ok => AuditEntry::new(AuditEvent::AuthSuccess)
+            .with_actor(actor)
+            .with_detail(serde_json::json!({ "method": "device_link" })),
+        "LOGOUT" if ok => AuditEntry::new(AuditEvent::Logout).with_actor(actor),
+        "SESSION_KILL" | "SESSION_SUSPEND" if ok => {
+            let event = if command == "SESSION_KILL" {
+                AuditEvent::SessionKill
+            } else {
+                AuditEvent::SessionSuspend
+            };
+            let target = data
+                .get("target_token")
+                .or_else(|| data.get("target"))
+                .and_then(Value::as_str)
+                .map(logging::session_id);
+            AuditEntry::new(event)
+                .with_actor(actor)
+                .with_detail(serde_json::json!({ "target_session_id": target }))
+        }
+        "PROFILE_SET" if ok => {
+            let fields: Vec<&String> = data
+                .as_object()
+                .map(|m| m.keys().filter(|k| !k.ends_with("token")).collect())
+                .unwrap_or_default();
+            AuditEntry::new(AuditEvent::ProfileChange)
+                .with_actor(actor)
+                .with_detail(serde_json::json!({ "fields": fields }))
+        }
+        "MB_PURGE_TRASH" if ok => AuditEntry::new(AuditEvent::MailboxPurge)
+            .with_actor(actor)
+            .with_detail(serde_json::json!({ "mailbox": field("mailbox") })),
+        "MSG_DELETE" | "MSG_EXPUNGE" if ok => AuditEntry::new(AuditEvent::MessageDelete)
+            .with_actor(actor)
+            .with_detail(serde_json::json!({
+                "command": command,
+                "mailbox": field("mailbox"),
+                "message_ids": data.get("message_ids").or_else(|| data.get("message_id")).cloned(),
+            })),
+        "MSG_BULK_ACTION" if ok && data.get("action").and_then(Value::as_str) == Some("delete") => {
+            AuditEntry::new(AuditEvent::MessageDelete)
+                .with_actor(actor)
+                .with_detail(serde_json::json!({
+                    "command": command,
+                    "message_ids": field("message_ids"),
+                }))
+        }
+        _ => return None,
+    };
+
+    let token = data
+        .get("session_token")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    Some(entry.with_session(token))
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Log and key file in separate directories of a fresh temp dir
+    fn temp_log(name: &str) -> (PathBuf, PathBuf) {
+        let root = std::env::temp_dir().join(format!("wmtp-audit-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("log")).unwrap();
+        std::fs::create_dir_all(root.join("keys")).unwrap();
+        (
+            root.join("log").join("audit.log"),
+            root.join("keys").join("audit.key"),
+        )
+    }
+
+    fn remove_temp_log(path: &Path) {
+        let _ = std::fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+
+    fn write_records(path: &Path, key_file: &Path, n: usize) {
+        let log = AuditLog::open(path, key_file).unwrap();
+        for i in 0..n {
+            log.record(
+                AuditEntry::new(AuditEvent::AuthSuccess)
+                    .with_actor(Some(&format!("user{i}@test.com")))
+                    .with_remote("127.0.0.1:5000".parse().unwrap()),
+            )
+            .unwrap();
+        }
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn test_chain_survives_reopen_and_verifies() {
+        let (path, key) = temp_log("reopen");
+        write_records(&path, &key, 0);
+        assert_eq!(verify(&path, &key).unwrap().records, 0);
+        write_records(&path, &key, 2);
+        write_records(&path, &key, 2);
+
+        let report = verify(&path, &key).unwrap();
+        assert_eq!(report.records, 4);
+        let last: AuditRecord = serde_json::from_str(lines(&path).last().unwrap()).unwrap();
+        assert_eq!(report.head.hash, last.hash);
+        assert_eq!(last.remote.as_deref(), Some("127.0.0.1:5000"));
+
+        // Another key does not verify the chain
+        std::fs::write(&key, hex::encode([7u8; 32])).unwrap();
+        let msg = verify(&path, &key).unwrap_err().to_string();
+        assert!(msg.contains("modified"), "{msg}");
+        remove_temp_log(&path);
+    }
+
+    #[test]
+    fn test_key_file_required_outside_log_dir() {
+        let (path, key) = temp_log("key");
+        let beside = path.with_file_name("audit.key");
+        assert!(key_location_problem(&path, &key).is_none());
+        assert!(key_location_problem(&path, &beside).is_some());
+        let msg = AuditLog::open(&path, &beside).err().unwrap().to_string();
+        assert!(msg.contains("outside"), "{msg}");
+        assert!(!beside.exists());
+
+        write_records(&path, &key, 1);
+        assert!(verify(&path, &beside).is_err());
+
+        // A log with records is neither continued nor verified without its key
+        std::fs::remove_file(&key).unwrap();
+        let msg = AuditLog::open(&path, &key).err().unwrap().to_string();
+        assert!(msg.contains("missing"), "{msg}");
+        let msg = verify(&path, &key).unwrap_err().to_string();
+        assert!(msg.contains("missing"), "{msg}");
+        remove_temp_log(&path);
+    }
+
+    #[test]
+    fn test_writer_thread() {
+        let (path, key) = temp_log("writer");
+        let writer = spawn_writer(AuditLog::open(&path, &key).unwrap()).unwrap();
+        for _ in 0..3 {
+            writer.record_or_log(AuditEntry::new(AuditEvent::Logout));
+        }
+        writer.flush();
+        assert_eq!(verify(&path, &key).unwrap().records, 3);
+        remove_temp_log(&path);
+    }
+
+    #[test]
+    fn test_detects_modification_and_removal() {
+        let (path, key) = temp_log("tamper");
+        write_records(&path, &key, 3);
+        let original = lines(&path);
+
+        let edited = original[1].replace("user1@test.com", "mallory@test.com");
+        std::fs::write(
+            &path,
+            format!("{}\n{}\n{}\n", original[0], edited, original[2]),
+        )
+        .unwrap();
+        let msg = verify(&path, &key).unwrap_err().to_string();
+        assert!(msg.contains("modified"), "{msg}");
+
+        std::fs::write(&path, format!("{}\n{}\n", original[0], original[2])).unwrap();
+        let msg = verify(&path, &key).unwrap_err().to_string();
+        assert!(msg.contains("expected seq 2"), "{msg}");
+        remove_temp_log(&path);
+    }
+
+    #[test]
+    fn test_detects_truncation() {
+        let (path, key) = temp_log("truncate");
+        write_records(&path, &key, 3);
+        let original = lines(&path);
+
+        std::fs::write(&path, format!("{}\n{}\n", original[0], original[1])).unwrap();
+        let msg = verify(&path, &key).unwrap_err().to_string();
+        assert!(msg.contains("truncated"), "{msg}");
+
+        // Removing the head file along with the tail does not hide it
+        std::fs::remove_file(head_path(&path)).unwrap();
+        let msg = verify(&path, &key).unwrap_err().to_string();
+        assert!(msg.contains("head file"), "{msg}");
+        remove_temp_log(&path);
+    }
+
+    #[test]
+    fn test_entry_for_command() {
+        let data = serde_json::json!({ "email": "a@test.com", "session_token": "WMTP-x" });
+
+        let entry = entry_for_command(
+            "AUTH",
+            &data,
+            r#"{"status":"OK","cmd":"AUTH_OK","email":"a@test.com"}"#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(entry.event, AuditEvent::AuthSuccess);
+        assert_eq!(entry.actor.as_deref(), Some("a@test.com"));
+        assert_eq!(entry.session_id, Some(logging::session_id("WMTP-x")));
+
+        let entry = entry_for_command(
+            "AUTH",
+            &data,
+            r#"{"status":"ERR","cmd":"AUTH","code":1004}"#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(entry.event, AuditEvent::AuthFailure);
+
+        let entry = entry_for_command(
+            "LINK_APPROVE",
+            &serde_json::json!({ "session_token": "WMTP-x", "code": "K7Q2MX" }),
+            r#"{"status":"OK","cmd":"LINK_APPROVED"}"#,
+            Some("a@test.com"),
+        )
+        .unwrap();
+        assert_eq!(entry.event, AuditEvent::AuthSuccess);
+        assert_eq!(entry.detail, serde_json::json!({ "method": "device_link" }));
+
+        let profile =
+            serde_json::json!({ "session_token": "WMTP-x", "display_name": "secret name" });
+        let entry = entry_for_command(
+            "PROFILE_SET",
+            &profile,
+            r#"{"status":"OK"}"#,
+            Some("a@test.com"),
+        )
+        .unwrap();
+        assert_eq!(
+            entry.detail,
+            serde_json::json!({ "fields": ["display_name"] })
+        );
+
+        assert!(
+            entry_for_command("MSG_DELETE", &data, r#"{"status":"ERR","code":3001}"#, None)
+                .is_none()
+        );
+        assert!(entry_for_command("PING", &data, r#"{"status":"OK"}"#, None).is_none());
+    }
+}
@@ -0,0 +1,94 @@
+//! wmtpctl - WMTP server administration
+//!
+//! Talks to a running server over its local admin socket.
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+#[cfg(unix)]
+use anyhow::{anyhow, Result};
+#[cfg(unix)]
+use wmtp_server::admin;
+#[cfg(unix)]
+use wmtp_server::config::{CliArgs, Config};
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("admin socket requires Unix");
+    std::process::exit(1);
+}
+
+#[cfg(unix)]
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut socket: Option<PathBuf> = None;
+    let mut config_path: Option<PathBuf> = None;
+    let mut raw_json = false;
+    let mut command = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", admin::ctl_usage());
+                return Ok(());
+            }
+            "--socket" => {
+                socket = Some(
+                    args.next()
+                        .ok_or_else(|| anyhow!("--socket needs a path"))?
+                        .into(),
+                )
+            }
+            "--config" => {
+                config_path = Some(
+                    args.next()
+                        .ok_or_else(|| anyhow!("--config needs a path"))?
+                        .into(),
+                )
+            }
+            "--json" => raw_json = true,
+            _ => {
+                command.push(arg);
+                command.extend(args.by_ref());
+            }
+        }
+    }
+
+    let (cmd, data) =
+        admin::parse_ctl_command(&command).map_err(|e| anyhow!("{e}\n\n{}", admin::ctl_usage()))?;
+
+    // Same layered configuration as the server (file, WMTP_* env)
+    let socket = match socket.or_else(|| std::env::var_os("WMTP_ADMIN_SOCKET").map(PathBuf::from)) {
+        Some(path) => path,
+        None => Config::load(&CliArgs {
+            config_path,
+            ..CliArgs::default()
+        })?
+        .admin_socket
+        .ok_or_else(|| anyhow!("admin_socket is not configured; pass --socket <path>"))?,
+    };
+
+    let response = admin::call(&socket, cmd, data).await?;
+    if raw_json {
+        println!("{}", response.to_json());
+    } else if response.status == "OK" {
+        if let Some(msg) = &response.msg {
+            println!("{msg}");
+        }
+        if let Some(data) = &response.data {
+            println!("{}", serde_json::to_string_pretty(data)?);
+        }
+    } else {
+        eprintln!(
+            "{} failed ({}): {}",
+            response.cmd,
+            response.code.unwrap_or_default(),
+            response.msg.as_deref().unwrap_or("error")
+        );
+    }
+    if response.status != "OK" {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -205,6 +205,58 @@ impl Default for Heartbeat {
     }
 }
 
+/// Server-initiated push notices
+pub mod notices {
+    use super::{cmd, Response};
+
+    /// Session removed to make room under a concurrent session cap
+    pub fn session_evicted(token: &str, limit: &str) -> Response {
+        Response::ok(cmd::SESSION_EVICTED)
+            .with_token(token.to_string())
+            .with_msg("Session evicted: too many concurrent sessions")
+            .with_data(serde_json::json!({ "limit": limit }))
+    }
+
+    /// Session killed (e.g. via `SESSION_KILL`); the connection will be closed
+    pub fn session_killed(token: &str) -> Response {
+        Response::ok(cmd::SESSION_KILLED)
+            .with_token(token.to_string())
+            .with_msg("Session killed")
+    }
+
+    /// Server is going away; reconnect after `retry_after` seconds
+    ///
+    /// `resume` says whether sessions survive the restart (`RESUME` with the
+    /// same token) or a fresh `INIT` is needed.
+    pub fn server_shutdown(grace_secs: u64, retry_after: u64, resume: bool) -> Response {
+        Response::ok(cmd::SERVER_SHUTDOWN)
+            .with_msg("Server shutting down")
+            .with_data(serde_json::json!({
+                "grace_secs": grace_secs,
+                "reconnect": true,
+                "retry_after": retry_after,
+                "resume": resume,
+            }))
+    }
+
+    /// Connection closed by an operator; sessions stay valid for RESUME
+    pub fn connection_closed() -> Response {
+        Response::ok(cmd::CONNECTION_CLOSED).with_msg("Connection closed by the server operator")
+    }
+
+    /// Maintenance mode switched on (or its reason / ETA changed) or off
+    ///
+    /// While on, logins and writes are refused with 5001; reads keep working.
+    pub fn maintenance(window: Option<&crate::maintenance::MaintenanceWindow>) -> Response {
+        let (msg, mut data) = match window {
+            Some(window) => (window.reason.as_str(), window.to_json()),
+            None => ("Maintenance finished", serde_json::json!({})),
+        };
+        data["enabled"] = serde_json::Value::Bool(window.is_some());
+        Response::ok(cmd::MAINTENANCE).with_msg(msg).with_data(data)
+    }
+}
+
 /// Command constants
 pub mod cmd {
     // Session commands
@@ -212,16 +264,34 @@ pub mod cmd {
     pub const AUTH: &str = "AUTH";
     pub const RESUME: &str = "RESUME";
     pub const LOGOUT: &str = "LOGOUT";
+    pub const AUTH_OK: &str = "AUTH_OK";
+    
+    // Device linking commands
+    pub const LINK_REQUEST: &str = "LINK_REQUEST";
+    pub const LINK_CODE: &str = "LINK_CODE";
+    pub const LINK_APPROVE: &str = "LINK_APPROVE";
+    pub const LINK_APPROVED: &str = "LINK_APPROVED";
     
     // Connectivity commands
     pub const PING: &str = "PING";
     pub const PONG: &str = "PONG";
+    pub const LATENCY_PING: &str = "LATENCY_PING";
     pub const HB: &str = "HB";
     
     // Info commands
     pub const STATUS: &str = "STATUS";
     pub const INFO: &str = "INFO";
     
+    // Message commands
+    pub const MSG_SEND: &str = "MSG_SEND";
+    
+    // Server push notices
+    pub const SESSION_EVICTED: &str = "SESSION_EVICTED";
+    pub const SESSION_KILLED: &str = "SESSION_KILLED";
+    pub const SERVER_SHUTDOWN: &str = "SERVER_SHUTDOWN";
+    pub const MAINTENANCE: &str = "MAINTENANCE";
+    pub const CONNECTION_CLOSED: &str = "CONNECTION_CLOSED";
+    
     // Mail commands (future implementation)
     pub const SEND: &str = "SEND";
     pub const FETCH: &str = "FETCH";
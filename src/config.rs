@@ -1,78 +1,679 @@
 //! Server configuration management
-//! 
-//! Loads settings from environment variables.
+//!
+//! Settings are layered: built-in defaults, then an optional TOML file,
+//! then `WMTP_*` environment variables, then command-line flags. Every
+//! value is parsed strictly; a bad value or an unknown key, variable or
+//! flag is a `WmtpError::Config` naming the setting and where it came from.
 
+use serde::Serialize;
 use std::env;
-use std::path::PathBuf;
+use std::fmt;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::admission::AdmissionLimits;
+use crate::audit;
+use crate::error::{WmtpError, WmtpResult};
+use crate::guest::{GuestPolicy, DEFAULT_GUEST_COMMANDS};
+use crate::listener::{ListenerSpec, DEFAULT_LISTENER};
+use crate::lockout::LockoutPolicy;
+use crate::logging::LogFormat;
+use crate::outbound::OutboundConfig;
+use crate::ratelimit::{parse_command_cost, RateLimits, DEFAULT_COMMAND_COSTS};
+use crate::session::{SessionLimitPolicy, SessionLimits};
+
+/// Secret used when none is configured (development only)
+pub const INSECURE_DEFAULT_SECRET: &str = "insecure-dev-secret-change-me-in-production";
+
+/// Environment variable naming the config file
+pub const CONFIG_FILE_ENV: &str = "WMTP_CONFIG";
+
+/// Shortest `server_secret` accepted in production mode
+pub const PRODUCTION_MIN_SECRET_LEN: usize = 32;
+
+/// `WMTP_*` variables that are not settings
+const NON_SETTING_ENV: &[&str] = &[CONFIG_FILE_ENV, "WMTP_BUILD_COMMIT"];
+
+/// Every setting name (snake_case; `WMTP_<UPPER>` in env, `--kebab-case` on CLI)
+pub const KEYS: &[&str] = &[
+    "host",
+    "port",
+    "listeners",
+    "accept_shards",
+    "domain",
+    "production",
+    "server_secret",
+    "server_secret_file",
+    "cert_path",
+    "key_path",
+    "tls_watch_interval",
+    "dev_cert",
+    "dev_cert_addr",
+    "storage_uri",
+    "storage_db",
+    "quic_idle_timeout",
+    "quic_keep_alive",
+    "session_timeout",
+    "heartbeat_interval",
+    "max_sessions_per_user",
+    "max_sessions_per_ip",
+    "session_limit_policy",
+    "auth_max_failures",
+    "auth_lockout",
+    "auth_slowdown_after",
+    "auth_slowdown_interval",
+    "max_connections",
+    "max_connections_per_ip",
+    "max_streams_per_connection",
+    "handshake_rate",
+    "handshake_burst",
+    "session_rate",
+    "session_burst",
+    "user_rate",
+    "user_burst",
+    "command_costs",
+    "outbound_queue_capacity",
+    "slow_consumer_timeout",
+    "guest_session_timeout",
+    "guest_commands",
+    "contact_mailbox",
+    "admin_users",
+    "link_code_ttl",
+    "shutdown_grace",
+    "maintenance",
+    "maintenance_reason",
+    "maintenance_eta",
+    "session_state_file",
+    "audit_log",
+    "audit_key_file",
+    "log_level",
+    "log_format",
+    "metrics_addr",
+    "health_addr",
+    "admin_socket",
+];
 
 /// Server configuration struct
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Config {
     /// Host address to bind to
     pub host: String,
-    
+
     /// Port to listen on
     pub port: u16,
-    
+
+    /// Listeners as `NAME=ADDR[;option...]` (empty = `host:port` only)
+    pub listeners: Vec<String>,
+
+    /// Endpoints per listener, on consecutive ports, each with its own accept loop
+    pub accept_shards: usize,
+
     /// Domain name (for production)
     pub domain: String,
-    
+
+    /// Production mode: insecure settings are startup errors, not warnings
+    pub production: bool,
+
     /// Secret key for HMAC token generation
     pub server_secret: String,
-    
+
+    /// Read `server_secret` from this file (systemd credential, Docker secret)
+    pub server_secret_file: Option<PathBuf>,
+
     /// Path to TLS certificate
     pub cert_path: PathBuf,
-    
+
     /// Path to TLS private key
     pub key_path: PathBuf,
-    
+
+    /// How often to check the TLS files for changes in seconds (0 = only on SIGHUP)
+    pub tls_watch_interval: u64,
+
+    /// Generate a self-signed certificate when none exists (development only)
+    pub dev_cert: bool,
+
+    /// Loopback address serving the development certificate hash
+    pub dev_cert_addr: String,
+
+    /// MongoDB connection URI
+    pub storage_uri: String,
+
+    /// MongoDB database name
+    pub storage_db: String,
+
+    /// QUIC max idle timeout in seconds
+    pub quic_idle_timeout: u64,
+
+    /// QUIC keep-alive interval in seconds
+    pub quic_keep_alive: u64,
+
     /// Session timeout in seconds
     pub session_timeout: u64,
-    
+
     /// Heartbeat interval in seconds
     pub heartbeat_interval: u64,
+
+    /// Max concurrent authenticated sessions per email (0 = unlimited)
+    pub max_sessions_per_user: usize,
+
+    /// Max concurrent sessions per remote IP (0 = unlimited)
+    pub max_sessions_per_ip: usize,
+
+    /// What to do when a session cap is hit
+    pub session_limit_policy: SessionLimitPolicy,
+
+    /// Failed logins for one address before it is locked out (0 = never)
+    pub auth_max_failures: u32,
+
+    /// How long a lockout lasts, and how long failures are remembered, in seconds
+    pub auth_lockout: u64,
+
+    /// Failed logins for one address, from any IPs, before it is slowed down (0 = never)
+    pub auth_slowdown_after: u32,
+
+    /// Seconds a slowed-down address waits after each failed login
+    pub auth_slowdown_interval: u64,
+
+    /// Max concurrent connections (0 = unlimited)
+    pub max_connections: usize,
+
+    /// Max concurrent connections per remote IP (0 = unlimited)
+    pub max_connections_per_ip: usize,
+
+    /// Max concurrently open streams per connection, control stream included (0 = unlimited)
+    pub max_streams_per_connection: usize,
+
+    /// New sessions accepted per second (0 = unlimited)
+    pub handshake_rate: u32,
+
+    /// New sessions accepted in a burst above `handshake_rate`
+    pub handshake_burst: u32,
+
+    /// Command tokens refilled per second for each session (0 = unlimited)
+    pub session_rate: u32,
+
+    /// Command tokens a session may spend in a burst
+    pub session_burst: u32,
+
+    /// Command tokens refilled per second for each user, across sessions (0 = unlimited)
+    pub user_rate: u32,
+
+    /// Command tokens a user may spend in a burst
+    pub user_burst: u32,
+
+    /// Token cost of commands, as `COMMAND=COST` (unlisted commands cost 1, 0 = exempt)
+    pub command_costs: Vec<String>,
+
+    /// Frames buffered per lane of a connection's outbound queue
+    pub outbound_queue_capacity: usize,
+
+    /// Disconnect a client whose outbound queue stays full this long, in seconds
+    pub slow_consumer_timeout: u64,
+
+    /// Idle timeout for guest (unauthenticated) sessions in seconds
+    pub guest_session_timeout: u64,
+
+    /// Commands a guest session may run
+    pub guest_commands: Vec<String>,
+
+    /// Public contact address guests may send to (disabled if unset)
+    pub contact_mailbox: Option<String>,
+
+    /// Users who see detailed `STATUS` output
+    pub admin_users: Vec<String>,
+
+    /// Lifetime of device-linking pairing codes in seconds
+    pub link_code_ttl: u64,
+
+    /// How long shutdown waits for in-flight commands and uploads in seconds
+    pub shutdown_grace: u64,
+
+    /// Run in maintenance mode: refuse logins and writes (reloadable)
+    pub maintenance: bool,
+
+    /// Reason shown to clients during maintenance
+    pub maintenance_reason: String,
+
+    /// Expected end of maintenance, RFC 3339 (unknown if empty)
+    pub maintenance_eta: String,
+
+    /// Where sessions are saved on shutdown and restored on startup (off if unset)
+    pub session_state_file: Option<PathBuf>,
+
+    /// Hash-chained audit log of security-relevant actions (off if unset)
+    pub audit_log: Option<PathBuf>,
+
+    /// Key of the audit log, outside its directory (required with `audit_log`)
+    pub audit_key_file: Option<PathBuf>,
+
+    /// Log filter directives (`tracing` env-filter syntax)
+    pub log_level: String,
+
+    /// Log output format (`text` or `json`)
+    pub log_format: LogFormat,
+
+    /// Loopback address serving Prometheus metrics at `/metrics` (off if unset)
+    pub metrics_addr: Option<String>,
+
+    /// Address serving `/livez` and `/readyz` probes (off if unset)
+    pub health_addr: Option<String>,
+
+    /// Unix socket for `wmtpctl` (off if unset)
+    pub admin_socket: Option<PathBuf>,
+}
+
+/// Where a setting came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    File(PathBuf),
+    Env(String),
+    Cli(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "config file {}", path.display()),
+            Source::Env(var) => write!(f, "environment variable {var}"),
+            Source::Cli(flag) => write!(f, "command-line flag {flag}"),
+        }
+    }
+}
+
+/// Parsed command-line arguments
+#[derive(Debug, Clone, Default)]
+pub struct CliArgs {
+    /// `--config <path>`
+    pub config_path: Option<PathBuf>,
+
+    /// `--check-config`: print the effective configuration and exit
+    pub check_config: bool,
+
+    /// `--verify-audit`: check the audit log's hash chain and exit
+    pub verify_audit: bool,
+
+    /// `--help`
+    pub help: bool,
+
+    /// `--<setting> <value>` overrides, as (snake_case key, raw value)
+    pub overrides: Vec<(String, String)>,
+}
+
+impl CliArgs {
+    /// Parse arguments (without the program name)
+    pub fn parse<I, S>(args: I) -> WmtpResult<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut cli = CliArgs::default();
+        let mut args = args.into_iter().map(Into::into);
+
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                if arg == "-h" {
+                    cli.help = true;
+                    continue;
+                }
+                return Err(WmtpError::Config(format!("unexpected argument `{arg}`")));
+            };
+
+            let (name, inline) = match flag.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (flag.to_string(), None),
+            };
+
+            match name.as_str() {
+                "check-config" => cli.check_config = true,
+                "verify-audit" => cli.verify_audit = true,
+                "help" => cli.help = true,
+                _ => {
+                    let key = name.replace('-', "_");
+                    if name != "config" && !KEYS.contains(&key.as_str()) {
+                        return Err(WmtpError::Config(format!("unknown flag `--{name}`")));
+                    }
+                    let value = inline.or_else(|| args.next()).ok_or_else(|| {
+                        WmtpError::Config(format!("flag `--{name}` needs a value"))
+                    })?;
+                    if name == "config" {
+                        cli.config_path = Some(PathBuf::from(value));
+                    } else {
+                        cli.overrides.push((key, value));
+                    }
+                }
+            }
+        }
+
+        Ok(cli)
+    }
+
+    /// Usage text for `--help`
+    pub fn usage() -> String {
+        let mut out = String::from(
+            "Usage: wmtp-server [--config <file>] [--check-config] [--verify-audit] [--<setting> <value>]...\n\nSettings:\n",
+        );
+        for key in KEYS {
+            out.push_str(&format!(
+                "  --{:<24} (env WMTP_{})\n",
+                key.replace('_', "-"),
+                key.to_uppercase()
+            ));
+        }
+        out
+    }
 }
 
 impl Config {
-    /// Load configuration from environment variables
-    pub fn from_env() -> Self {
-        Self {
-            host: env::var("WMTP_HOST")
-                .unwrap_or_else(|_| "0.0.0.0".to_string()),
-            
-            port: env::var("WMTP_PORT")
-                .unwrap_or_else(|_| "4433".to_string())
-                .parse()
-                .unwrap_or(4433),
-            
-            domain: env::var("WMTP_DOMAIN")
-                .unwrap_or_else(|_| "localhost".to_string()),
-            
-            server_secret: env::var("WMTP_SERVER_SECRET")
-                .unwrap_or_else(|_| {
-                    tracing::warn!("⚠️  WMTP_SERVER_SECRET not set! Using insecure default.");
-                    "insecure-dev-secret-change-me-in-production".to_string()
-                }),
-            
-            cert_path: PathBuf::from(
-                env::var("WMTP_CERT_PATH")
-                    .unwrap_or_else(|_| "../certs/cert.pem".to_string()),
-            ),
-            
-            key_path: PathBuf::from(
-                env::var("WMTP_KEY_PATH")
-                    .unwrap_or_else(|_| "../certs/key.pem".to_string()),
-            ),
-            
-            session_timeout: env::var("WMTP_SESSION_TIMEOUT")
-                .unwrap_or_else(|_| "3600".to_string())
-                .parse()
-                .unwrap_or(3600),
-            
-            heartbeat_interval: env::var("WMTP_HEARTBEAT_INTERVAL")
-                .unwrap_or_else(|_| "5".to_string())
-                .parse()
-                .unwrap_or(5),
+    /// Load configuration: defaults < file < environment < command line
+    ///
+    /// The file is `--config <path>` or `$WMTP_CONFIG`; without either, no
+    /// file is read. Call `warn_insecure_defaults` once logging is set up.
+    pub fn load(cli: &CliArgs) -> WmtpResult<Self> {
+        let mut config = Self::default();
+
+        let file = cli
+            .config_path
+            .clone()
+            .or_else(|| env::var(CONFIG_FILE_ENV).ok().map(PathBuf::from));
+        if let Some(path) = file {
+            config.apply_file(&path)?;
+        }
+
+        config.apply_env(env::vars())?;
+
+        for (key, value) in &cli.overrides {
+            let source = Source::Cli(format!("--{}", key.replace('_', "-")));
+            config.apply(key, value, &source)?;
+        }
+
+        config.read_secret_file()?;
+        Ok(config)
+    }
+
+    /// Load configuration from environment variables (over defaults)
+    pub fn from_env() -> WmtpResult<Self> {
+        let mut config = Self::default();
+        config.apply_env(env::vars())?;
+        config.read_secret_file()?;
+        Ok(config)
+    }
+
+    /// Replace `server_secret` with the contents of `server_secret_file`, if set
+    ///
+    /// Surrounding whitespace (such as a trailing newline) is ignored.
+    pub fn read_secret_file(&mut self) -> WmtpResult<()> {
+        let Some(path) = &self.server_secret_file else {
+            return Ok(());
+        };
+        let secret = std::fs::read_to_string(path).map_err(|e| {
+            WmtpError::Config(format!("server_secret_file: cannot read {}: {e}", path.display()))
+        })?;
+        let secret = secret.trim();
+        if secret.is_empty() {
+            return Err(WmtpError::Config(format!(
+                "server_secret_file: {} is empty",
+                path.display()
+            )));
+        }
+        self.server_secret = secret.to_string();
+        Ok(())
+    }
+
+    /// Apply settings from a TOML file
+    pub fn apply_file(&mut self, path: &Path) -> WmtpResult<()> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            WmtpError::Config(format!("cannot read config file {}: {e}", path.display()))
+        })?;
+        self.apply_toml(&text, &Source::File(path.to_path_buf()))
+    }
+
+    /// Apply settings from TOML text
+    pub fn apply_toml(&mut self, text: &str, source: &Source) -> WmtpResult<()> {
+        let table: toml::Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| WmtpError::Config(format!("{source}: {}", e.message())))?;
+
+        for (key, value) in table {
+            let raw = match value {
+                toml::Value::String(s) => s,
+                toml::Value::Integer(i) => i.to_string(),
+                toml::Value::Boolean(b) => b.to_string(),
+                toml::Value::Array(items) => items
+                    .iter()
+                    .map(|item| match item {
+                        toml::Value::String(s) => Ok(s.clone()),
+                        other => Err(WmtpError::Config(format!(
+                            "{source}: `{key}` must be a list of strings, found {}",
+                            other.type_str()
+                        ))),
+                    })
+                    .collect::<WmtpResult<Vec<_>>>()?
+                    .join(","),
+                other => {
+                    return Err(WmtpError::Config(format!(
+                        "{source}: unsupported {} value for `{key}`",
+                        other.type_str()
+                    )))
+                }
+            };
+            self.apply(&key, &raw, source)?;
+        }
+        Ok(())
+    }
+
+    /// Apply `WMTP_*` variables from an iterator of (name, value)
+    ///
+    /// Like unknown file keys and flags, an unknown `WMTP_*` variable is an
+    /// error, so a misspelt setting is not silently ignored.
+    pub fn apply_env<I>(&mut self, vars: I) -> WmtpResult<()>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (name, value) in vars {
+            if NON_SETTING_ENV.contains(&name.as_str()) {
+                continue;
+            }
+            let Some(key) = name.strip_prefix("WMTP_") else {
+                continue;
+            };
+            let key = key.to_lowercase();
+            self.apply(&key, &value, &Source::Env(name.clone()))?;
+        }
+        Ok(())
+    }
+
+    /// Apply one raw setting, reporting errors with their source
+    pub fn apply(&mut self, key: &str, raw: &str, source: &Source) -> WmtpResult<()> {
+        self.set(key, raw).map_err(|e| WmtpError::Config(format!("{source}: `{key}`: {e}")))
+    }
+
+    /// Parse and set one setting by name
+    fn set(&mut self, key: &str, raw: &str) -> Result<(), String> {
+        let raw = raw.trim();
+        match key {
+            "host" => self.host = non_empty(raw)?,
+            "port" => self.port = parse_num(raw)?,
+            "listeners" => {
+                self.listeners = raw
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty())
+                    .map(|l| ListenerSpec::parse(l).map(|spec| spec.to_string()))
+                    .collect::<Result<_, _>>()?
+            }
+            "accept_shards" => self.accept_shards = parse_num(raw)?,
+            "domain" => self.domain = non_empty(raw)?,
+            "production" => self.production = parse_num(raw)?,
+            "server_secret" => self.server_secret = non_empty(raw)?,
+            "server_secret_file" => {
+                self.server_secret_file = if raw.is_empty() { None } else { Some(PathBuf::from(raw)) }
+            }
+            "cert_path" => self.cert_path = PathBuf::from(non_empty(raw)?),
+            "key_path" => self.key_path = PathBuf::from(non_empty(raw)?),
+            "tls_watch_interval" => self.tls_watch_interval = parse_num(raw)?,
+            "dev_cert" => self.dev_cert = parse_num(raw)?,
+            "dev_cert_addr" => self.dev_cert_addr = non_empty(raw)?,
+            "storage_uri" => self.storage_uri = non_empty(raw)?,
+            "storage_db" => self.storage_db = non_empty(raw)?,
+            "quic_idle_timeout" => self.quic_idle_timeout = parse_num(raw)?,
+            "quic_keep_alive" => self.quic_keep_alive = parse_num(raw)?,
+            "session_timeout" => self.session_timeout = parse_num(raw)?,
+            "heartbeat_interval" => self.heartbeat_interval = parse_num(raw)?,
+            "max_sessions_per_user" => self.max_sessions_per_user = parse_num(raw)?,
+            "max_sessions_per_ip" => self.max_sessions_per_ip = parse_num(raw)?,
+            "session_limit_policy" => self.session_limit_policy = raw.parse()?,
+            "auth_max_failures" => self.auth_max_failures = parse_num(raw)?,
+            "auth_lockout" => self.auth_lockout = parse_num(raw)?,
+            "auth_slowdown_after" => self.auth_slowdown_after = parse_num(raw)?,
+            "auth_slowdown_interval" => self.auth_slowdown_interval = parse_num(raw)?,
+            "max_connections" => self.max_connections = parse_num(raw)?,
+            "max_connections_per_ip" => self.max_connections_per_ip = parse_num(raw)?,
+            "max_streams_per_connection" => self.max_streams_per_connection = parse_num(raw)?,
+            "handshake_rate" => self.handshake_rate = parse_num(raw)?,
+            "handshake_burst" => self.handshake_burst = parse_num(raw)?,
+            "session_rate" => self.session_rate = parse_num(raw)?,
+            "session_burst" => self.session_burst = parse_num(raw)?,
+            "user_rate" => self.user_rate = parse_num(raw)?,
+            "user_burst" => self.user_burst = parse_num(raw)?,
+            "command_costs" => {
+                self.command_costs = raw
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|c| !c.is_empty())
+                    .map(|c| parse_command_cost(c).map(|(cmd, cost)| format!("{cmd}={cost}")))
+                    .collect::<Result<_, _>>()?
+            }
+            "outbound_queue_capacity" => self.outbound_queue_capacity = parse_num(raw)?,
+            "slow_consumer_timeout" => self.slow_consumer_timeout = parse_num(raw)?,
+            "guest_session_timeout" => self.guest_session_timeout = parse_num(raw)?,
+            "guest_commands" => {
+                self.guest_commands = raw
+                    .split(',')
+                    .map(|c| c.trim().to_uppercase())
+                    .filter(|c| !c.is_empty())
+                    .collect()
+            }
+            "contact_mailbox" => {
+                self.contact_mailbox = if raw.is_empty() { None } else { Some(raw.to_string()) }
+            }
+            "admin_users" => {
+                self.admin_users = raw
+                    .split(',')
+                    .map(|u| u.trim().to_lowercase())
+                    .filter(|u| !u.is_empty())
+                    .collect()
+            }
+            "link_code_ttl" => self.link_code_ttl = parse_num(raw)?,
+            "shutdown_grace" => self.shutdown_grace = parse_num(raw)?,
+            "maintenance" => self.maintenance = parse_num(raw)?,
+            "maintenance_reason" => self.maintenance_reason = raw.to_string(),
+            "maintenance_eta" => self.maintenance_eta = raw.to_string(),
+            "session_state_file" => {
+                self.session_state_file = if raw.is_empty() { None } else { Some(PathBuf::from(raw)) }
+            }
+            "audit_log" => {
+                self.audit_log = if raw.is_empty() { None } else { Some(PathBuf::from(raw)) }
+            }
+            "audit_key_file" => {
+                self.audit_key_file = if raw.is_empty() { None } else { Some(PathBuf::from(raw)) }
+            }
+            "log_level" => self.log_level = non_empty(raw)?,
+            "log_format" => self.log_format = raw.parse()?,
+            "metrics_addr" => {
+                self.metrics_addr = if raw.is_empty() { None } else { Some(raw.to_string()) }
+            }
+            "health_addr" => {
+                self.health_addr = if raw.is_empty() { None } else { Some(raw.to_string()) }
+            }
+            "admin_socket" => {
+                self.admin_socket = if raw.is_empty() { None } else { Some(PathBuf::from(raw)) }
+            }
+            _ => return Err("unknown setting".to_string()),
+        }
+        Ok(())
+    }
+
+    /// Log a warning for insecure settings tolerated outside production
+    pub fn warn_insecure_defaults(&self) {
+        // In production mode `validate` refuses these instead
+        if !self.production && self.server_secret == INSECURE_DEFAULT_SECRET {
+            tracing::warn!("⚠️  WMTP_SERVER_SECRET not set! Using insecure default.");
+        }
+    }
+
+    /// Guest session policy
+    pub fn guest_policy(&self) -> GuestPolicy {
+        GuestPolicy::new(
+            self.guest_commands.iter().map(String::as_str),
+            self.contact_mailbox.clone(),
+        )
+    }
+
+    /// Concurrent session caps for the `SessionManager`
+    pub fn session_limits(&self) -> SessionLimits {
+        SessionLimits {
+            max_per_user: self.max_sessions_per_user,
+            max_per_ip: self.max_sessions_per_ip,
+            policy: self.session_limit_policy,
+        }
+    }
+
+    /// Whether `email` is one of the `admin_users`
+    pub fn is_admin(&self, email: &str) -> bool {
+        let email = email.trim().to_lowercase();
+        self.admin_users.contains(&email)
+    }
+
+    /// Configured end of maintenance, if set and valid
+    pub fn maintenance_eta(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        chrono::DateTime::parse_from_rfc3339(&self.maintenance_eta)
+            .ok()
+            .map(|eta| eta.with_timezone(&chrono::Utc))
+    }
+
+    /// When repeated login failures lock an address out
+    pub fn lockout_policy(&self) -> LockoutPolicy {
+        LockoutPolicy {
+            max_failures: self.auth_max_failures,
+            duration: Duration::from_secs(self.auth_lockout),
+            slowdown_after: self.auth_slowdown_after,
+            slowdown_interval: Duration::from_secs(self.auth_slowdown_interval),
+        }
+    }
+
+    /// Connection admission caps
+    pub fn admission_limits(&self) -> AdmissionLimits {
+        AdmissionLimits {
+            max_connections: self.max_connections,
+            max_connections_per_ip: self.max_connections_per_ip,
+            max_streams_per_connection: self.max_streams_per_connection,
+            handshake_rate: self.handshake_rate,
+            handshake_burst: self.handshake_burst,
+        }
+    }
+
+    /// Per-session and per-user command rate limits
+    pub fn rate_limits(&self) -> RateLimits {
+        RateLimits {
+            session_rate: self.session_rate,
+            session_burst: self.session_burst,
+            user_rate: self.user_rate,
+            user_burst: self.user_burst,
+            // Entries were validated when set
+            costs: self
+                .command_costs
+                .iter()
+                .filter_map(|c| parse_command_cost(c).ok())
+                .collect(),
+        }
+    }
+
+    /// Outbound queue sizing and slow-consumer policy for each connection
+    pub fn outbound_config(&self) -> OutboundConfig {
+        OutboundConfig {
+            capacity: self.outbound_queue_capacity,
+            slow_consumer_timeout: Duration::from_secs(self.slow_consumer_timeout),
         }
     }
 
@@ -81,26 +682,692 @@ impl Config {
         format!("{}:{}", self.host, self.port)
     }
 
-    /// Validate configuration
-    pub fn validate(&self) -> Result<(), String> {
-        if !self.cert_path.exists() {
-            return Err(format!("Certificate not found: {:?}", self.cert_path));
+    /// Resolve the bind address to a socket address
+    pub fn socket_addr(&self) -> WmtpResult<SocketAddr> {
+        (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(|e| WmtpError::Config(format!("host: cannot resolve `{}`: {e}", self.host)))?
+            .next()
+            .ok_or_else(|| WmtpError::Config(format!("host: `{}` has no address", self.host)))
+    }
+
+    /// Listeners to bind; `host:port` when none are configured
+    pub fn listener_specs(&self) -> WmtpResult<Vec<ListenerSpec>> {
+        if self.listeners.is_empty() {
+            return Ok(vec![ListenerSpec::new(DEFAULT_LISTENER, self.socket_addr()?)]);
+        }
+        self.listeners
+            .iter()
+            .map(|l| ListenerSpec::parse(l).map_err(|e| WmtpError::Config(format!("listeners: {e}"))))
+            .collect()
+    }
+
+    /// QUIC max idle timeout
+    pub fn quic_idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.quic_idle_timeout)
+    }
+
+    /// QUIC keep-alive interval
+    pub fn quic_keep_alive(&self) -> Duration {
+        Duration::from_secs(self.quic_keep_alive)
+    }
+
+    /// Effective configuration as TOML, with the secret redacted
+    pub fn to_redacted_toml(&self) -> String {
+        let mut shown = self.clone();
+        shown.server_secret = "<redacted>".to_string();
+        shown.storage_uri = redact_uri_credentials(&self.storage_uri);
+        toml::to_string(&shown).unwrap_or_else(|e| format!("# cannot render config: {e}\n"))
+    }
+
+    /// Validate configuration, reporting every problem at once
+    pub fn validate(&self) -> WmtpResult<()> {
+        let mut problems = Vec::new();
+
+        // Hostnames are resolved the same way as when binding
+        if let Err(WmtpError::Config(problem)) = self.socket_addr() {
+            problems.push(problem);
+        }
+        if self.port == 0 {
+            problems.push("port: must not be 0".to_string());
+        }
+        let mut names = Vec::new();
+        let mut addrs = Vec::new();
+        for listener in &self.listeners {
+            match ListenerSpec::parse(listener) {
+                Ok(spec) => {
+                    if names.contains(&spec.name) {
+                        problems.push(format!("listeners: name `{}` is used twice", spec.name));
+                    }
+                    match spec.shard_addrs(self.accept_shards) {
+                        Ok(shard_addrs) => {
+                            if let Some(addr) = shard_addrs.iter().find(|a| addrs.contains(*a)) {
+                                problems.push(format!("listeners: {addr} is bound twice"));
+                            }
+                            addrs.extend(shard_addrs);
+                        }
+                        Err(e) => problems.push(format!("accept_shards: {e}")),
+                    }
+                    names.push(spec.name);
+                }
+                Err(e) => problems.push(format!("listeners: {e}")),
+            }
+        }
+        if self.accept_shards == 0 {
+            problems.push("accept_shards: must be at least 1".to_string());
+        } else if self.listeners.is_empty() {
+            let default = ListenerSpec::new(DEFAULT_LISTENER, SocketAddr::from(([0, 0, 0, 0], self.port)));
+            if let Err(e) = default.shard_addrs(self.accept_shards) {
+                problems.push(format!("accept_shards: {e}"));
+            }
+        }
+        // With dev_cert, missing files mean "generate one"
+        if !self.dev_cert && !self.cert_path.exists() {
+            problems.push(format!("cert_path: certificate not found: {:?}", self.cert_path));
+        }
+        if !self.dev_cert && !self.key_path.exists() {
+            problems.push(format!("key_path: private key not found: {:?}", self.key_path));
         }
-        
-        if !self.key_path.exists() {
-            return Err(format!("Private key not found: {:?}", self.key_path));
+        if self.dev_cert {
+            problems.extend(loopback_problem("dev_cert_addr", &self.dev_cert_addr));
+        }
+        if let Some(addr) = &self.metrics_addr {
+            problems.extend(loopback_problem("metrics_addr", addr));
+        }
+        // Probes carry no secrets; orchestrators may need to reach them off-host
+        if let Some(addr) = &self.health_addr {
+            if let Err(e) = addr.parse::<SocketAddr>() {
+                problems.push(format!("health_addr: `{addr}`: {e}"));
+            }
         }
-        
         if self.server_secret.len() < 16 {
-            return Err("Server secret must be at least 16 characters".to_string());
+            problems.push("server_secret: must be at least 16 characters".to_string());
         }
-        
-        Ok(())
+        if self.production {
+            problems.extend(self.production_problems());
+        }
+        if !(self.storage_uri.starts_with("mongodb://")
+            || self.storage_uri.starts_with("mongodb+srv://"))
+        {
+            problems.push("storage_uri: must start with mongodb:// or mongodb+srv://".to_string());
+        }
+        if self.quic_idle_timeout == 0 {
+            problems.push("quic_idle_timeout: must be greater than 0".to_string());
+        }
+        if self.quic_keep_alive >= self.quic_idle_timeout {
+            problems.push("quic_keep_alive: must be shorter than quic_idle_timeout".to_string());
+        }
+        if self.session_timeout == 0 {
+            problems.push("session_timeout: must be greater than 0".to_string());
+        }
+        if self.heartbeat_interval == 0 {
+            problems.push("heartbeat_interval: must be greater than 0".to_string());
+        } else if self.heartbeat_interval >= self.session_timeout {
+            problems.push("heartbeat_interval: must be shorter than session_timeout".to_string());
+        }
+        if self.guest_session_timeout == 0 || self.guest_session_timeout > self.session_timeout {
+            problems.push(
+                "guest_session_timeout: must be between 1 and session_timeout".to_string(),
+            );
+        }
+        if self.guest_commands.is_empty() {
+            problems.push("guest_commands: must list at least one command".to_string());
+        }
+        if let Some(mailbox) = &self.contact_mailbox {
+            if !mailbox.contains('@') {
+                problems.push(format!("contact_mailbox: `{mailbox}` is not an email address"));
+            }
+        }
+        for admin in &self.admin_users {
+            if !admin.contains('@') {
+                problems.push(format!("admin_users: `{admin}` is not an email address"));
+            }
+        }
+        if self.link_code_ttl == 0 {
+            problems.push("link_code_ttl: must be greater than 0".to_string());
+        }
+        if self.session_rate > 0 && self.session_burst < self.session_rate {
+            problems.push("session_burst: must be at least session_rate".to_string());
+        }
+        if self.user_rate > 0 && self.user_burst < self.user_rate {
+            problems.push("user_burst: must be at least user_rate".to_string());
+        }
+        if self.auth_max_failures > 0 && self.auth_lockout == 0 {
+            problems.push("auth_lockout: must be greater than 0 when auth_max_failures is set".to_string());
+        }
+        let slowdown_timed = self.auth_lockout > 0 && self.auth_slowdown_interval > 0;
+        if self.auth_slowdown_after > 0 && !slowdown_timed {
+            problems.push(
+                "auth_slowdown_after: needs auth_lockout and auth_slowdown_interval greater than 0"
+                    .to_string(),
+            );
+        }
+        if !self.maintenance_eta.is_empty()
+            && chrono::DateTime::parse_from_rfc3339(&self.maintenance_eta).is_err()
+        {
+            problems.push(format!("maintenance_eta: `{}` is not an RFC 3339 time", self.maintenance_eta));
+        }
+        match (&self.audit_log, &self.audit_key_file) {
+            (Some(log), Some(key_file)) => problems.extend(audit::key_location_problem(log, key_file)),
+            (Some(_), None) => problems.push("audit_key_file: required when audit_log is set".to_string()),
+            _ => {}
+        }
+        if self.outbound_queue_capacity == 0 {
+            problems.push("outbound_queue_capacity: must be greater than 0".to_string());
+        }
+        if self.slow_consumer_timeout == 0 {
+            problems.push("slow_consumer_timeout: must be greater than 0".to_string());
+        }
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log_level) {
+            problems.push(format!("log_level: `{}`: {e}", self.log_level));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(WmtpError::Config(format!(
+                "invalid configuration:\n  - {}",
+                problems.join("\n  - ")
+            )))
+        }
+    }
+
+    /// Settings tolerated in development but refused in production mode
+    fn production_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.server_secret == INSECURE_DEFAULT_SECRET {
+            problems.push("server_secret: the built-in development secret is not allowed in production".to_string());
+        } else if self.server_secret.len() < PRODUCTION_MIN_SECRET_LEN {
+            problems.push(format!(
+                "server_secret: must be at least {PRODUCTION_MIN_SECRET_LEN} characters in production"
+            ));
+        }
+        if self.dev_cert {
+            problems.push("dev_cert: self-signed development certificates are not allowed in production".to_string());
+        }
+
+        let secret_files = [
+            ("key_path", Some(&self.key_path)),
+            ("server_secret_file", self.server_secret_file.as_ref()),
+        ];
+        for (key, path) in secret_files {
+            if let Some(path) = path.filter(|p| world_readable(p)) {
+                problems.push(format!(
+                    "{key}: {} is readable by all users (chmod o-r)",
+                    path.display()
+                ));
+            }
+        }
+        problems
+    }
+}
+
+/// Whether any user on the system may read the file
+#[cfg(unix)]
+fn world_readable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o004 != 0)
+}
+
+#[cfg(not(unix))]
+fn world_readable(_path: &Path) -> bool {
+    false
+}
+
+/// Hide `user:password@` in a connection URI
+fn redact_uri_credentials(uri: &str) -> String {
+    match (uri.find("://"), uri.rfind('@')) {
+        (Some(scheme_end), Some(at)) if at > scheme_end => {
+            format!("{}<redacted>{}", &uri[..scheme_end + 3], &uri[at..])
+        }
+        _ => uri.to_string(),
+    }
+}
+
+fn non_empty(raw: &str) -> Result<String, String> {
+    if raw.is_empty() {
+        Err("must not be empty".to_string())
+    } else {
+        Ok(raw.to_string())
     }
 }
 
+/// Problem with an address that must be a loopback `ip:port`, if any
+fn loopback_problem(key: &str, addr: &str) -> Option<String> {
+    match addr.parse::<SocketAddr>() {
+        Ok(parsed) if parsed.ip().is_loopback() => None,
+        Ok(_) => Some(format!("{key}: must be a loopback address")),
+        Err(e) => Some(format!("{key}: `{addr}`: {e}")),
+    }
+}
+
+fn parse_num<T>(raw: &str) -> Result<T, String>
+where
+    T: std::str::FromStr,
+    T::Err: fmt::Display,
+{
+    raw.parse()
+        .map_err(|e| format!("`{raw}` is not a valid {}: {e}", std::any::type_name::<T>()))
+}
+
 impl Default for Config {
+    /// Built-in defaults (no environment or file)
     fn default() -> Self {
-        Self::from_env()
+        Self {
+            host: "0.0.0.0".to_string(),
+            port: 4433,
+            listeners: Vec::new(),
+            accept_shards: 1,
+            domain: "localhost".to_string(),
+            production: false,
+            server_secret: INSECURE_DEFAULT_SECRET.to_string(),
+            server_secret_file: None,
+            cert_path: PathBuf::from("../certs/cert.pem"),
+            key_path: PathBuf::from("../certs/key.pem"),
+            tls_watch_interval: 60,
+            dev_cert: false,
+            dev_cert_addr: "127.0.0.1:4480".to_string(),
+            storage_uri: "mongodb://localhost:27017".to_string(),
+            storage_db: "wmtp".to_string(),
+            quic_idle_timeout: 60,
+            quic_keep_alive: 10,
+            session_timeout: 3600,
+            heartbeat_interval: 5,
+            max_sessions_per_user: 10,
+            max_sessions_per_ip: 50,
+            session_limit_policy: SessionLimitPolicy::RejectNewest,
+            auth_max_failures: 5,
+            auth_lockout: 900,
+            auth_slowdown_after: 20,
+            auth_slowdown_interval: 5,
+            max_connections: 10_000,
+            max_connections_per_ip: 100,
+            max_streams_per_connection: 16,
+            handshake_rate: 50,
+            handshake_burst: 200,
+            session_rate: 10,
+            session_burst: 40,
+            user_rate: 20,
+            user_burst: 80,
+            command_costs: DEFAULT_COMMAND_COSTS
+                .iter()
+                .map(|(cmd, cost)| format!("{cmd}={cost}"))
+                .collect(),
+            outbound_queue_capacity: 256,
+            slow_consumer_timeout: 30,
+            guest_session_timeout: 300,
+            guest_commands: DEFAULT_GUEST_COMMANDS.iter().map(|c| c.to_string()).collect(),
+            contact_mailbox: None,
+            admin_users: Vec::new(),
+            link_code_ttl: 120,
+            shutdown_grace: 30,
+            maintenance: false,
+            maintenance_reason: String::new(),
+            maintenance_eta: String::new(),
+            session_state_file: None,
+            audit_log: None,
+            audit_key_file: None,
+            log_level: "info,wmtp_server=debug".to_string(),
+            log_format: LogFormat::Text,
+            metrics_addr: None,
+            health_addr: None,
+            admin_socket: None,
+        }
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_layer_precedence() {
+        let mut config = Config::default();
+        let file = Source::File(PathBuf::from("wmtp.toml"));
+        config
+            .apply_toml("port = 5000\nhost = \"127.0.0.1\"\nguest_commands = [\"ping\", \"auth\"]", &file)
+            .unwrap();
+        config.apply_env(vars(&[("WMTP_PORT", "6000"), ("HOME", "/root")])).unwrap();
+        config.apply("port", "7000", &Source::Cli("--port".to_string())).unwrap();
+
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.host, "127.0.0.1");
+        assert_eq!(config.guest_commands, vec!["PING".to_string(), "AUTH".to_string()]);
+    }
+
+    #[test]
+    fn test_precise_errors() {
+        let mut config = Config::default();
+
+        let err = config.apply_env(vars(&[("WMTP_PORT", "44x3")])).unwrap_err();
+        let msg = err.to_string();
+        assert!(matches!(err, WmtpError::Config(_)));
+        assert!(msg.contains("WMTP_PORT") && msg.contains("44x3"), "{msg}");
+
+        let msg = config.apply_env(vars(&[("WMTP_PROT", "4433")])).unwrap_err().to_string();
+        assert!(msg.contains("WMTP_PROT") && msg.contains("unknown setting"), "{msg}");
+        config
+            .apply_env(vars(&[("WMTP_CONFIG", "wmtp.toml"), ("WMTP_BUILD_COMMIT", "abc")]))
+            .unwrap();
+
+        let file = Source::File(PathBuf::from("wmtp.toml"));
+        let msg = config.apply_toml("bogus = 1", &file).unwrap_err().to_string();
+        assert!(msg.contains("wmtp.toml") && msg.contains("bogus"), "{msg}");
+
+        assert!(config.apply_toml("port = [", &file).is_err());
+        assert!(config.apply_toml("session_limit_policy = \"drop\"", &file).is_err());
+        assert!(config.apply_toml("log_format = \"xml\"", &file).is_err());
+        config.apply_toml("log_format = \"json\"", &file).unwrap();
+        assert_eq!(config.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn test_cli_parse() {
+        let cli = CliArgs::parse([
+            "--config",
+            "/etc/wmtp.toml",
+            "--check-config",
+            "--verify-audit",
+            "--port=8443",
+            "--session-timeout",
+            "60",
+        ])
+        .unwrap();
+        assert_eq!(cli.config_path, Some(PathBuf::from("/etc/wmtp.toml")));
+        assert!(cli.check_config);
+        assert!(cli.verify_audit);
+        assert_eq!(
+            cli.overrides,
+            vec![
+                ("port".to_string(), "8443".to_string()),
+                ("session_timeout".to_string(), "60".to_string()),
+            ]
+        );
+
+        assert!(CliArgs::parse(["--nope", "1"]).is_err());
+        assert!(CliArgs::parse(["--port"]).is_err());
+    }
+
+    #[test]
+    fn test_validate_reports_all_problems() {
+        let config = Config {
+            port: 0,
+            heartbeat_interval: 0,
+            log_level: "wmtp_server=loud".to_string(),
+            cert_path: PathBuf::from("/nonexistent/cert.pem"),
+            key_path: PathBuf::from("/nonexistent/key.pem"),
+            ..Config::default()
+        };
+        let msg = config.validate().unwrap_err().to_string();
+        assert!(msg.contains("port"));
+        assert!(msg.contains("heartbeat_interval"));
+        assert!(msg.contains("cert_path"));
+        assert!(msg.contains("log_level"));
+    }
+
+    #[test]
+    fn test_audit_key_file_required_outside_log_dir() {
+        let mut config = Config {
+            dev_cert: true,
+            audit_log: Some(PathBuf::from("/var/lib/wmtp/audit/audit.log")),
+            ..Config::default()
+        };
+        let msg = config.validate().unwrap_err().to_string();
+        assert!(msg.contains("audit_key_file: required"), "{msg}");
+
+        config.audit_key_file = Some(PathBuf::from("/var/lib/wmtp/audit/keys/audit.key"));
+        let msg = config.validate().unwrap_err().to_string();
+        assert!(msg.contains("outside the audit log's directory"), "{msg}");
+
+        config.audit_key_file = Some(PathBuf::from("/etc/wmtp/audit.key"));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_resolves_host() {
+        let mut config = Config {
+            host: "localhost".to_string(),
+            dev_cert: true,
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+
+        config.host = "wmtp.invalid".to_string();
+        let msg = config.validate().unwrap_err().to_string();
+        assert!(msg.contains("host: cannot resolve `wmtp.invalid`"), "{msg}");
+    }
+
+    #[test]
+    fn test_dev_cert_settings() {
+        let mut config = Config {
+            cert_path: PathBuf::from("/nonexistent/cert.pem"),
+            key_path: PathBuf::from("/nonexistent/key.pem"),
+            ..Config::default()
+        };
+        config.apply("dev_cert", "true", &Source::Cli("--dev-cert".to_string())).unwrap();
+        assert!(config.dev_cert);
+        assert!(config.validate().is_ok());
+
+        config.dev_cert_addr = "0.0.0.0:4480".to_string();
+        let msg = config.validate().unwrap_err().to_string();
+        assert!(msg.contains("dev_cert_addr") && !msg.contains("cert_path"), "{msg}");
+
+        assert!(config.apply("dev_cert", "yes", &Source::Cli("--dev-cert".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_operator_endpoint_addrs() {
+        let mut config = Config {
+            dev_cert: true,
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+
+        config.apply("metrics_addr", "127.0.0.1:9464", &Source::Env("WMTP_METRICS_ADDR".to_string())).unwrap();
+        assert!(config.validate().is_ok());
+
+        config.metrics_addr = Some("0.0.0.0:9464".to_string());
+        let msg = config.validate().unwrap_err().to_string();
+        assert!(msg.contains("metrics_addr: must be a loopback address"), "{msg}");
+
+        config.apply("metrics_addr", "", &Source::Env("WMTP_METRICS_ADDR".to_string())).unwrap();
+        assert_eq!(config.metrics_addr, None);
+
+        config.health_addr = Some("0.0.0.0:8080".to_string());
+        assert!(config.validate().is_ok());
+        config.health_addr = Some("localhost".to_string());
+        assert!(config.validate().unwrap_err().to_string().contains("health_addr"));
+    }
+
+    #[test]
+    fn test_rate_limit_settings() {
+        let mut config = Config::default();
+        assert_eq!(config.rate_limits().cost("MSG_SEND"), 5);
+        assert_eq!(config.rate_limits().cost("PING"), 1);
+
+        let file = Source::File(PathBuf::from("wmtp.toml"));
+        config
+            .apply_toml("session_rate = 2\nsession_burst = 4\ncommand_costs = [\"msg_send=10\", \"ping = 0\"]", &file)
+            .unwrap();
+        assert_eq!(config.command_costs, vec!["MSG_SEND=10".to_string(), "PING=0".to_string()]);
+        let limits = config.rate_limits();
+        assert_eq!((limits.session_rate, limits.session_burst), (2, 4));
+        assert_eq!(limits.cost("MSG_SEND"), 10);
+        assert_eq!(limits.cost("PING"), 0);
+        assert_eq!(limits.cost("SEARCH_GLOBAL"), 1);
+
+        let msg = config.apply_toml("command_costs = [\"MSG_SEND\"]", &file).unwrap_err().to_string();
+        assert!(msg.contains("command_costs") && msg.contains("COMMAND=COST"), "{msg}");
+
+        config.dev_cert = true;
+        config.session_burst = 1;
+        let msg = config.validate().unwrap_err().to_string();
+        assert!(msg.contains("session_burst: must be at least session_rate"), "{msg}");
+    }
+
+    #[test]
+    fn test_admin_users() {
+        let mut config = Config::default();
+        let file = Source::File(PathBuf::from("wmtp.toml"));
+        config.apply_toml("admin_users = [\" Ops@Example.com\"]", &file).unwrap();
+        assert!(config.is_admin("ops@example.com"));
+        assert!(!config.is_admin("user@example.com"));
+
+        config.dev_cert = true;
+        config.admin_users.push("ops".to_string());
+        let msg = config.validate().unwrap_err().to_string();
+        assert!(msg.contains("admin_users: `ops` is not an email address"), "{msg}");
+    }
+
+    #[test]
+    fn test_listeners() {
+        let mut config = Config {
+            host: "127.0.0.1".to_string(),
+            ..Config::default()
+        };
+        let specs = config.listener_specs().unwrap();
+        assert_eq!(specs.len(), 1);
+        assert_eq!(specs[0].name, DEFAULT_LISTENER);
+        assert_eq!(specs[0].addr, "127.0.0.1:4433".parse().unwrap());
+
+        let file = Source::File(PathBuf::from("wmtp.toml"));
+        config
+            .apply_toml(
+                "listeners = [\"public=0.0.0.0:4433\", \"public6=[::]:4433; v6only\", \"ops=127.0.0.1:4434;admin;commands=status|info\"]",
+                &file,
+            )
+            .unwrap();
+        assert_eq!(config.listeners[1], "public6=[::]:4433;v6only");
+        let specs = config.listener_specs().unwrap();
+        assert_eq!(specs.len(), 3);
+        assert!(specs[2].admin && !specs[2].allows("MSG_SEND"));
+
+        let msg = config.apply_toml("listeners = [\"public=0.0.0.0\"]", &file).unwrap_err().to_string();
+        assert!(msg.contains("listeners"), "{msg}");
+
+        config.dev_cert = true;
+        config.listeners.push("public=127.0.0.1:4433".to_string());
+        let msg = config.validate().unwrap_err().to_string();
+        assert!(msg.contains("listeners: name `public` is used twice"), "{msg}");
+
+        // Shards take the ports after a listener's own
+        config.listeners.pop();
+        assert!(config.validate().is_ok());
+        config.apply("accept_shards", "2", &file).unwrap();
+        config.listeners.push("extra=0.0.0.0:4434".to_string());
+        let msg = config.validate().unwrap_err().to_string();
+        assert!(msg.contains("listeners: 0.0.0.0:4434 is bound twice"), "{msg}");
+
+        config.listeners.clear();
+        config.port = 65535;
+        let msg = config.validate().unwrap_err().to_string();
+        assert!(msg.contains("accept_shards: default: 2 shards from port 65535"), "{msg}");
+    }
+
+    fn temp_file(name: &str, contents: &str, mode: u32) -> PathBuf {
+        let path = env::temp_dir().join(format!("wmtp-config-{name}-{}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+        path
+    }
+
+    #[test]
+    fn test_secret_file() {
+        let path = temp_file("secret", "from-a-file-0123456789abcdef\n", 0o600);
+        let mut config = Config::default();
+        config
+            .apply_env(vars(&[("WMTP_SERVER_SECRET_FILE", path.to_str().unwrap())]))
+            .unwrap();
+        config.read_secret_file().unwrap();
+        assert_eq!(config.server_secret, "from-a-file-0123456789abcdef");
+
+        config.server_secret_file = Some(PathBuf::from("/nonexistent/secret"));
+        let msg = config.read_secret_file().unwrap_err().to_string();
+        assert!(msg.contains("server_secret_file") && msg.contains("/nonexistent/secret"), "{msg}");
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_production_mode() {
+        let key = temp_file("key", "key", 0o600);
+        let config = Config {
+            cert_path: key.clone(),
+            key_path: key.clone(),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+
+        // Development defaults are hard errors in production
+        let production = Config { production: true, ..config.clone() };
+        let msg = production.validate().unwrap_err().to_string();
+        assert!(msg.contains("server_secret"), "{msg}");
+
+        let production = Config {
+            production: true,
+            server_secret: "0123456789abcdef0123".to_string(),
+            ..config.clone()
+        };
+        let msg = production.validate().unwrap_err().to_string();
+        assert!(msg.contains("at least 32"), "{msg}");
+
+        let production = Config {
+            production: true,
+            server_secret: "0123456789abcdef0123456789abcdef".to_string(),
+            ..config
+        };
+        assert!(production.validate().is_ok());
+
+        #[cfg(unix)]
+        {
+            let readable = temp_file("readable-key", "key", 0o644);
+            let production = Config { key_path: readable.clone(), ..production };
+            let msg = production.validate().unwrap_err().to_string();
+            assert!(msg.contains("key_path") && msg.contains("readable by all"), "{msg}");
+            std::fs::remove_file(readable).ok();
+        }
+        std::fs::remove_file(key).ok();
+    }
+
+    #[test]
+    fn test_redacted_toml() {
+        let config = Config {
+            server_secret: "super-secret-value-1234".to_string(),
+            ..Config::default()
+        };
+        let out = config.to_redacted_toml();
+        assert!(!out.contains("super-secret-value-1234"));
+        assert!(out.contains("<redacted>"));
+        assert!(out.contains("port = 4433"));
+
+        assert_eq!(
+            redact_uri_credentials("mongodb://admin:pw@db.internal:27017/wmtp"),
+            "mongodb://<redacted>@db.internal:27017/wmtp"
+        );
+        assert_eq!(redact_uri_credentials("mongodb://localhost:27017"), "mongodb://localhost:27017");
+    }
+
+    #[test]
+    fn test_socket_addr() {
+        let config = Config {
+            host: "127.0.0.1".to_string(),
+            port: 4433,
+            ..Config::default()
+        };
+        assert_eq!(config.socket_addr().unwrap(), "127.0.0.1:4433".parse().unwrap());
     }
 }
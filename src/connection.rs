@@ -0,0 +1,577 @@
+//! Connection tracking for WMTP server
+//!
+//! Keeps the list of live WebTransport connections and an index of which
+//! connections hold which session, so the server can push frames to (or
+//! close) every connection of a session or user.
+
+use serde::{Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::outbound::OutboundSender;
+use crate::session::{Admission, SessionLimitKind, WmtpSession};
+
+/// Info about one live connection (as reported by `CONNECTION_LIST`)
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionInfo {
+    /// Server-assigned connection id
+    pub id: u64,
+
+    /// Remote socket address
+    pub remote_addr: Option<String>,
+
+    /// Session tokens bound to this connection
+    pub session_tokens: Vec<String>,
+
+    /// Email of the authenticated user (if any session is authenticated)
+    pub email: Option<String>,
+
+    /// Client version sent with INIT (`client_version`)
+    pub client_version: Option<String>,
+
+    /// When the connection was accepted (RFC 3339)
+    pub connected_at: String,
+
+    /// Traffic counters, updated by the connection's tasks
+    #[serde(serialize_with = "serialize_stats")]
+    pub stats: SharedConnectionStats,
+}
+
+/// Longest client version kept
+pub const MAX_CLIENT_VERSION_LEN: usize = 64;
+
+/// Traffic counters of one connection
+///
+/// Updated lock-free from the control stream, writer and attachment tasks.
+/// Path figures are limited to RTT and datagram size: wtransport 0.1 does
+/// not expose the congestion window or loss counters.
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    frames_in: AtomicU64,
+    frames_out: AtomicU64,
+    streams_open: AtomicI64,
+    commands: AtomicU64,
+    last_activity_ms: AtomicI64,
+    rtt_us: AtomicU64,
+    max_datagram_size: AtomicU64,
+}
+
+/// Thread-safe connection stats type
+pub type SharedConnectionStats = Arc<ConnectionStats>;
+
+/// Point-in-time copy of [`ConnectionStats`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConnectionStatsSnapshot {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub frames_in: u64,
+    pub frames_out: u64,
+    pub streams_open: i64,
+    pub commands: u64,
+    /// Last data received from the client (RFC 3339; none yet if null)
+    pub last_activity: Option<String>,
+    /// Seconds since `last_activity`
+    pub idle_secs: Option<u64>,
+    /// QUIC smoothed round-trip time, as of the last sample
+    pub rtt_ms: f64,
+    /// Largest datagram the path currently allows (0 if datagrams are off)
+    pub max_datagram_size: u64,
+}
+
+impl ConnectionStats {
+    /// Control frame received from the client
+    pub fn frame_received(&self, bytes: usize) {
+        self.frames_in.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received(bytes);
+    }
+
+    /// Bytes received on any stream (counts as activity)
+    pub fn bytes_received(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.last_activity_ms.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    /// Frame written to the control stream
+    pub fn frame_sent(&self, bytes: usize) {
+        self.frames_out.fetch_add(1, Ordering::Relaxed);
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// A stream was opened on the connection
+    pub fn stream_opened(&self) {
+        self.streams_open.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A stream of the connection finished
+    pub fn stream_closed(&self) {
+        self.streams_open.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// A command was processed
+    pub fn command_processed(&self) {
+        self.commands.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Latest QUIC path figures
+    pub fn record_path(&self, rtt: Duration, max_datagram_size: Option<usize>) {
+        self.rtt_us.store(rtt.as_micros() as u64, Ordering::Relaxed);
+        self.max_datagram_size.store(max_datagram_size.unwrap_or(0) as u64, Ordering::Relaxed);
+    }
+
+    /// Copy the counters
+    pub fn snapshot(&self) -> ConnectionStatsSnapshot {
+        let last_ms = self.last_activity_ms.load(Ordering::Relaxed);
+        let last = (last_ms > 0).then(|| chrono::DateTime::from_timestamp_millis(last_ms)).flatten();
+        ConnectionStatsSnapshot {
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            frames_in: self.frames_in.load(Ordering::Relaxed),
+            frames_out: self.frames_out.load(Ordering::Relaxed),
+            streams_open: self.streams_open.load(Ordering::Relaxed),
+            commands: self.commands.load(Ordering::Relaxed),
+            last_activity: last.map(|t| t.to_rfc3339()),
+            idle_secs: last.map(|t| (Utc::now() - t).num_seconds().max(0) as u64),
+            rtt_ms: self.rtt_us.load(Ordering::Relaxed) as f64 / 1000.0,
+            max_datagram_size: self.max_datagram_size.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn serialize_stats<S: Serializer>(stats: &SharedConnectionStats, s: S) -> Result<S::Ok, S::Error> {
+    stats.snapshot().serialize(s)
+}
+
+/// Thread-safe connection store type
+pub type ConnectionStore = Arc<Mutex<HashMap<u64, ConnectionInfo>>>;
+
+/// Create a new empty connection store
+pub fn create_connection_store() -> ConnectionStore {
+    Arc::new(Mutex::new(HashMap::new()))
+}
+
+/// Record a session binding on a connection's info record
+pub fn bind_connection_info(
+    store: &ConnectionStore,
+    conn_id: u64,
+    token: &str,
+    email: Option<&str>,
+) {
+    let mut store = store.lock().unwrap();
+    if let Some(info) = store.get_mut(&conn_id) {
+        if !info.session_tokens.iter().any(|t| t == token) {
+            info.session_tokens.push(token.to_string());
+        }
+        if email.is_some() {
+            info.email = email.map(String::from);
+        }
+    }
+}
+
+/// Record the client version a connection reported
+///
+/// Control characters are dropped and the value is cut to
+/// [`MAX_CLIENT_VERSION_LEN`] characters.
+pub fn set_client_version(store: &ConnectionStore, conn_id: u64, version: &str) {
+    let version: String = version.chars().filter(|c| !c.is_control()).take(MAX_CLIENT_VERSION_LEN).collect();
+    if let Some(info) = store.lock().unwrap().get_mut(&conn_id) {
+        info.client_version = Some(version);
+    }
+}
+
+/// Remove a session from every connection's info record
+pub fn unbind_connection_info(store: &ConnectionStore, token: &str) {
+    let mut store = store.lock().unwrap();
+    for info in store.values_mut() {
+        info.session_tokens.retain(|t| t != token);
+    }
+}
+
+/// Build the info record for a new connection
+pub fn make_connection_info(id: u64, remote: Option<SocketAddr>) -> ConnectionInfo {
+    ConnectionInfo {
+        id,
+        remote_addr: remote.map(|a| a.to_string()),
+        session_tokens: Vec::new(),
+        email: None,
+        client_version: None,
+        connected_at: Utc::now().to_rfc3339(),
+        stats: SharedConnectionStats::default(),
+    }
+}
+
+// ============================================================================
+// SESSION <-> CONNECTION INDEX
+// ============================================================================
+
+/// Frame queued for a connection's control stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushFrame {
+    /// Write this JSON text to the control stream
+    Frame(String),
+
+    /// Write a final frame (if any), then close the connection
+    Close(Option<String>),
+}
+
+/// Outbound queue of one connection's control stream
+pub type PushSender = OutboundSender;
+
+struct ConnEntry {
+    sender: PushSender,
+    tokens: HashSet<String>,
+}
+
+#[derive(Default)]
+struct Index {
+    conns: HashMap<u64, ConnEntry>,
+    by_token: HashMap<String, HashSet<u64>>,
+    by_user: HashMap<String, HashSet<String>>,
+}
+
+impl Index {
+    fn conns_of_session(&self, token: &str) -> HashSet<u64> {
+        self.by_token.get(token).cloned().unwrap_or_default()
+    }
+
+    fn conns_of_user(&self, email: &str) -> HashSet<u64> {
+        self.by_user
+            .get(&normalize_email(email))
+            .map(|tokens| tokens.iter().flat_map(|t| self.conns_of_session(t)).collect())
+            .unwrap_or_default()
+    }
+
+    fn send(&self, conns: &HashSet<u64>, frame: PushFrame) -> usize {
+        conns
+            .iter()
+            .filter_map(|id| self.conns.get(id))
+            .filter(|entry| entry.sender.push(frame.clone()))
+            .count()
+    }
+}
+
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Two-way index of session tokens and the live connections holding them
+#[derive(Default)]
+pub struct ConnectionIndex {
+    inner: Mutex<Index>,
+}
+
+/// Thread-safe connection index type
+pub type SharedConnectionIndex = Arc<ConnectionIndex>;
+
+/// Create a new empty connection index
+pub fn create_connection_index() -> SharedConnectionIndex {
+    Arc::new(ConnectionIndex::default())
+}
+
+impl ConnectionIndex {
+    /// Register a newly opened connection
+    pub fn register(&self, conn_id: u64, sender: PushSender) {
+        let mut index = self.inner.lock().unwrap();
+        index.conns.insert(conn_id, ConnEntry { sender, tokens: HashSet::new() });
+    }
+
+    /// Forget a closed connection and all its bindings
+    pub fn unregister(&self, conn_id: u64) {
+        let mut index = self.inner.lock().unwrap();
+        let Some(entry) = index.conns.remove(&conn_id) else {
+            return;
+        };
+        for token in entry.tokens {
+            if let Some(conns) = index.by_token.get_mut(&token) {
+                conns.remove(&conn_id);
+                if conns.is_empty() {
+                    index.by_token.remove(&token);
+                }
+            }
+        }
+    }
+
+    /// Bind a session (and its user, if authenticated) to a connection
+    pub fn bind(&self, token: &str, conn_id: u64, email: Option<&str>) {
+        let mut index = self.inner.lock().unwrap();
+        let Some(entry) = index.conns.get_mut(&conn_id) else {
+            return;
+        };
+        entry.tokens.insert(token.to_string());
+        index.by_token.entry(token.to_string()).or_default().insert(conn_id);
+        if let Some(email) = email {
+            index
+                .by_user
+                .entry(normalize_email(email))
+                .or_default()
+                .insert(token.to_string());
+        }
+    }
+
+    /// Forget a session (call when it is removed from the session store)
+    pub fn unbind_session(&self, token: &str) {
+        let mut index = self.inner.lock().unwrap();
+        for conn_id in index.by_token.remove(token).unwrap_or_default() {
+            if let Some(entry) = index.conns.get_mut(&conn_id) {
+                entry.tokens.remove(token);
+            }
+        }
+        index.by_user.retain(|_, tokens| {
+            tokens.remove(token);
+            !tokens.is_empty()
+        });
+    }
+
+    /// Connection ids holding a session
+    pub fn connections_of_session(&self, token: &str) -> Vec<u64> {
+        let index = self.inner.lock().unwrap();
+        index.conns_of_session(token).into_iter().collect()
+    }
+
+    /// Connection ids holding any session of a user
+    pub fn connections_of_user(&self, email: &str) -> Vec<u64> {
+        let index = self.inner.lock().unwrap();
+        index.conns_of_user(email).into_iter().collect()
+    }
+
+    /// Bound session tokens for which `is_live` returns false
+    pub fn stale_sessions<F>(&self, is_live: F) -> Vec<String>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let index = self.inner.lock().unwrap();
+        index.by_token.keys().filter(|t| !is_live(t)).cloned().collect()
+    }
+
+    /// Session tokens bound to a connection
+    pub fn sessions_of_connection(&self, conn_id: u64) -> Vec<String> {
+        let index = self.inner.lock().unwrap();
+        index
+            .conns
+            .get(&conn_id)
+            .map(|entry| entry.tokens.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Send a frame to one connection
+    pub fn send_to_connection(&self, conn_id: u64, frame: &str) -> bool {
+        let index = self.inner.lock().unwrap();
+        index
+            .conns
+            .get(&conn_id)
+            .is_some_and(|entry| entry.sender.push(PushFrame::Frame(frame.to_string())))
+    }
+
+    /// Send a frame to every connection of a session; returns how many were reached
+    pub fn send_to_session(&self, token: &str, frame: &str) -> usize {
+        let index = self.inner.lock().unwrap();
+        let conns = index.conns_of_session(token);
+        index.send(&conns, PushFrame::Frame(frame.to_string()))
+    }
+
+    /// Send a frame to every connection of a user; returns how many were reached
+    pub fn send_to_user(&self, email: &str, frame: &str) -> usize {
+        let index = self.inner.lock().unwrap();
+        let conns = index.conns_of_user(email);
+        index.send(&conns, PushFrame::Frame(frame.to_string()))
+    }
+
+    /// Send a frame to every connection; returns how many were reached
+    pub fn broadcast(&self, frame: &str) -> usize {
+        let index = self.inner.lock().unwrap();
+        let conns = index.conns.keys().copied().collect();
+        index.send(&conns, PushFrame::Frame(frame.to_string()))
+    }
+
+    /// Close every connection; returns how many were asked to close
+    pub fn close_all(&self) -> usize {
+        let index = self.inner.lock().unwrap();
+        let conns = index.conns.keys().copied().collect();
+        index.send(&conns, PushFrame::Close(None))
+    }
+
+    /// Close one connection, optionally sending a final frame first
+    pub fn close_connection(&self, conn_id: u64, final_frame: Option<&str>) -> bool {
+        let index = self.inner.lock().unwrap();
+        index
+            .conns
+            .get(&conn_id)
+            .is_some_and(|entry| entry.sender.push(PushFrame::Close(final_frame.map(String::from))))
+    }
+
+    /// Close every connection of a session, optionally sending a final frame first
+    pub fn close_session(&self, token: &str, final_frame: Option<&str>) -> usize {
+        let index = self.inner.lock().unwrap();
+        let conns = index.conns_of_session(token);
+        index.send(&conns, PushFrame::Close(final_frame.map(String::from)))
+    }
+
+    /// Tell the connections of evicted sessions, then forget the sessions
+    pub fn notify_evicted(&self, kind: SessionLimitKind, evicted: Vec<WmtpSession>) {
+        for session in evicted {
+            let notice = Admission::eviction_notice(kind, &session).to_json();
+            self.send_to_session(&session.token, &notice);
+            self.unbind_session(&session.token);
+        }
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outbound::{self, OutboundConfig, OutboundQueue};
+
+    fn queue() -> (PushSender, OutboundQueue) {
+        outbound::channel(OutboundConfig::default())
+    }
+
+    #[test]
+    fn test_make_connection_info() {
+        let addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let info = make_connection_info(7, Some(addr));
+        assert_eq!(info.id, 7);
+        assert_eq!(info.remote_addr, Some("127.0.0.1:5000".to_string()));
+        assert!(info.session_tokens.is_empty());
+        assert_eq!(info.client_version, None);
+    }
+
+    #[test]
+    fn test_stats_and_client_version() {
+        let store = create_connection_store();
+        let info = make_connection_info(1, None);
+        let stats = info.stats.clone();
+        store.lock().unwrap().insert(1, info);
+
+        assert_eq!(stats.snapshot().last_activity, None);
+        stats.frame_received(20);
+        stats.bytes_received(1000);
+        stats.frame_sent(50);
+        stats.stream_opened();
+        stats.stream_opened();
+        stats.stream_closed();
+        stats.command_processed();
+        stats.record_path(Duration::from_micros(12_500), Some(1200));
+        set_client_version(&store, 1, "wmtp-js/1.2\n\u{7}");
+        set_client_version(&store, 2, "ignored");
+
+        let value = serde_json::to_value(store.lock().unwrap().get(&1).unwrap()).unwrap();
+        assert_eq!(value["client_version"], "wmtp-js/1.2");
+        let stats = &value["stats"];
+        assert_eq!((stats["bytes_in"].as_u64(), stats["frames_in"].as_u64()), (Some(1020), Some(1)));
+        assert_eq!((stats["bytes_out"].as_u64(), stats["frames_out"].as_u64()), (Some(50), Some(1)));
+        assert_eq!(stats["streams_open"], 1);
+        assert_eq!(stats["commands"], 1);
+        assert_eq!(stats["rtt_ms"], 12.5);
+        assert_eq!(stats["max_datagram_size"], 1200);
+        assert!(stats["last_activity"].is_string());
+        assert_eq!(stats["idle_secs"], 0);
+    }
+
+    #[test]
+    fn test_fan_out_to_session_and_user() {
+        let index = create_connection_index();
+        let (tx1, mut rx1) = queue();
+        let (tx2, mut rx2) = queue();
+        index.register(1, tx1);
+        index.register(2, tx2);
+
+        // Same user on two devices, each with its own session
+        index.bind("desktop", 1, Some("User@test.com"));
+        index.bind("phone", 2, Some("user@test.com"));
+
+        assert_eq!(index.send_to_session("desktop", "a"), 1);
+        assert_eq!(rx1.try_recv().unwrap(), PushFrame::Frame("a".to_string()));
+        assert!(rx2.try_recv().is_err());
+
+        assert_eq!(index.send_to_user("user@test.com", "b"), 2);
+        assert_eq!(rx1.try_recv().unwrap(), PushFrame::Frame("b".to_string()));
+        assert_eq!(rx2.try_recv().unwrap(), PushFrame::Frame("b".to_string()));
+    }
+
+    #[test]
+    fn test_unregister_and_unbind() {
+        let index = create_connection_index();
+        let (tx, _rx) = queue();
+        index.register(1, tx);
+        index.bind("t1", 1, Some("u@test.com"));
+        index.bind("t2", 1, None);
+
+        index.unbind_session("t1");
+        assert!(index.connections_of_session("t1").is_empty());
+        assert!(index.connections_of_user("u@test.com").is_empty());
+        assert_eq!(index.sessions_of_connection(1), vec!["t2".to_string()]);
+
+        index.unregister(1);
+        assert!(index.connections_of_session("t2").is_empty());
+        assert_eq!(index.send_to_session("t2", "x"), 0);
+    }
+
+    #[test]
+    fn test_close_connection() {
+        let index = create_connection_index();
+        let (tx, mut rx) = queue();
+        index.register(1, tx);
+        assert!(index.close_connection(1, Some("bye")));
+        assert!(!index.close_connection(2, None));
+        assert_eq!(rx.try_recv().unwrap(), PushFrame::Close(Some("bye".to_string())));
+    }
+
+    #[test]
+    fn test_close_session() {
+        let index = create_connection_index();
+        let (tx, mut rx) = queue();
+        index.register(1, tx);
+        index.bind("t1", 1, None);
+
+        assert_eq!(index.close_session("t1", Some("bye")), 1);
+        assert_eq!(rx.try_recv().unwrap(), PushFrame::Close(Some("bye".to_string())));
+    }
+
+    #[test]
+    fn test_broadcast_and_close_all() {
+        let index = create_connection_index();
+        let (tx1, mut rx1) = queue();
+        let (tx2, mut rx2) = queue();
+        index.register(1, tx1);
+        index.register(2, tx2);
+        index.bind("t1", 1, None);
+
+        // Reaches connections with and without a session
+        assert_eq!(index.broadcast("bye"), 2);
+        assert_eq!(rx1.try_recv().unwrap(), PushFrame::Frame("bye".to_string()));
+        assert_eq!(rx2.try_recv().unwrap(), PushFrame::Frame("bye".to_string()));
+
+        assert_eq!(index.close_all(), 2);
+        assert_eq!(rx2.try_recv().unwrap(), PushFrame::Close(None));
+    }
+
+    #[test]
+    fn test_stale_sessions_and_info_binding() {
+        let store = create_connection_store();
+        store.lock().unwrap().insert(1, make_connection_info(1, None));
+        let index = create_connection_index();
+        let (tx, _rx) = queue();
+        index.register(1, tx);
+        index.bind("live", 1, None);
+        index.bind("gone", 1, None);
+        bind_connection_info(&store, 1, "live", Some("u@test.com"));
+        bind_connection_info(&store, 1, "gone", None);
+
+        assert_eq!(index.stale_sessions(|t| t == "live"), vec!["gone".to_string()]);
+
+        unbind_connection_info(&store, "gone");
+        let info = store.lock().unwrap().get(&1).cloned().unwrap();
+        assert_eq!(info.session_tokens, vec!["live".to_string()]);
+        assert_eq!(info.email, Some("u@test.com".to_string()));
+    }
+}
@@ -56,14 +56,41 @@ pub mod codes {
     pub const SESSION_NOT_FOUND: u32 = 2003;
     pub const SESSION_EXPIRED: u32 = 2004;
     pub const INVALID_TOKEN: u32 = 2005;
+    pub const SESSION_LIMIT_EXCEEDED: u32 = 2006;
+    pub const ACCOUNT_LOCKED: u32 = 2007;
+    pub const CONNECTION_NOT_FOUND: u32 = 2008;
+    pub const COMMAND_NOT_ALLOWED: u32 = 2009;
+    pub const ALREADY_AUTHENTICATED: u32 = 2010;
+    pub const LINK_SELF: u32 = 2011;
+    pub const ACCOUNT_DISABLED: u32 = 2012;
     
     // Mail errors (3xxx)
     pub const MAIL_NOT_FOUND: u32 = 3001;
     pub const MAILBOX_NOT_FOUND: u32 = 3002;
     pub const RECIPIENT_NOT_FOUND: u32 = 3003;
     pub const MAIL_TOO_LARGE: u32 = 3004;
+    pub const USER_NOT_FOUND: u32 = 3005;
+    pub const ALREADY_EXISTS: u32 = 3006;
+    
+    // Limit errors (4xxx)
+    pub const RATE_LIMITED: u32 = 4001;
     
     // Server errors (5xxx)
     pub const INTERNAL_ERROR: u32 = 5000;
     pub const SERVICE_UNAVAILABLE: u32 = 5001;
 }
+
+/// WebTransport close / stream reset codes
+pub mod close_codes {
+    /// Normal close
+    pub const NORMAL: u32 = 0;
+
+    // Admission control (429x)
+    pub const CONNECTION_LIMIT: u32 = 4290;
+    pub const IP_CONNECTION_LIMIT: u32 = 4291;
+    pub const HANDSHAKE_RATE: u32 = 4292;
+    pub const STREAM_LIMIT: u32 = 4293;
+
+    /// Client stopped reading (outbound queue stayed full)
+    pub const SLOW_CONSUMER: u32 = 4294;
+}
@@ -0,0 +1,134 @@
+//! Guest (unauthenticated) session policy
+//!
+//! A session that has not completed `AUTH` is a guest. Guests may only run
+//! an explicit set of commands; everything else is refused before dispatch.
+
+use serde_json::Value;
+use std::collections::HashSet;
+
+use crate::commands::{cmd, Response};
+use crate::error::codes;
+
+/// Commands a guest may run when nothing else is configured
+pub const DEFAULT_GUEST_COMMANDS: &[&str] = &[
+    cmd::INIT,
+    cmd::AUTH,
+    cmd::RESUME,
+    cmd::LOGOUT,
+    cmd::LINK_REQUEST,
+    cmd::PING,
+    cmd::LATENCY_PING,
+    cmd::INFO,
+];
+
+/// What a guest session is allowed to do
+#[derive(Debug, Clone)]
+pub struct GuestPolicy {
+    /// Upper-cased command names guests may run
+    allowed: HashSet<String>,
+
+    /// Public contact address guests may `MSG_SEND` to (disabled if `None`)
+    pub contact_mailbox: Option<String>,
+}
+
+impl Default for GuestPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_GUEST_COMMANDS.iter().copied(), None)
+    }
+}
+
+impl GuestPolicy {
+    /// Create a policy from a list of allowed command names
+    pub fn new<'a, I>(allowed: I, contact_mailbox: Option<String>) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        Self {
+            allowed: allowed
+                .into_iter()
+                .map(|c| c.trim().to_uppercase())
+                .filter(|c| !c.is_empty())
+                .collect(),
+            contact_mailbox: contact_mailbox.map(|m| m.trim().to_lowercase()),
+        }
+    }
+
+    /// Whether a guest may run `command` with the given request data
+    pub fn allows(&self, command: &str, data: &Value) -> bool {
+        if self.allowed.contains(command) {
+            return true;
+        }
+        command == cmd::MSG_SEND && self.is_contact_submission(data)
+    }
+
+    /// Check a guest request; returns the refusal response if not allowed
+    pub fn check(&self, command: &str, data: &Value) -> Option<Response> {
+        if self.allows(command, data) {
+            None
+        } else {
+            Some(
+                Response::err(command, "AUTH_REQUIRED", codes::AUTH_REQUIRED)
+                    .with_data(serde_json::json!({ "guest": true })),
+            )
+        }
+    }
+
+    /// A `MSG_SEND` whose only recipient is the public contact mailbox
+    fn is_contact_submission(&self, data: &Value) -> bool {
+        let Some(contact) = &self.contact_mailbox else {
+            return false;
+        };
+        let recipients: Vec<&str> = match data.get("to") {
+            Some(Value::String(to)) => vec![to.as_str()],
+            Some(Value::Array(to)) => to.iter().filter_map(Value::as_str).collect(),
+            _ => return false,
+        };
+        !recipients.is_empty()
+            && recipients.iter().all(|r| r.trim().to_lowercase() == *contact)
+            && data.get("cc").is_none()
+            && data.get("bcc").is_none()
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_default_guest_commands() {
+        let policy = GuestPolicy::default();
+        assert!(policy.allows("PING", &Value::Null));
+        assert!(policy.allows("AUTH", &Value::Null));
+        assert!(!policy.allows("MB_LIST", &Value::Null));
+        assert!(!policy.allows("MSG_SEND", &json!({ "to": "a@b.com" })));
+    }
+
+    #[test]
+    fn test_contact_mailbox_submission() {
+        let policy = GuestPolicy::new(["PING"], Some("Contact@wmtp.online".to_string()));
+
+        assert!(policy.allows("MSG_SEND", &json!({ "to": "contact@wmtp.online" })));
+        assert!(policy.allows("MSG_SEND", &json!({ "to": ["contact@wmtp.online"] })));
+        assert!(!policy.allows("MSG_SEND", &json!({ "to": ["contact@wmtp.online", "x@y.z"] })));
+        assert!(!policy.allows(
+            "MSG_SEND",
+            &json!({ "to": "contact@wmtp.online", "bcc": "x@y.z" })
+        ));
+        assert!(!policy.allows("MSG_SEND", &json!({})));
+    }
+
+    #[test]
+    fn test_refusal_response() {
+        let policy = GuestPolicy::new(["ping"], None);
+        assert!(policy.check("PING", &Value::Null).is_none());
+
+        let resp = policy.check("MB_LIST", &Value::Null).unwrap();
+        assert_eq!(resp.status, "ERR");
+        assert_eq!(resp.code, Some(codes::AUTH_REQUIRED));
+    }
+}
@@ -0,0 +1,306 @@
+//! Liveness and readiness probes
+//!
+//! `/livez` answers whether the accept loop of every listener (one per accept
+//! shard) is running (a loop that returned or panicked fails it); `/readyz`
+//! whether the server should receive traffic: a certificate is loaded, the
+//! storage backend answered its last ping, and the server is not draining.
+//! Both return 200 or 503 with a JSON body detailing every check.
+//!
+//! Storage is pinged by a background task and the result cached here, so
+//! probes are cheap and never hit the database themselves.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use tokio::task::JoinHandle;
+
+use crate::http::{HttpHandler, HttpResponse};
+use crate::shutdown::SharedShutdown;
+
+/// How often the storage backend is pinged
+pub const STORAGE_PING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A storage result older than this no longer counts as healthy
+pub const STORAGE_STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// Outcome of one check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+    pub checked_at: DateTime<Utc>,
+}
+
+impl Check {
+    /// Passing check
+    pub fn pass(detail: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            detail: detail.into(),
+            checked_at: Utc::now(),
+        }
+    }
+
+    /// Failing check
+    pub fn fail(detail: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            detail: detail.into(),
+            checked_at: Utc::now(),
+        }
+    }
+
+    /// JSON form used in probe responses
+    pub fn to_json(&self) -> Value {
+        json!({
+            "ok": self.ok,
+            "detail": self.detail,
+            "checked_at": self.checked_at.to_rfc3339(),
+        })
+    }
+}
+
+/// Health state of the running server
+pub struct Health {
+    shutdown: SharedShutdown,
+    accepting: AtomicBool,
+    accept_loops: Mutex<Vec<(String, JoinHandle<()>)>>,
+    tls: RwLock<Option<Check>>,
+    storage: RwLock<Option<Check>>,
+}
+
+/// Thread-safe health type
+pub type SharedHealth = Arc<Health>;
+
+/// Create the health state for a server
+pub fn create_health(shutdown: SharedShutdown) -> SharedHealth {
+    Arc::new(Health::new(shutdown))
+}
+
+impl Health {
+    /// Nothing checked yet: neither live nor ready
+    pub fn new(shutdown: SharedShutdown) -> Self {
+        Self {
+            shutdown,
+            accepting: AtomicBool::new(false),
+            accept_loops: Mutex::new(Vec::new()),
+            tls: RwLock::new(None),
+            storage: RwLock::new(None),
+        }
+    }
+
+    /// The accept loop started (`true`) or stopped (`false`)
+    pub fn set_accepting(&self, accepting: bool) {
+        self.accepting.store(accepting, Ordering::SeqCst);
+    }
+
+    /// Watch the accept loop task `name` (a listener or one of its shards);
+    /// liveness fails once it ends
+    pub fn watch_accept_loop(&self, name: &str, handle: JoinHandle<()>) {
+        self.accept_loops
+            .lock()
+            .unwrap()
+            .push((name.to_string(), handle));
+    }
+
+    /// Result of loading or reloading the TLS identity
+    pub fn record_tls(&self, check: Check) {
+        *self.tls.write().unwrap() = Some(check);
+    }
+
+    /// Result of a storage ping
+    pub fn record_storage(&self, check: Check) {
+        *self.storage.write().unwrap() = Some(check);
+    }
+
+    /// Whether the process is alive, with details
+    pub fn liveness(&self) -> (bool, Value) {
+        let loops = self.accept_loops.lock().unwrap();
+        let stopped: Vec<&str> = loops
+            .iter()
+            .filter(|(_, handle)| handle.is_finished())
+            .map(|(name, _)| name.as_str())
+            .collect();
+        let accept_loop = if !self.accepting.load(Ordering::SeqCst) {
+            Check::fail("accept loop not running")
+        } else if !stopped.is_empty() {
+            Check::fail(format!("accept loop stopped on {}", stopped.join(", ")))
+        } else {
+            Check::pass("accepting connections")
+        };
+        report(&[("accept_loop", accept_loop)])
+    }
+
+    /// Storage backend health (a stale successful ping counts as a failure)
+    pub fn storage(&self) -> Check {
+        match self.storage.read().unwrap().clone() {
+            None => Check::fail("not checked yet"),
+            Some(check) if check.ok && is_stale(&check, Utc::now()) => Check {
+                ok: false,
+                detail: format!("no successful ping since {}", check.checked_at.to_rfc3339()),
+                ..check
+            },
+            Some(check) => check,
+        }
+    }
+
+    /// Whether the server should receive traffic, with details
+    pub fn readiness(&self) -> (bool, Value) {
+        let tls = self
+            .tls
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| Check::fail("no certificate loaded"));
+
+        let storage = self.storage();
+
+        let draining = if self.shutdown.is_draining() {
+            Check::fail("server is shutting down")
+        } else {
+            Check::pass("not draining")
+        };
+
+        report(&[("tls", tls), ("storage", storage), ("draining", draining)])
+    }
+
+    /// HTTP handler serving `/livez` and `/readyz`
+    pub fn handler(self: &Arc<Self>) -> HttpHandler {
+        let health = self.clone();
+        Arc::new(move |path| {
+            let (ok, body) = match path {
+                "/livez" => health.liveness(),
+                "/readyz" => health.readiness(),
+                _ => return HttpResponse::not_found(),
+            };
+            HttpResponse::json(if ok { 200 } else { 503 }, &body)
+        })
+    }
+}
+
+fn is_stale(check: &Check, now: DateTime<Utc>) -> bool {
+    (now - check.checked_at).to_std().unwrap_or_default() > STORAGE_STALE_AFTER
+}
+
+/// Overall result and JSON body for a set of named checks
+fn report(checks: &[(&str, Check)]) -> (bool, Value) {
+    let ok = checks.iter().all(|(_, check)| check.ok);
+    let details: Map<String, Value> = checks
+        .iter()
+        .map(|(name, check)| (name.to_string(), check.to_json()))
+        .collect();
+    let body = json!({
+        "status": if ok { "ok" } else { "fail" },
+        "checks": details,
+    });
+    (ok, body)
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shutdown::create_shutdown;
+
+    #[test]
+    fn test_liveness_follows_accept_loop() {
+        let health = Health::new(create_shutdown());
+        assert!(!health.liveness().0);
+
+        health.set_accepting(true);
+        let (ok, body) = health.liveness();
+        assert!(ok);
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["checks"]["accept_loop"]["ok"], true);
+    }
+
+    #[tokio::test]
+    async fn test_liveness_fails_when_an_accept_loop_ends() {
+        let health = Health::new(create_shutdown());
+        health.set_accepting(true);
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        health.watch_accept_loop("public", tokio::spawn(std::future::pending()));
+        health.watch_accept_loop(
+            "ops",
+            tokio::spawn(async move {
+                let _ = stop_rx.await;
+            }),
+        );
+        assert!(health.liveness().0);
+
+        stop_tx.send(()).unwrap();
+        for _ in 0..50 {
+            if !health.liveness().0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let (ok, body) = health.liveness();
+        assert!(!ok);
+        assert_eq!(
+            body["checks"]["accept_loop"]["detail"],
+            "accept loop stopped on ops"
+        );
+    }
+
+    #[test]
+    fn test_readiness_checks() {
+        let shutdown = create_shutdown();
+        let health = Health::new(shutdown.clone());
+
+        let (ok, body) = health.readiness();
+        assert!(!ok);
+        assert_eq!(body["checks"]["tls"]["ok"], false);
+        assert_eq!(body["checks"]["storage"]["detail"], "not checked yet");
+        assert_eq!(body["checks"]["draining"]["ok"], true);
+
+        health.record_tls(Check::pass("SHA-256 AB:CD"));
+        health.record_storage(Check::pass("ping ok"));
+        assert!(health.readiness().0);
+
+        health.record_storage(Check::fail("connection refused"));
+        let (ok, body) = health.readiness();
+        assert!(!ok);
+        assert_eq!(body["checks"]["storage"]["detail"], "connection refused");
+
+        health.record_storage(Check::pass("ping ok"));
+        shutdown.begin();
+        let (ok, body) = health.readiness();
+        assert!(!ok);
+        assert_eq!(body["status"], "fail");
+        assert_eq!(body["checks"]["draining"]["ok"], false);
+    }
+
+    #[test]
+    fn test_stale_storage_ping() {
+        let health = Health::new(create_shutdown());
+        health.record_tls(Check::pass("SHA-256 AB:CD"));
+        health.record_storage(Check {
+            checked_at: Utc::now() - chrono::Duration::seconds(120),
+            ..Check::pass("ping ok")
+        });
+        let (ok, body) = health.readiness();
+        assert!(!ok);
+        assert!(body["checks"]["storage"]["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with("no successful ping since"));
+    }
+
+    #[test]
+    fn test_handler_status_codes() {
+        let health = create_health(create_shutdown());
+        let handler = health.handler();
+        assert_eq!(handler("/livez").status, 503);
+        health.set_accepting(true);
+        assert_eq!(handler("/livez").status, 200);
+        assert_eq!(handler("/readyz").status, 503);
+        assert_eq!(handler("/other").status, 404);
+    }
+}
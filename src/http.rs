@@ -0,0 +1,186 @@
+//! Minimal HTTP/1.1 responder for local operator endpoints
+//!
+//! Only what loopback tooling needs: `GET` requests, one response per
+//! connection, small bodies. Not meant to be exposed publicly.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Largest request head accepted (bytes)
+const MAX_REQUEST_HEAD: usize = 8192;
+
+/// How long a client gets to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A response to a local HTTP request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+
+    /// Send `Access-Control-Allow-Origin: *` (readable from any web page)
+    pub any_origin: bool,
+}
+
+impl HttpResponse {
+    /// JSON response
+    pub fn json(status: u16, body: &serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: body.to_string(),
+            any_origin: false,
+        }
+    }
+
+    /// Plain-text response
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into(),
+            any_origin: false,
+        }
+    }
+
+    /// Allow pages of any origin to read this response
+    pub fn with_any_origin(mut self) -> Self {
+        self.any_origin = true;
+        self
+    }
+
+    /// 404 response
+    pub fn not_found() -> Self {
+        Self::text(404, "not found\n")
+    }
+
+    /// Serialize as an HTTP/1.1 response
+    pub fn render(&self) -> String {
+        let cors = if self.any_origin {
+            "Access-Control-Allow-Origin: *\r\n"
+        } else {
+            ""
+        };
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\n{}Connection: close\r\n\r\n{}",
+            self.status,
+            reason(self.status),
+            self.content_type,
+            self.body.len(),
+            cors,
+            self.body
+        )
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+/// Handles a `GET` for a path (query string already stripped)
+pub type HttpHandler = Arc<dyn Fn(&str) -> HttpResponse + Send + Sync>;
+
+/// Method and path (without query) from a request head
+pub fn parse_request_line(head: &str) -> Option<(&str, &str)> {
+    let mut parts = head.lines().next()?.split_whitespace();
+    let method = parts.next()?;
+    let target = parts.next()?;
+    let path = target.split('?').next().unwrap_or(target);
+    Some((method, path))
+}
+
+/// Serve requests from `listener` until it fails
+pub async fn serve(listener: TcpListener, handler: HttpHandler) -> std::io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, &handler).await {
+                tracing::debug!("Local HTTP request failed: {:?}", e);
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream, handler: &HttpHandler) -> std::io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    let read = async {
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST_HEAD {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            head.extend_from_slice(&buf[..n]);
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    if tokio::time::timeout(READ_TIMEOUT, read).await.is_err() {
+        return Ok(());
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let response = match parse_request_line(&head) {
+        Some(("GET", path)) => handler(path),
+        Some(_) => HttpResponse::text(405, "method not allowed\n"),
+        None => HttpResponse::text(400, "bad request\n"),
+    };
+    stream.write_all(response.render().as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request_line() {
+        assert_eq!(
+            parse_request_line("GET /cert-hash?x=1 HTTP/1.1\r\nHost: a\r\n\r\n"),
+            Some(("GET", "/cert-hash"))
+        );
+        assert_eq!(parse_request_line(""), None);
+        assert_eq!(parse_request_line("GET"), None);
+    }
+
+    #[tokio::test]
+    async fn test_serve_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handler: HttpHandler = Arc::new(|path| match path {
+            "/hello" => HttpResponse::json(200, &serde_json::json!({ "ok": true })),
+            _ => HttpResponse::not_found(),
+        });
+        tokio::spawn(serve(listener, handler));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /hello HTTP/1.1\r\nHost: x\r\n\r\n").await.unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).await.unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"), "{out}");
+        assert!(out.ends_with("{\"ok\":true}"), "{out}");
+        assert!(!out.contains("Access-Control-Allow-Origin"), "{out}");
+    }
+
+    #[test]
+    fn test_any_origin() {
+        let response = HttpResponse::text(200, "x").with_any_origin();
+        assert!(response.render().contains("\r\nAccess-Control-Allow-Origin: *\r\n"));
+        assert!(!HttpResponse::not_found().render().contains("Access-Control"));
+    }
+}
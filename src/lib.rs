@@ -3,16 +3,42 @@
 //! WebTransport Mail Transfer Protocol implementation in Rust.
 //! Built on QUIC for secure, low-latency mail transfer.
 
+pub mod accept;
+pub mod admin;
+pub mod admission;
+pub mod audit;
 pub mod config;
 pub mod commands;
+pub mod connection;
 pub mod error;
+pub mod guest;
+pub mod health;
+pub mod http;
+pub mod linking;
+pub mod listener;
+pub mod lockout;
+pub mod logging;
+pub mod maintenance;
+pub mod metrics;
+pub mod outbound;
+pub mod ratelimit;
+pub mod reload;
 pub mod server;
 pub mod session;
+pub mod shutdown;
+pub mod status;
+pub mod tls;
 pub mod token;
 
 // Re-exports for convenience
 pub use config::Config;
+pub use connection::{ConnectionIndex, ConnectionStore, create_connection_index};
 pub use error::{WmtpError, WmtpResult};
-pub use server::run_server;
-pub use session::{SessionStore, WmtpSession, create_session_store};
-pub use token::{generate_identity_token, verify_identity_token, generate_ephemeral_token};
+pub use server::{run_server, run_server_with_hooks};
+pub use session::{
+    SessionEvent, SessionEventKind, SessionManager, SessionObserver, SessionStore, WmtpSession,
+    create_session_store,
+};
+pub use token::{
+    generate_identity_token, verify_identity_token, generate_ephemeral_token, generate_device_token,
+};
@@ -0,0 +1,464 @@
+//! Cross-device session hand-off (device linking)
+//!
+//! A new device with a guest session asks for a short pairing code
+//! (`LINK_REQUEST`) and shows it to the user. While waiting, its session is
+//! suspended. A session already logged in as the user approves the code
+//! (`LINK_APPROVE`); the waiting session is then resumed as an authenticated
+//! session of the same user under a fresh device token, and receives an
+//! `AUTH_OK` push just like after a normal login. The per-user session cap
+//! applies as for `AUTH`, except that the approving session is never evicted
+//! to make room.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::commands::{cmd, Response};
+use crate::connection::{
+    bind_connection_info, unbind_connection_info, ConnectionIndex, ConnectionStore,
+};
+use crate::error::codes;
+use crate::session::{Admission, SessionCause, SessionLimitKind, SessionManager, WmtpSession};
+use crate::token::generate_device_token;
+
+/// Pairing code length (characters)
+pub const CODE_LEN: usize = 6;
+
+/// Unambiguous code alphabet (no 0/O, 1/I/L)
+const CODE_ALPHABET: &[u8] = b"23456789ABCDEFGHJKMNPQRSTUVWXYZ";
+
+/// Why a link request or approval was refused
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum LinkError {
+    #[error("session not found")]
+    SessionNotFound,
+
+    #[error("session is already authenticated")]
+    AlreadyAuthenticated,
+
+    #[error("approving session is not authenticated")]
+    NotAuthenticated,
+
+    #[error("unknown or expired pairing code")]
+    UnknownCode,
+
+    #[error("a session cannot link itself")]
+    LinkSelf,
+
+    #[error("linking session no longer exists")]
+    LinkingSessionGone,
+
+    #[error("too many concurrent sessions")]
+    SessionLimit(SessionLimitKind),
+}
+
+impl LinkError {
+    /// Protocol error code
+    pub fn code(&self) -> u32 {
+        match self {
+            LinkError::NotAuthenticated => codes::AUTH_REQUIRED,
+            LinkError::AlreadyAuthenticated => codes::ALREADY_AUTHENTICATED,
+            LinkError::LinkSelf => codes::LINK_SELF,
+            LinkError::SessionLimit(_) => codes::SESSION_LIMIT_EXCEEDED,
+            LinkError::SessionNotFound | LinkError::UnknownCode | LinkError::LinkingSessionGone => {
+                codes::SESSION_NOT_FOUND
+            }
+        }
+    }
+}
+
+/// A pending link request
+#[derive(Debug, Clone)]
+struct Pairing {
+    token: String,
+    expires_at: Instant,
+}
+
+/// Pending pairing codes
+pub struct PairingRegistry {
+    pending: Mutex<HashMap<String, Pairing>>,
+    ttl: Duration,
+}
+
+/// Thread-safe pairing registry type
+pub type SharedPairingRegistry = Arc<PairingRegistry>;
+
+/// Create a pairing registry whose codes live for `ttl_secs`
+pub fn create_pairing_registry(ttl_secs: u64) -> SharedPairingRegistry {
+    Arc::new(PairingRegistry::new(ttl_secs))
+}
+
+/// Generate a random pairing code
+fn generate_code() -> String {
+    uuid::Uuid::new_v4()
+        .as_bytes()
+        .iter()
+        .take(CODE_LEN)
+        .map(|b| CODE_ALPHABET[*b as usize % CODE_ALPHABET.len()] as char)
+        .collect()
+}
+
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+impl PairingRegistry {
+    /// Create a new registry
+    pub fn new(ttl_secs: u64) -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            ttl: Duration::from_secs(ttl_secs),
+        }
+    }
+
+    /// Code lifetime in seconds
+    pub fn ttl_secs(&self) -> u64 {
+        self.ttl.as_secs()
+    }
+
+    /// Issue a pairing code for a guest session and suspend it until approved
+    ///
+    /// Asking again replaces the previous code of the same session. The
+    /// suspension goes through the manager, which reports it as `Suspended`.
+    pub fn request(&self, manager: &SessionManager, token: &str) -> Result<String, LinkError> {
+        let session = manager.get(token).ok_or(LinkError::SessionNotFound)?;
+        if !session.is_guest() {
+            return Err(LinkError::AlreadyAuthenticated);
+        }
+
+        let code = {
+            let mut pending = self.pending.lock().unwrap();
+            let now = Instant::now();
+            pending.retain(|_, p| p.token != token && p.expires_at > now);
+
+            let mut code = generate_code();
+            while pending.contains_key(&code) {
+                code = generate_code();
+            }
+            pending.insert(
+                code.clone(),
+                Pairing {
+                    token: token.to_string(),
+                    expires_at: now + self.ttl,
+                },
+            );
+            code
+        };
+
+        manager.suspend(token, SessionCause::command(cmd::LINK_REQUEST));
+        Ok(code)
+    }
+
+    /// Approve a pairing code from an authenticated session
+    ///
+    /// Returns `(old_token, new_session)` for the linked device. Refused
+    /// when the user is at the per-user cap under the reject policy, or when
+    /// only evicting the approver would make room; the code then stays valid.
+    pub fn approve(
+        &self,
+        manager: &SessionManager,
+        approver_token: &str,
+        code: &str,
+    ) -> Result<(String, WmtpSession), LinkError> {
+        let email = manager
+            .get(approver_token)
+            .filter(|s| s.authenticated)
+            .and_then(|s| s.email)
+            .ok_or(LinkError::NotAuthenticated)?;
+
+        let pairing = {
+            let mut pending = self.pending.lock().unwrap();
+            pending
+                .remove(&normalize_code(code))
+                .filter(|p| p.expires_at > Instant::now())
+                .ok_or(LinkError::UnknownCode)?
+        };
+        if pairing.token == approver_token {
+            return Err(LinkError::LinkSelf);
+        }
+        let admission = manager.admit_user_keeping(&email, &pairing.token, approver_token);
+        if let Admission::Rejected(kind) = admission {
+            let mut pending = self.pending.lock().unwrap();
+            pending.insert(normalize_code(code), pairing);
+            return Err(LinkError::SessionLimit(kind));
+        }
+
+        let new_token = generate_device_token();
+        let linked = manager
+            .promote(
+                &pairing.token,
+                &new_token,
+                email,
+                SessionCause::command(cmd::LINK_APPROVE),
+            )
+            .ok_or(LinkError::LinkingSessionGone)?;
+        Ok((pairing.token, linked))
+    }
+
+    /// Drop expired codes; returns how many were removed
+    pub fn purge_expired(&self) -> usize {
+        let mut pending = self.pending.lock().unwrap();
+        let before = pending.len();
+        let now = Instant::now();
+        pending.retain(|_, p| p.expires_at > now);
+        before - pending.len()
+    }
+}
+
+// ============================================================================
+// COMMAND HANDLERS
+// ============================================================================
+
+/// `LINK_REQUEST`: new device asks for a pairing code
+pub fn handle_link_request(token: &str, manager: &SessionManager, pairings: &PairingRegistry) -> String {
+    match pairings.request(manager, token) {
+        Ok(code) => Response::ok(cmd::LINK_CODE)
+            .with_token(token.to_string())
+            .with_data(serde_json::json!({
+                "code": code,
+                "expires_in": pairings.ttl_secs(),
+            }))
+            .to_json(),
+        Err(e) => link_error(cmd::LINK_REQUEST, e),
+    }
+}
+
+/// `LINK_APPROVE`: logged-in session approves a code shown on the new device
+///
+/// The new device's connections get an `AUTH_OK` push carrying their own
+/// token and are re-bound to it in the connection index and store. Under the
+/// evict policy, sessions pushing the user over the cap are evicted, never
+/// the approving one.
+pub fn handle_link_approve(
+    token: &str,
+    code: Option<&str>,
+    manager: &SessionManager,
+    pairings: &PairingRegistry,
+    index: &ConnectionIndex,
+    connections: &ConnectionStore,
+) -> String {
+    let Some(code) = code else {
+        return Response::err(cmd::LINK_APPROVE, "MISSING_CODE", codes::MISSING_FIELD).to_json();
+    };
+
+    match pairings.approve(manager, token, code) {
+        Ok((old_token, linked)) => {
+            let email = linked.email.clone().unwrap_or_default();
+            let mut auth_ok = Response::ok(cmd::AUTH_OK)
+                .with_token(linked.token.clone())
+                .with_auth(true)
+                .with_email(email.clone())
+                .with_data(serde_json::json!({ "linked": true }));
+            if let Some(username) = linked.username.clone() {
+                auth_ok = auth_ok.with_username(username);
+            }
+
+            let conns = index.connections_of_session(&old_token);
+            index.send_to_session(&old_token, &auth_ok.to_json());
+            index.unbind_session(&old_token);
+            unbind_connection_info(connections, &old_token);
+            for conn_id in conns {
+                index.bind(&linked.token, conn_id, Some(&email));
+                bind_connection_info(connections, conn_id, &linked.token, Some(&email));
+            }
+
+            let admission = manager.evict_for_user_keeping(&email, &linked.token, token);
+            if let Admission::Evicted(kind, evicted) = admission {
+                index.notify_evicted(kind, evicted);
+            }
+
+            Response::ok(cmd::LINK_APPROVED)
+                .with_msg("Device linked")
+                .to_json()
+        }
+        Err(LinkError::SessionLimit(kind)) => {
+            Admission::rejection_response(cmd::LINK_APPROVE, kind).to_json()
+        }
+        Err(e) => link_error(cmd::LINK_APPROVE, e),
+    }
+}
+
+fn link_error(command: &str, e: LinkError) -> String {
+    Response::err(command, &e.to_string(), e.code()).to_json()
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::{create_connection_index, create_connection_store, PushFrame};
+    use crate::outbound::{self, OutboundConfig};
+    use crate::session::{create_session_store, SessionLimitPolicy, SessionLimits};
+
+    fn setup() -> (SessionManager, PairingRegistry) {
+        let manager = SessionManager::new(create_session_store(), 3600);
+        manager.insert(WmtpSession::new_ephemeral("WMTP-new".to_string()));
+        manager.insert(WmtpSession::new_authenticated(
+            "desktop".to_string(),
+            "user@test.com".to_string(),
+        ));
+        (manager, PairingRegistry::new(120))
+    }
+
+    #[test]
+    fn test_code_format() {
+        let code = generate_code();
+        assert_eq!(code.len(), CODE_LEN);
+        assert!(code.bytes().all(|b| CODE_ALPHABET.contains(&b)));
+        assert_eq!(normalize_code("ab2-c3d"), "AB2C3D");
+    }
+
+    #[test]
+    fn test_link_flow() {
+        let (manager, pairings) = setup();
+
+        let code = pairings.request(&manager, "WMTP-new").unwrap();
+        assert!(manager.get("WMTP-new").unwrap().suspended);
+
+        let (old, linked) = pairings
+            .approve(&manager, "desktop", &code.to_lowercase())
+            .unwrap();
+        assert_eq!(old, "WMTP-new");
+        assert!(linked.authenticated);
+        assert!(!linked.suspended);
+        assert_eq!(linked.email, Some("user@test.com".to_string()));
+        assert_ne!(linked.token, "desktop");
+        assert!(!manager.exists("WMTP-new"));
+        assert!(manager.exists(&linked.token));
+
+        // Codes are single-use
+        assert_eq!(
+            pairings.approve(&manager, "desktop", &code).unwrap_err(),
+            LinkError::UnknownCode
+        );
+    }
+
+    #[test]
+    fn test_link_requires_authenticated_approver() {
+        let (manager, pairings) = setup();
+        manager.insert(WmtpSession::new_ephemeral("WMTP-other".to_string()));
+
+        let code = pairings.request(&manager, "WMTP-new").unwrap();
+        assert_eq!(
+            pairings.approve(&manager, "WMTP-other", &code).unwrap_err(),
+            LinkError::NotAuthenticated
+        );
+        let refused = pairings.request(&manager, "desktop").unwrap_err();
+        assert_eq!(refused, LinkError::AlreadyAuthenticated);
+        assert_eq!(refused.code(), codes::ALREADY_AUTHENTICATED);
+    }
+
+    #[test]
+    fn test_link_self() {
+        let (manager, pairings) = setup();
+        let code = pairings.request(&manager, "WMTP-new").unwrap();
+        // Logged in some other way while the code was pending
+        manager.authenticate("WMTP-new", "user@test.com".to_string());
+        let refused = pairings.approve(&manager, "WMTP-new", &code).unwrap_err();
+        assert_eq!(refused, LinkError::LinkSelf);
+        assert_eq!(refused.code(), codes::LINK_SELF);
+    }
+
+    #[test]
+    fn test_expired_code() {
+        let (manager, _) = setup();
+        let pairings = PairingRegistry::new(0);
+
+        let code = pairings.request(&manager, "WMTP-new").unwrap();
+        assert!(pairings.approve(&manager, "desktop", &code).is_err());
+        assert_eq!(pairings.purge_expired(), 0);
+    }
+
+    fn one_per_user(policy: SessionLimitPolicy) -> SessionLimits {
+        SessionLimits {
+            max_per_user: 1,
+            policy,
+            ..SessionLimits::default()
+        }
+    }
+
+    #[test]
+    fn test_link_rejected_at_session_cap() {
+        let (manager, pairings) = setup();
+        let manager = manager.with_limits(one_per_user(SessionLimitPolicy::RejectNewest));
+        let index = create_connection_index();
+        let connections = create_connection_store();
+
+        let code = pairings.request(&manager, "WMTP-new").unwrap();
+        let response =
+            handle_link_approve("desktop", Some(&code), &manager, &pairings, &index, &connections);
+        let value: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(value["code"], codes::SESSION_LIMIT_EXCEEDED);
+        assert_eq!(value["data"]["limit"], "per_user");
+        assert!(manager.get("WMTP-new").unwrap().suspended);
+
+        // The code survives the refusal and works once the cap is lifted
+        assert_eq!(
+            pairings.approve(&manager, "desktop", &code).unwrap_err(),
+            LinkError::SessionLimit(SessionLimitKind::PerUser)
+        );
+        manager.set_limits(SessionLimits::default());
+        assert!(pairings.approve(&manager, "desktop", &code).is_ok());
+    }
+
+    #[test]
+    fn test_link_evicts_at_session_cap_but_not_approver() {
+        let (manager, pairings) = setup();
+        let manager = manager.with_limits(SessionLimits {
+            max_per_user: 2,
+            policy: SessionLimitPolicy::EvictLeastRecentlyActive,
+            ..SessionLimits::default()
+        });
+        // The approver is the least recently active session of the user
+        if let Some(desktop) = manager.store().lock().unwrap().get_mut("desktop") {
+            desktop.last_activity = Some(Instant::now() - Duration::from_secs(60));
+        }
+        manager.insert(WmtpSession::new_authenticated(
+            "laptop".to_string(),
+            "user@test.com".to_string(),
+        ));
+        let index = create_connection_index();
+        let connections = create_connection_store();
+        let (laptop_tx, mut laptop_rx) = outbound::channel(OutboundConfig::default());
+        index.register(1, laptop_tx);
+        index.bind("laptop", 1, Some("user@test.com"));
+
+        let code = pairings.request(&manager, "WMTP-new").unwrap();
+        let response =
+            handle_link_approve("desktop", Some(&code), &manager, &pairings, &index, &connections);
+        assert!(response.contains(cmd::LINK_APPROVED));
+        assert!(manager.exists("desktop"));
+        assert!(!manager.exists("laptop"));
+        assert_eq!(manager.authenticated_count(), 2);
+
+        match laptop_rx.try_recv() {
+            Ok(PushFrame::Frame(frame)) => assert!(frame.contains(cmd::SESSION_EVICTED)),
+            other => panic!("expected eviction notice, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_link_refused_when_only_approver_could_be_evicted() {
+        let (manager, pairings) = setup();
+        let manager =
+            manager.with_limits(one_per_user(SessionLimitPolicy::EvictLeastRecentlyActive));
+        let index = create_connection_index();
+        let connections = create_connection_store();
+
+        let code = pairings.request(&manager, "WMTP-new").unwrap();
+        let response =
+            handle_link_approve("desktop", Some(&code), &manager, &pairings, &index, &connections);
+        let value: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(value["code"], codes::SESSION_LIMIT_EXCEEDED);
+        assert!(manager.exists("desktop"));
+        assert!(manager.get("WMTP-new").unwrap().suspended);
+    }
+}
@@ -0,0 +1,352 @@
+//! Listeners
+//!
+//! The server can accept WebTransport sessions on several addresses at once:
+//! IPv4 and IPv6 bound explicitly, a public listener next to a loopback-only
+//! one for operators, and so on. Each listener gets its own endpoint,
+//! optionally its own admission caps and a restricted command set; all of them
+//! share one session store, so a session made through one listener can be
+//! resumed through another. Without `listeners` the server binds `host:port`
+//! only.
+//!
+//! With `accept_shards = N` every listener runs N endpoints on consecutive
+//! ports starting at its own (see `accept`).
+//!
+//! The top-level admission caps apply to the server as a whole, across all
+//! listeners. A listener's own caps are checked on top of them, so they can
+//! only tighten the limits for that listener.
+//!
+//! A listener is configured as `NAME=ADDR` followed by `;`-separated options:
+//!
+//! | Option | Meaning |
+//! |--------|---------|
+//! | `v6only` | IPv6 only, no IPv4-mapped addresses (IPv6 addresses only) |
+//! | `admin` | Operator listener: must be loopback; `STATUS` shows the admin detail |
+//! | `max_connections=N` | Connections on this listener |
+//! | `max_connections_per_ip=N` | Connections per IP on this listener |
+//! | `max_streams=N` | Streams per connection on this listener |
+//! | `commands=CMD\|CMD\|...` | Only these commands (plus `INIT`, `PING`, `HB`) |
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::admission::{create_nested_admission, AdmissionLimits, SharedAdmission};
+use crate::commands::Response;
+use crate::error::codes;
+
+/// Commands every listener accepts, whatever its command set
+pub const ALWAYS_ALLOWED: &[&str] = &["INIT", "PING", "HB"];
+
+/// Name of the listener made from `host`/`port` when none are configured
+pub const DEFAULT_LISTENER: &str = "default";
+
+/// One configured listener
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerSpec {
+    pub name: String,
+    pub addr: SocketAddr,
+
+    /// Refuse IPv4-mapped addresses (otherwise the OS default applies)
+    pub v6_only: bool,
+
+    /// Loopback-only operator listener
+    pub admin: bool,
+
+    /// Admission caps of this listener, on top of the top-level ones
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    pub max_streams_per_connection: Option<usize>,
+
+    /// Commands accepted besides `ALWAYS_ALLOWED` (None = all)
+    pub commands: Option<Vec<String>>,
+}
+
+impl ListenerSpec {
+    /// Listener on `addr` with the top-level limits and every command
+    pub fn new(name: &str, addr: SocketAddr) -> Self {
+        Self {
+            name: name.to_string(),
+            addr,
+            v6_only: false,
+            admin: false,
+            max_connections: None,
+            max_connections_per_ip: None,
+            max_streams_per_connection: None,
+            commands: None,
+        }
+    }
+
+    /// Parse `NAME=ADDR[;option...]`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(';').map(str::trim);
+        let head = parts.next().unwrap_or_default();
+        let (name, addr) = head
+            .split_once('=')
+            .ok_or_else(|| format!("`{spec}` is not NAME=ADDR"))?;
+        let name = name.trim().to_lowercase();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "`{spec}`: name must be letters, digits, `-` or `_`"
+            ));
+        }
+        let addr = addr
+            .trim()
+            .parse()
+            .map_err(|e| format!("`{spec}`: bad address: {e}"))?;
+        let mut listener = Self::new(&name, addr);
+
+        let count = |value: &str| {
+            value
+                .parse::<usize>()
+                .map_err(|e| format!("`{spec}`: `{value}` is not a whole number: {e}"))
+        };
+        for option in parts.filter(|o| !o.is_empty()) {
+            let (key, value) = option
+                .split_once('=')
+                .map_or((option, None), |(k, v)| (k.trim(), Some(v.trim())));
+            match (key, value) {
+                ("v6only", None) => listener.v6_only = true,
+                ("admin", None) => listener.admin = true,
+                ("max_connections", Some(v)) => listener.max_connections = Some(count(v)?),
+                ("max_connections_per_ip", Some(v)) => {
+                    listener.max_connections_per_ip = Some(count(v)?)
+                }
+                ("max_streams", Some(v)) => listener.max_streams_per_connection = Some(count(v)?),
+                ("commands", Some(v)) => {
+                    let commands: Vec<String> = v
+                        .split('|')
+                        .map(|c| c.trim().to_uppercase())
+                        .filter(|c| !c.is_empty())
+                        .collect();
+                    if commands.is_empty() {
+                        return Err(format!("`{spec}`: commands must list at least one command"));
+                    }
+                    listener.commands = Some(commands);
+                }
+                _ => return Err(format!("`{spec}`: unknown option `{option}`")),
+            }
+        }
+
+        if listener.v6_only && !addr.is_ipv6() {
+            return Err(format!("`{spec}`: v6only needs an IPv6 address"));
+        }
+        if listener.admin && !addr.ip().is_loopback() {
+            return Err(format!(
+                "`{spec}`: an admin listener must be on a loopback address"
+            ));
+        }
+        Ok(listener)
+    }
+
+    /// This listener's own admission caps (0 = only the top-level cap applies)
+    pub fn admission_limits(&self) -> AdmissionLimits {
+        AdmissionLimits {
+            max_connections: self.max_connections.unwrap_or(0),
+            max_connections_per_ip: self.max_connections_per_ip.unwrap_or(0),
+            max_streams_per_connection: self.max_streams_per_connection.unwrap_or(0),
+            ..AdmissionLimits::default()
+        }
+    }
+
+    /// Addresses of this listener's `shards` endpoints: consecutive ports
+    /// from `addr`, or each its own ephemeral port if `addr`'s port is 0
+    pub fn shard_addrs(&self, shards: usize) -> Result<Vec<SocketAddr>, String> {
+        let base = self.addr.port();
+        (0..shards.max(1))
+            .map(|shard| {
+                let port = match base {
+                    0 => Some(0),
+                    _ => u16::try_from(shard).ok().and_then(|n| base.checked_add(n)),
+                };
+                port.map(|port| SocketAddr::new(self.addr.ip(), port))
+                    .ok_or_else(|| {
+                        format!(
+                            "{}: {shards} shards from port {base} run past 65535",
+                            self.name
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    /// Whether this listener accepts `command`
+    pub fn allows(&self, command: &str) -> bool {
+        ALWAYS_ALLOWED.contains(&command)
+            || self
+                .commands
+                .as_ref()
+                .is_none_or(|commands| commands.iter().any(|c| c == command))
+    }
+}
+
+/// Normalized form, parsed back by [`ListenerSpec::parse`]
+impl fmt::Display for ListenerSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.addr)?;
+        if self.v6_only {
+            write!(f, ";v6only")?;
+        }
+        if self.admin {
+            write!(f, ";admin")?;
+        }
+        if let Some(n) = self.max_connections {
+            write!(f, ";max_connections={n}")?;
+        }
+        if let Some(n) = self.max_connections_per_ip {
+            write!(f, ";max_connections_per_ip={n}")?;
+        }
+        if let Some(n) = self.max_streams_per_connection {
+            write!(f, ";max_streams={n}")?;
+        }
+        if let Some(commands) = &self.commands {
+            write!(f, ";commands={}", commands.join("|"))?;
+        }
+        Ok(())
+    }
+}
+
+/// A running listener: its configuration and admission state
+pub struct Listener {
+    pub spec: ListenerSpec,
+
+    /// This listener's caps, nested in the server-wide admission control
+    pub admission: SharedAdmission,
+}
+
+/// Thread-safe listener type
+pub type SharedListener = Arc<Listener>;
+
+/// Create a listener whose own caps apply on top of the shared `server` ones
+pub fn create_listener(spec: ListenerSpec, server: &SharedAdmission) -> SharedListener {
+    let admission = create_nested_admission(spec.admission_limits(), server.clone());
+    Arc::new(Listener { spec, admission })
+}
+
+impl Listener {
+    /// Listener name, for logs and `STATUS`
+    pub fn name(&self) -> &str {
+        &self.spec.name
+    }
+
+    /// Error response if `command` is not accepted on this listener
+    pub fn check(&self, command: &str) -> Option<Response> {
+        (!self.spec.allows(command)).then(|| {
+            Response::err(
+                command,
+                &format!("{command} is not available on this listener"),
+                codes::COMMAND_NOT_ALLOWED,
+            )
+        })
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admission::create_admission;
+
+    #[test]
+    fn test_parse_and_display() {
+        let spec = ListenerSpec::parse(
+            "Ops = 127.0.0.1:4434; admin; max_connections=10; commands=status|info|session_list",
+        )
+        .unwrap();
+        assert_eq!(spec.name, "ops");
+        assert_eq!(spec.addr, "127.0.0.1:4434".parse().unwrap());
+        assert!(spec.admin);
+        assert_eq!(spec.max_connections, Some(10));
+        assert_eq!(
+            spec.to_string(),
+            "ops=127.0.0.1:4434;admin;max_connections=10;commands=STATUS|INFO|SESSION_LIST"
+        );
+        assert_eq!(ListenerSpec::parse(&spec.to_string()).unwrap(), spec);
+
+        let v6 = ListenerSpec::parse("public6=[::]:4433;v6only").unwrap();
+        assert!(v6.v6_only);
+        assert_eq!(v6.commands, None);
+
+        for bad in [
+            "0.0.0.0:4433",
+            "public=nowhere:4433",
+            "public=0.0.0.0:4433;v6only",
+            "ops=0.0.0.0:4434;admin",
+            "public=0.0.0.0:4433;max_connections=lots",
+            "public=0.0.0.0:4433;commands=",
+            "public=0.0.0.0:4433;fast",
+        ] {
+            assert!(ListenerSpec::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_shard_addrs() {
+        let spec = ListenerSpec::parse("public=0.0.0.0:4433").unwrap();
+        let addrs: Vec<String> = spec
+            .shard_addrs(3)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(addrs, ["0.0.0.0:4433", "0.0.0.0:4434", "0.0.0.0:4435"]);
+        assert_eq!(spec.shard_addrs(0).unwrap(), vec![spec.addr]);
+
+        let ephemeral = ListenerSpec::parse("test=127.0.0.1:0").unwrap();
+        assert!(ephemeral
+            .shard_addrs(2)
+            .unwrap()
+            .iter()
+            .all(|a| a.port() == 0));
+        let top = ListenerSpec::parse("top=0.0.0.0:65535").unwrap();
+        assert!(top.shard_addrs(1).is_ok());
+        assert!(top.shard_addrs(2).is_err());
+    }
+
+    #[test]
+    fn test_limits_and_commands() {
+        let defaults = AdmissionLimits {
+            max_connections: 1000,
+            max_connections_per_ip: 20,
+            max_streams_per_connection: 64,
+            handshake_rate: 50,
+            handshake_burst: 100,
+        };
+        let server = create_admission(defaults);
+        let ops = create_listener(
+            ListenerSpec::parse("ops=[::1]:4434;max_connections_per_ip=2;commands=STATUS").unwrap(),
+            &server,
+        );
+        let limits = ops.admission.limits();
+        assert_eq!(limits.max_connections, 0);
+        assert_eq!(limits.max_connections_per_ip, 2);
+        assert_eq!(limits.handshake_rate, 0);
+        let stats = ops.admission.stats();
+        assert_eq!(stats.server.map(|s| s.limits), Some(defaults));
+
+        assert!(ops.check("STATUS").is_none());
+        assert!(ops.check("PING").is_none());
+        let refused = ops.check("MSG_SEND").unwrap();
+        assert_eq!(refused.code, Some(codes::COMMAND_NOT_ALLOWED));
+
+        let public = create_listener(
+            ListenerSpec::new(DEFAULT_LISTENER, "0.0.0.0:4433".parse().unwrap()),
+            &server,
+        );
+        assert_eq!(public.admission.limits(), AdmissionLimits::default());
+        assert!(public.check("MSG_SEND").is_none());
+
+        // Connections through either listener count against the shared caps
+        let ip = "10.0.0.1".parse().unwrap();
+        let _ops = ops.admission.admit(ip).unwrap();
+        let _public = public.admission.admit(ip).unwrap();
+        assert_eq!(server.stats().open_connections, 2);
+        assert_eq!(server.stats().distinct_ips, 1);
+    }
+}
@@ -0,0 +1,404 @@
+//! Login lockouts
+//!
+//! Repeated AUTH failures for one address from one client IP lock that IP
+//! out of that address: after `max_failures` failures (each within
+//! `duration` of the previous one) further AUTH attempts from the IP are
+//! refused with `ACCOUNT_LOCKED` until `duration` has passed or an operator
+//! clears the lockout with `wmtpctl`. A successful login from the IP resets
+//! its count.
+//!
+//! Failures from other IPs never count towards that lockout, so nobody can
+//! lock a user out of their account by guessing wrong passwords for it from
+//! elsewhere. Guessing spread over many IPs is slowed down instead: once an
+//! address has `slowdown_after` failures (from any IPs, each within
+//! `duration` of the previous one), AUTH for it is accepted at most once
+//! per `slowdown_interval` after each failure, from anywhere. A successful
+//! login resets that count too.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::commands::Response;
+use crate::error::codes;
+
+/// When addresses get locked out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    /// Failures before locking (0 = never lock)
+    pub max_failures: u32,
+
+    /// How long a lockout lasts, and how long failures are remembered
+    pub duration: Duration,
+
+    /// Failures for one address, from any IPs, before it is slowed down (0 = never)
+    pub slowdown_after: u32,
+
+    /// Wait after each failure on a slowed-down address
+    pub slowdown_interval: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            duration: Duration::from_secs(900),
+            slowdown_after: 20,
+            slowdown_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// One address and client IP with recent failures, as shown to operators
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LockoutInfo {
+    pub email: String,
+    pub ip: IpAddr,
+    pub failures: u32,
+    pub locked: bool,
+    /// Seconds until the lockout ends (0 if not locked)
+    pub remaining_secs: u64,
+}
+
+/// Failures of one address from all IPs
+#[derive(Debug, Clone)]
+struct AddressEntry {
+    failures: u32,
+    last_failure: Instant,
+}
+
+/// Failure counts and lockouts per address and client IP
+pub struct Lockouts {
+    policy: LockoutPolicy,
+    entries: Mutex<HashMap<(String, IpAddr), Entry>>,
+    addresses: Mutex<HashMap<String, AddressEntry>>,
+}
+
+/// Thread-safe lockouts type
+pub type SharedLockouts = Arc<Lockouts>;
+
+/// Create lockout tracking with the given policy
+pub fn create_lockouts(policy: LockoutPolicy) -> SharedLockouts {
+    Arc::new(Lockouts::new(policy))
+}
+
+fn key(email: &str, ip: IpAddr) -> (String, IpAddr) {
+    (normalize(email), ip)
+}
+
+fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+impl Lockouts {
+    /// No failures recorded yet
+    pub fn new(policy: LockoutPolicy) -> Self {
+        Self {
+            policy,
+            entries: Mutex::new(HashMap::new()),
+            addresses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long `ip` has to wait before trying `email` again: locked out, or
+    /// the address is slowed down
+    pub fn check(&self, email: &str, ip: IpAddr) -> Option<Duration> {
+        self.check_at(email, ip, Instant::now())
+    }
+
+    fn check_at(&self, email: &str, ip: IpAddr, now: Instant) -> Option<Duration> {
+        let locked_until = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&key(email, ip))
+            .and_then(|entry| entry.locked_until);
+        let slowed_until = self.slowed_until(&normalize(email), now);
+        let until = locked_until.into_iter().chain(slowed_until).max()?;
+        (until > now).then(|| until - now)
+    }
+
+    fn slowed_until(&self, email: &str, now: Instant) -> Option<Instant> {
+        let addresses = self.addresses.lock().unwrap();
+        let entry = addresses.get(email)?;
+        let recent = now.saturating_duration_since(entry.last_failure) <= self.policy.duration;
+        (self.policy.slowdown_after > 0 && recent && entry.failures >= self.policy.slowdown_after)
+            .then(|| entry.last_failure + self.policy.slowdown_interval)
+    }
+
+    /// Count a failed login from `ip`; returns the lockout length if this one locked it out
+    pub fn record_failure(&self, email: &str, ip: IpAddr) -> Option<Duration> {
+        self.record_failure_at(email, ip, Instant::now())
+    }
+
+    fn record_failure_at(&self, email: &str, ip: IpAddr, now: Instant) -> Option<Duration> {
+        if self.policy.slowdown_after > 0 {
+            let mut addresses = self.addresses.lock().unwrap();
+            let entry = addresses.entry(normalize(email)).or_insert(AddressEntry {
+                failures: 0,
+                last_failure: now,
+            });
+            if now.saturating_duration_since(entry.last_failure) > self.policy.duration {
+                entry.failures = 0;
+            }
+            entry.failures += 1;
+            entry.last_failure = now;
+        }
+        if self.policy.max_failures == 0 {
+            return None;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(key(email, ip)).or_insert(Entry {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+        // Old failures (or an expired lockout) no longer count
+        if now.saturating_duration_since(entry.last_failure) > self.policy.duration
+            || entry.locked_until.is_some_and(|until| until <= now)
+        {
+            entry.failures = 0;
+            entry.locked_until = None;
+        }
+        entry.failures += 1;
+        entry.last_failure = now;
+        if entry.failures >= self.policy.max_failures && entry.locked_until.is_none() {
+            entry.locked_until = Some(now + self.policy.duration);
+            return Some(self.policy.duration);
+        }
+        None
+    }
+
+    /// Successful login from `ip`: forget its earlier failures, and the
+    /// address's slowdown
+    pub fn record_success(&self, email: &str, ip: IpAddr) {
+        self.entries.lock().unwrap().remove(&key(email, ip));
+        self.addresses.lock().unwrap().remove(&normalize(email));
+    }
+
+    /// Clear an address's failures, lockouts and slowdown; false if there were none
+    pub fn clear(&self, email: &str) -> bool {
+        let email = normalize(email);
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|(entry_email, _), _| *entry_email != email);
+        let slowed = self.addresses.lock().unwrap().remove(&email).is_some();
+        entries.len() < before || slowed
+    }
+
+    /// Addresses and IPs with recent failures, locked ones first
+    pub fn list(&self) -> Vec<LockoutInfo> {
+        self.list_at(Instant::now())
+    }
+
+    fn list_at(&self, now: Instant) -> Vec<LockoutInfo> {
+        let entries = self.entries.lock().unwrap();
+        let mut list: Vec<LockoutInfo> = entries
+            .iter()
+            .map(|((email, ip), entry)| {
+                let remaining = entry
+                    .locked_until
+                    .map(|until| until.saturating_duration_since(now))
+                    .unwrap_or_default();
+                LockoutInfo {
+                    email: email.clone(),
+                    ip: *ip,
+                    failures: entry.failures,
+                    locked: !remaining.is_zero(),
+                    remaining_secs: remaining.as_secs(),
+                }
+            })
+            .collect();
+        list.sort_by(|a, b| {
+            b.locked
+                .cmp(&a.locked)
+                .then_with(|| a.email.cmp(&b.email))
+                .then_with(|| a.ip.cmp(&b.ip))
+        });
+        list
+    }
+
+    /// Drop entries that no longer matter
+    pub fn prune(&self) {
+        let now = Instant::now();
+        let duration = self.policy.duration;
+        self.entries.lock().unwrap().retain(|_, entry| {
+            entry.locked_until.is_some_and(|until| until > now)
+                || now.saturating_duration_since(entry.last_failure) <= duration
+        });
+        self.addresses
+            .lock()
+            .unwrap()
+            .retain(|_, entry| now.saturating_duration_since(entry.last_failure) <= duration);
+    }
+
+    /// Error response for an AUTH attempt on a locked address and IP
+    pub fn refusal(command: &str, remaining: Duration) -> Response {
+        Response::err(
+            command,
+            "Too many failed logins, account temporarily locked",
+            codes::ACCOUNT_LOCKED,
+        )
+        .with_data(serde_json::json!({ "retry_after": remaining.as_secs().max(1) }))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last])
+    }
+
+    fn policy() -> LockoutPolicy {
+        LockoutPolicy {
+            max_failures: 3,
+            duration: Duration::from_secs(60),
+            slowdown_after: 0,
+            ..LockoutPolicy::default()
+        }
+    }
+
+    #[test]
+    fn test_locks_after_max_failures() {
+        let lockouts = Lockouts::new(policy());
+        let now = Instant::now();
+        assert_eq!(lockouts.record_failure_at("A@test.com", ip(1), now), None);
+        assert_eq!(lockouts.record_failure_at("a@test.com", ip(1), now), None);
+        assert_eq!(
+            lockouts.record_failure_at("a@test.com", ip(1), now),
+            Some(Duration::from_secs(60))
+        );
+
+        assert_eq!(
+            lockouts.check_at("a@test.com", ip(1), now + Duration::from_secs(10)),
+            Some(Duration::from_secs(50))
+        );
+        assert_eq!(
+            lockouts.check_at("a@test.com", ip(1), now + Duration::from_secs(61)),
+            None
+        );
+        assert_eq!(lockouts.check_at("b@test.com", ip(1), now), None);
+
+        let list = lockouts.list_at(now);
+        assert_eq!(list.len(), 1);
+        assert!(list[0].locked);
+        assert_eq!(list[0].failures, 3);
+    }
+
+    #[test]
+    fn test_success_clear_and_expiry_reset() {
+        let lockouts = Lockouts::new(policy());
+        let now = Instant::now();
+        lockouts.record_failure_at("a@test.com", ip(1), now);
+        lockouts.record_failure_at("a@test.com", ip(1), now);
+        lockouts.record_success("a@test.com", ip(1));
+        assert_eq!(lockouts.record_failure_at("a@test.com", ip(1), now), None);
+
+        // Failures older than the window are forgotten
+        let later = now + Duration::from_secs(120);
+        lockouts.record_failure_at("a@test.com", ip(1), later);
+        assert_eq!(lockouts.list_at(later)[0].failures, 1);
+
+        assert!(lockouts.clear("A@test.com"));
+        assert!(!lockouts.clear("a@test.com"));
+        assert!(lockouts.list().is_empty());
+    }
+
+    #[test]
+    fn test_disabled_policy_never_locks() {
+        let lockouts = Lockouts::new(LockoutPolicy {
+            max_failures: 0,
+            ..policy()
+        });
+        for _ in 0..10 {
+            assert_eq!(lockouts.record_failure("a@test.com", ip(1)), None);
+        }
+        assert_eq!(lockouts.check("a@test.com", ip(1)), None);
+
+        let resp = Lockouts::refusal("AUTH", Duration::from_millis(200));
+        assert_eq!(resp.code, Some(codes::ACCOUNT_LOCKED));
+        assert_eq!(resp.data.unwrap()["retry_after"], 1);
+    }
+
+    #[test]
+    fn test_lockout_is_per_ip() {
+        let lockouts = Lockouts::new(policy());
+        let now = Instant::now();
+        for _ in 0..3 {
+            lockouts.record_failure_at("a@test.com", ip(1), now);
+        }
+        lockouts.record_failure_at("a@test.com", ip(2), now);
+
+        // The attacker's IP is locked out; the owner logging in from elsewhere is not
+        assert!(lockouts.check_at("a@test.com", ip(1), now).is_some());
+        assert_eq!(lockouts.check_at("a@test.com", ip(2), now), None);
+        assert_eq!(lockouts.check_at("a@test.com", ip(3), now), None);
+
+        let list = lockouts.list_at(now);
+        assert_eq!((list[0].ip, list[0].locked), (ip(1), true));
+        assert_eq!((list[1].ip, list[1].failures), (ip(2), 1));
+
+        assert!(lockouts.clear("a@test.com"));
+        assert!(lockouts.list_at(now).is_empty());
+    }
+
+    #[test]
+    fn test_address_slowed_down_across_ips() {
+        let lockouts = Lockouts::new(LockoutPolicy {
+            slowdown_after: 4,
+            slowdown_interval: Duration::from_secs(5),
+            ..policy()
+        });
+        let now = Instant::now();
+        // One failure each from many IPs: no IP gets locked out
+        for last in 1..=3 {
+            assert_eq!(
+                lockouts.record_failure_at("a@test.com", ip(last), now),
+                None
+            );
+        }
+        assert_eq!(lockouts.check_at("a@test.com", ip(9), now), None);
+
+        lockouts.record_failure_at("a@test.com", ip(4), now);
+        assert!(lockouts.list_at(now).iter().all(|info| !info.locked));
+
+        // Every IP now waits after each failure, but is never locked out
+        assert_eq!(
+            lockouts.check_at("A@test.com", ip(9), now + Duration::from_secs(1)),
+            Some(Duration::from_secs(4))
+        );
+        assert_eq!(
+            lockouts.check_at("a@test.com", ip(9), now + Duration::from_secs(5)),
+            None
+        );
+        assert_eq!(lockouts.check_at("b@test.com", ip(9), now), None);
+
+        let later = now + Duration::from_secs(5);
+        lockouts.record_failure_at("a@test.com", ip(5), later);
+        assert!(lockouts.check_at("a@test.com", ip(9), later).is_some());
+
+        // Forgotten once failures stop for the window, or on a successful login
+        assert_eq!(
+            lockouts.check_at("a@test.com", ip(9), later + Duration::from_secs(61)),
+            None
+        );
+        lockouts.record_success("a@test.com", ip(9));
+        assert_eq!(lockouts.check_at("a@test.com", ip(5), later), None);
+    }
+}
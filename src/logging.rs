@@ -0,0 +1,228 @@
+//! Structured logging helpers
+//!
+//! Every connection runs inside a `conn` span (`conn_id`, `remote`) and every
+//! command inside a `request` span (`command`, `request_id`, `session_id`,
+//! `outcome`, `duration_ms`), so log lines can be correlated in either the
+//! text or the JSON output format.
+//!
+//! Nothing secret reaches the log: session tokens are replaced by a short
+//! one-way `session_id`, and request payloads pass through [`redact_request`]
+//! which blanks tokens, secrets, pairing codes and message contents.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::field::Empty;
+use tracing::Span;
+
+/// Replacement for redacted values
+pub const REDACTED: &str = "<redacted>";
+
+/// Payload fields never written to the log
+const SENSITIVE_KEYS: &[&str] = &[
+    // credentials
+    "session_token",
+    "token",
+    "password",
+    "secret",
+    "code",
+    // message contents
+    "subject",
+    "body",
+    "body_text",
+    "body_html",
+    "html",
+    "text",
+    "content",
+    "snippet",
+];
+
+/// Log output format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[serde(rename = "text")]
+    Text,
+
+    /// One JSON object per line (for log shippers)
+    #[serde(rename = "json")]
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "text" | "pretty" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format: {other}")),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        })
+    }
+}
+
+/// Stable, non-reversible identifier for a session token
+pub fn session_id(token: &str) -> String {
+    if token.is_empty() {
+        return "-".to_string();
+    }
+    hex::encode(&Sha256::digest(token.as_bytes())[..6])
+}
+
+fn is_sensitive(key: &str) -> bool {
+    let key = key.to_lowercase();
+    SENSITIVE_KEYS.contains(&key.as_str()) || key.ends_with("_token") || key.ends_with("_secret")
+}
+
+/// Blank sensitive fields of a JSON value, recursively
+pub fn redact_value(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                if is_sensitive(key) && !field.is_null() {
+                    *field = Value::String(REDACTED.to_string());
+                } else {
+                    redact_value(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+/// Raw request text, safe to log
+pub fn redact_request(text: &str) -> String {
+    match serde_json::from_str::<Value>(text) {
+        Ok(mut value) => {
+            redact_value(&mut value);
+            value.to_string()
+        }
+        Err(_) => format!("<{} bytes, not JSON>", text.len()),
+    }
+}
+
+/// Span covering one WebTransport connection
+///
+/// `remote` is recorded once the session request has arrived.
+pub fn connection_span(conn_id: u64) -> Span {
+    tracing::info_span!("conn", conn_id, remote = Empty)
+}
+
+/// Record the remote address on the current connection span
+pub fn record_remote(remote: SocketAddr) {
+    Span::current().record("remote", tracing::field::display(remote));
+}
+
+/// Span covering one command; `outcome` and `duration_ms` are filled by [`finish_request`]
+pub fn request_span(request: &str, request_id: &str) -> Span {
+    let parsed = serde_json::from_str::<Value>(request).ok();
+    let command = parsed
+        .as_ref()
+        .and_then(|v| v.get("cmd").and_then(Value::as_str))
+        .map(str::to_uppercase)
+        .unwrap_or_else(|| "-".to_string());
+    let token = parsed
+        .as_ref()
+        .and_then(|v| v.get("data"))
+        .and_then(|d| d.get("session_token").or_else(|| d.get("token")))
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        command = %command,
+        request_id,
+        session_id = %session_id(token),
+        outcome = Empty,
+        duration_ms = Empty,
+    )
+}
+
+/// `ok`, or `err:<code>` for an error response
+pub fn outcome(response: &str) -> String {
+    match serde_json::from_str::<Value>(response) {
+        Ok(v) if v.get("status").and_then(Value::as_str) == Some("ERR") => {
+            format!("err:{}", v.get("code").and_then(Value::as_u64).unwrap_or(0))
+        }
+        Ok(_) => "ok".to_string(),
+        Err(_) => "invalid".to_string(),
+    }
+}
+
+/// Record the result of a command on its span and log it
+pub fn finish_request(span: &Span, response: &str, elapsed: Duration) {
+    let outcome = outcome(response);
+    span.record("outcome", outcome.as_str());
+    span.record("duration_ms", elapsed.as_secs_f64() * 1000.0);
+    span.in_scope(|| tracing::debug!("request completed"));
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_format_parse() {
+        assert_eq!("JSON".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert_eq!("text".parse::<LogFormat>(), Ok(LogFormat::Text));
+        assert!("xml".parse::<LogFormat>().is_err());
+        assert_eq!(LogFormat::Json.to_string(), "json");
+    }
+
+    #[test]
+    fn test_session_id_is_short_and_stable() {
+        let id = session_id("WMTP-secret-token");
+        assert_eq!(id.len(), 12);
+        assert_eq!(id, session_id("WMTP-secret-token"));
+        assert_ne!(id, session_id("WMTP-other-token"));
+        assert!(!id.contains("secret"));
+        assert_eq!(session_id(""), "-");
+    }
+
+    #[test]
+    fn test_redact_request() {
+        let raw = r#"{"cmd":"MSG_SEND","data":{"session_token":"WMTP-abc","to":"a@b.c","subject":"hi","body":"private","attachments":[{"upload_token":"x","name":"f.txt"}]}}"#;
+        let redacted = redact_request(raw);
+        assert!(!redacted.contains("WMTP-abc"));
+        assert!(!redacted.contains("private"));
+        assert!(!redacted.contains("\"hi\""));
+        assert!(!redacted.contains("\"x\""));
+        assert!(redacted.contains("a@b.c"));
+        assert!(redacted.contains("f.txt"));
+        assert!(redacted.contains("MSG_SEND"));
+
+        assert_eq!(redact_request("WMTP-abc garbage"), "<16 bytes, not JSON>");
+    }
+
+    #[test]
+    fn test_outcome() {
+        assert_eq!(outcome(r#"{"status":"OK"}"#), "ok");
+        assert_eq!(outcome(r#"{"status":"ERR","code":1002}"#), "err:1002");
+        assert_eq!(outcome("nope"), "invalid");
+    }
+
+    #[test]
+    fn test_request_span_without_subscriber() {
+        // No subscriber is installed; building and finishing the span must not panic
+        let span = request_span(r#"{"cmd":"ping","data":{"session_token":"WMTP-abc"}}"#, "1-1");
+        finish_request(&span, r#"{"status":"OK"}"#, Duration::from_millis(3));
+    }
+}
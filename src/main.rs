@@ -5,25 +5,97 @@
 
 use anyhow::Result;
 use tracing::info;
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter};
+use wmtp_server::audit;
+use wmtp_server::config::{CliArgs, Config};
+use wmtp_server::logging::LogFormat;
+use wmtp_server::reload::RuntimeHooks;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Load environment variables from .env file
     dotenvy::dotenv().ok();
 
-    // Initialize tracing/logging
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("wmtp_server=debug".parse().unwrap()),
-        )
+    // Layered config: defaults < file < env < CLI flags
+    let cli = CliArgs::parse(std::env::args().skip(1))?;
+    if cli.help {
+        print!("{}", CliArgs::usage());
+        return Ok(());
+    }
+    let config = Config::load(&cli)?;
+
+    if cli.check_config {
+        print!("{}", config.to_redacted_toml());
+        if let Err(e) = config.validate() {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        println!("# configuration OK");
+        return Ok(());
+    }
+
+    if cli.verify_audit {
+        let (Some(path), Some(key_file)) = (&config.audit_log, &config.audit_key_file) else {
+            eprintln!("--verify-audit needs both audit_log and audit_key_file configured");
+            std::process::exit(1);
+        };
+        match audit::verify(path, key_file) {
+            Ok(report) => {
+                println!(
+                    "{}: {} record(s) OK, head seq {} hash {}",
+                    path.display(),
+                    report.records,
+                    report.head.seq,
+                    report.head.hash
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("{}: {e}", path.display());
+                std::process::exit(1);
+            }
+        }
+    }
+
+    config.validate()?;
+
+    // Initialize tracing/logging; RUST_LOG wins at startup, `log_level` is reloadable
+    let directives = std::env::var("RUST_LOG")
+        .ok()
+        .filter(|d| !d.trim().is_empty())
+        .unwrap_or_else(|| config.log_level.clone());
+    let (filter, filter_handle) = reload::Layer::new(EnvFilter::try_new(directives)?);
+    let (text, json) = match config.log_format {
+        LogFormat::Text => (Some(fmt::layer()), None),
+        LogFormat::Json => (
+            None,
+            Some(fmt::layer().json().flatten_event(true).with_current_span(true).with_span_list(true)),
+        ),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(text)
+        .with(json)
         .init();
+    config.warn_insecure_defaults();
+
+    // SIGHUP re-reads the same file/env/flags the server started with
+    let reload_cli = cli.clone();
+    let hooks = RuntimeHooks {
+        config_source: Some(Box::new(move || Config::load(&reload_cli))),
+        set_log_level: Some(Box::new(move |level| {
+            let filter = EnvFilter::try_new(level).map_err(|e| e.to_string())?;
+            filter_handle.reload(filter).map_err(|e| e.to_string())
+        })),
+    };
 
-    // Print banner
-    print_banner();
+    // Print banner (not in JSON logs, where it is just noise)
+    if config.log_format == LogFormat::Text {
+        print_banner();
+    }
 
     // Run the server
-    wmtp_server::run_server().await?;
+    wmtp_server::run_server_with_hooks(config, hooks).await?;
 
     Ok(())
 }
@@ -0,0 +1,260 @@
+//! Maintenance mode
+//!
+//! While the server is in maintenance mode it stays up but refuses new
+//! logins and commands that write (see [`BLOCKED_COMMANDS`]) with
+//! `SERVICE_UNAVAILABLE`, telling clients why and until when. Reads, `PING`
+//! and heartbeats keep working. The mode is switched by an operator
+//! (`wmtpctl maintenance on|off`) or by reloading a configuration that
+//! changes `maintenance`; connected clients get a `MAINTENANCE` push notice
+//! each time it changes.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use tokio::sync::watch;
+
+use crate::commands::Response;
+use crate::config::Config;
+use crate::error::codes;
+
+/// Commands refused during maintenance: new logins and writes
+pub const BLOCKED_COMMANDS: &[&str] = &[
+    "AUTH",
+    "LINK_APPROVE",
+    "MSG_SEND",
+    "MSG_SEND_DRAFT",
+    "MSG_MOVE",
+    "MSG_COPY",
+    "MSG_DELETE",
+    "MSG_EXPUNGE",
+    "MSG_UNDELETE",
+    "MSG_FLAG_SET",
+    "MSG_FLAG_CLEAR",
+    "MSG_BULK_ACTION",
+    "MB_CREATE",
+    "MB_PURGE_TRASH",
+    "PROFILE_SET",
+    "ATTACH_UPLOAD_INIT",
+    "SEND",
+    "DELETE",
+];
+
+/// Reason given when none is configured
+pub const DEFAULT_REASON: &str = "Scheduled maintenance";
+
+/// An active maintenance window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    /// Shown to clients
+    pub reason: String,
+
+    /// When the operator expects the window to end (if known)
+    pub eta: Option<DateTime<Utc>>,
+
+    /// When the window started
+    pub since: DateTime<Utc>,
+}
+
+impl MaintenanceWindow {
+    /// JSON form used in admin output and client notices
+    pub fn to_json(&self) -> Value {
+        json!({
+            "reason": self.reason,
+            "eta": self.eta.map(|eta| eta.to_rfc3339()),
+            "since": self.since.to_rfc3339(),
+        })
+    }
+
+    /// Seconds until the ETA (none if unknown or already passed)
+    pub fn retry_after(&self) -> Option<u64> {
+        let left = self.eta? - Utc::now();
+        u64::try_from(left.num_seconds())
+            .ok()
+            .filter(|secs| *secs > 0)
+    }
+}
+
+/// Maintenance state of the running server
+pub struct Maintenance {
+    window: watch::Sender<Option<MaintenanceWindow>>,
+}
+
+/// Thread-safe maintenance state type
+pub type SharedMaintenance = Arc<Maintenance>;
+
+/// Create maintenance state (off)
+pub fn create_maintenance() -> SharedMaintenance {
+    Arc::new(Maintenance::default())
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self {
+            window: watch::Sender::new(None),
+        }
+    }
+}
+
+impl Maintenance {
+    /// Enter maintenance (or update the reason and ETA of the current window)
+    pub fn enable(&self, reason: &str, eta: Option<DateTime<Utc>>) -> MaintenanceWindow {
+        let mut new = None;
+        self.window.send_modify(|window| {
+            let since = window.as_ref().map_or_else(Utc::now, |w| w.since);
+            let updated = MaintenanceWindow {
+                reason: reason.to_string(),
+                eta,
+                since,
+            };
+            *window = Some(updated.clone());
+            new = Some(updated);
+        });
+        new.expect("window was just set")
+    }
+
+    /// Leave maintenance; false if it was not on
+    pub fn disable(&self) -> bool {
+        self.window
+            .send_if_modified(|window| window.take().is_some())
+    }
+
+    /// The current window, if in maintenance
+    pub fn current(&self) -> Option<MaintenanceWindow> {
+        self.window.borrow().clone()
+    }
+
+    /// Whether the server is in maintenance
+    pub fn is_active(&self) -> bool {
+        self.window.borrow().is_some()
+    }
+
+    /// Get notified whenever the mode or window changes
+    pub fn subscribe(&self) -> watch::Receiver<Option<MaintenanceWindow>> {
+        self.window.subscribe()
+    }
+
+    /// Error response if `command` is not allowed right now
+    pub fn check(&self, command: &str) -> Option<Response> {
+        if !BLOCKED_COMMANDS.contains(&command) {
+            return None;
+        }
+        self.window
+            .borrow()
+            .as_ref()
+            .map(|window| Self::refusal(command, window))
+    }
+
+    /// Error response for a command refused during `window`
+    pub fn refusal(command: &str, window: &MaintenanceWindow) -> Response {
+        let mut data = window.to_json();
+        data["maintenance"] = Value::Bool(true);
+        if let Some(secs) = window.retry_after() {
+            data["retry_after"] = json!(secs);
+        }
+        let msg = match window.eta {
+            Some(eta) => format!("{} (expected back by {})", window.reason, eta.to_rfc3339()),
+            None => window.reason.clone(),
+        };
+        Response::err(command, &msg, codes::SERVICE_UNAVAILABLE).with_data(data)
+    }
+
+    /// Follow the `maintenance` settings of a (re)loaded configuration
+    ///
+    /// Only called when those settings change, so an operator's toggle holds
+    /// until the configuration says otherwise.
+    pub fn apply_config(&self, config: &Config) {
+        if config.maintenance {
+            let reason = if config.maintenance_reason.is_empty() {
+                DEFAULT_REASON
+            } else {
+                &config.maintenance_reason
+            };
+            self.enable(reason, config.maintenance_eta());
+        } else {
+            self.disable();
+        }
+    }
+}
+
+/// Whether two configurations differ in their maintenance settings
+pub fn config_changed(old: &Config, new: &Config) -> bool {
+    old.maintenance != new.maintenance
+        || old.maintenance_reason != new.maintenance_reason
+        || old.maintenance_eta != new.maintenance_eta
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enable_update_disable() {
+        let maintenance = Maintenance::default();
+        let mut changes = maintenance.subscribe();
+        assert!(!maintenance.is_active());
+        assert!(!maintenance.disable());
+        assert!(!changes.has_changed().unwrap());
+
+        let first = maintenance.enable("database upgrade", None);
+        let updated = maintenance.enable("database upgrade, almost done", Some(Utc::now()));
+        assert_eq!(first.since, updated.since);
+        assert!(maintenance.is_active());
+        assert!(changes.has_changed().unwrap());
+        assert_eq!(
+            changes.borrow_and_update().as_ref().unwrap().reason,
+            "database upgrade, almost done"
+        );
+        assert!(updated.to_json()["eta"].is_string());
+
+        assert!(maintenance.disable());
+        assert!(changes.has_changed().unwrap());
+        assert_eq!(maintenance.current(), None);
+    }
+
+    #[test]
+    fn test_blocks_logins_and_writes_only() {
+        let maintenance = Maintenance::default();
+        assert!(maintenance.check("MSG_SEND").is_none());
+
+        let eta = Utc::now() + chrono::Duration::minutes(10);
+        maintenance.enable("storage migration", Some(eta));
+        for command in ["AUTH", "MSG_SEND", "MSG_MOVE", "MB_CREATE"] {
+            let resp = maintenance.check(command).expect(command);
+            assert_eq!(resp.code, Some(codes::SERVICE_UNAVAILABLE));
+            assert!(resp.msg.unwrap().starts_with("storage migration"));
+            let data = resp.data.unwrap();
+            assert_eq!(data["maintenance"], true);
+            assert!(data["retry_after"].as_u64().unwrap() > 500);
+        }
+        for command in ["PING", "MSG_LIST", "MSG_GET", "MB_LIST", "INIT", "LOGOUT"] {
+            assert!(maintenance.check(command).is_none(), "{command}");
+        }
+    }
+
+    #[test]
+    fn test_follows_config() {
+        let maintenance = Maintenance::default();
+        let config = Config {
+            maintenance: true,
+            maintenance_eta: "2030-01-01T00:00:00Z".to_string(),
+            ..Config::default()
+        };
+        assert!(config_changed(&Config::default(), &config));
+
+        maintenance.apply_config(&config);
+        let window = maintenance.current().unwrap();
+        assert_eq!(window.reason, DEFAULT_REASON);
+        assert_eq!(
+            window.eta.unwrap().to_rfc3339(),
+            "2030-01-01T00:00:00+00:00"
+        );
+
+        maintenance.apply_config(&Config::default());
+        assert!(!maintenance.is_active());
+    }
+}
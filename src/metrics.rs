@@ -0,0 +1,458 @@
+//! Prometheus metrics
+//!
+//! Counters are updated on the hot path with atomics or short locks and
+//! rendered in the Prometheus text format on scrape. Session gauges are read
+//! from the `SessionManager` at scrape time.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::session::SessionManager;
+
+/// Distinct command labels tracked; anything beyond is counted as `OTHER`
+pub const MAX_COMMAND_LABELS: usize = 128;
+
+/// Command latency buckets (seconds)
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Streams-per-connection buckets
+const STREAM_BUCKETS: &[f64] = &[1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0];
+
+/// A cumulative Prometheus histogram
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            let _ = writeln!(out, "{name}_bucket{{{labels}{sep}le=\"{bound}\"}} {count}");
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{labels}{sep}le=\"+Inf\"}} {}",
+            self.count
+        );
+        let braces = |s: &str| {
+            if s.is_empty() {
+                String::new()
+            } else {
+                format!("{{{s}}}")
+            }
+        };
+        let _ = writeln!(out, "{name}_sum{} {}", braces(labels), self.sum);
+        let _ = writeln!(out, "{name}_count{} {}", braces(labels), self.count);
+    }
+}
+
+#[derive(Default)]
+struct CommandStats {
+    requests: HashMap<String, u64>,
+    errors: HashMap<(String, u32), u64>,
+    latency: HashMap<String, Histogram>,
+}
+
+/// Requests seen for one command label
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommandCount {
+    pub command: String,
+    pub requests: u64,
+    /// Requests answered with an error
+    pub errors: u64,
+}
+
+/// Server-wide metrics
+pub struct Metrics {
+    connections_open: AtomicI64,
+    streams_open: AtomicI64,
+    heartbeat_write_failures: AtomicU64,
+    outbound_frames_dropped: AtomicU64,
+    slow_consumer_disconnects: AtomicU64,
+    attachment_bytes_received: AtomicU64,
+    streams_per_connection: Mutex<Histogram>,
+    commands: Mutex<CommandStats>,
+}
+
+/// Thread-safe metrics type
+pub type SharedMetrics = Arc<Metrics>;
+
+/// Create an empty metrics registry
+pub fn create_metrics() -> SharedMetrics {
+    Arc::new(Metrics::default())
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            connections_open: AtomicI64::new(0),
+            streams_open: AtomicI64::new(0),
+            heartbeat_write_failures: AtomicU64::new(0),
+            outbound_frames_dropped: AtomicU64::new(0),
+            slow_consumer_disconnects: AtomicU64::new(0),
+            attachment_bytes_received: AtomicU64::new(0),
+            streams_per_connection: Mutex::new(Histogram::new(STREAM_BUCKETS)),
+            commands: Mutex::new(CommandStats::default()),
+        }
+    }
+}
+
+/// Command name of a request, for use as a metric label
+///
+/// Requests the server did not recognise are all labelled `UNKNOWN` so
+/// clients cannot create arbitrary series.
+pub fn command_label(request: &str, response: &str) -> String {
+    let unknown = serde_json::from_str::<Value>(response)
+        .is_ok_and(|v| v.get("cmd").and_then(Value::as_str) == Some("UNKNOWN"));
+    if unknown {
+        return "UNKNOWN".to_string();
+    }
+    serde_json::from_str::<Value>(request)
+        .ok()
+        .and_then(|v| v.get("cmd").and_then(Value::as_str).map(str::to_uppercase))
+        .filter(|c| {
+            !c.is_empty() && c.len() <= 32 && c.bytes().all(|b| b.is_ascii_uppercase() || b == b'_')
+        })
+        .unwrap_or_else(|| "INVALID".to_string())
+}
+
+/// Error code of a response (`None` if it is not an error)
+pub fn response_error_code(response: &str) -> Option<u32> {
+    let v: Value = serde_json::from_str(response).ok()?;
+    if v.get("status").and_then(Value::as_str) != Some("ERR") {
+        return None;
+    }
+    Some(v.get("code").and_then(Value::as_u64).unwrap_or(0) as u32)
+}
+
+impl Metrics {
+    /// A connection was accepted
+    pub fn connection_opened(&self) {
+        self.connections_open.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A connection ended after opening `streams` streams
+    pub fn connection_closed(&self, streams: u64) {
+        self.connections_open.fetch_sub(1, Ordering::Relaxed);
+        self.streams_per_connection
+            .lock()
+            .unwrap()
+            .observe(streams as f64);
+    }
+
+    /// A stream was opened
+    pub fn stream_opened(&self) {
+        self.streams_open.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A stream finished
+    pub fn stream_closed(&self) {
+        self.streams_open.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Writing a heartbeat to a control stream failed
+    pub fn heartbeat_write_failed(&self) {
+        self.heartbeat_write_failures
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A connection ended; `dropped` frames were lost to a full outbound queue
+    pub fn outbound_frames_dropped(&self, dropped: u64) {
+        self.outbound_frames_dropped
+            .fetch_add(dropped, Ordering::Relaxed);
+    }
+
+    /// A client was disconnected for not reading its outbound queue
+    pub fn slow_consumer_disconnected(&self) {
+        self.slow_consumer_disconnects
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Attachment payload bytes received
+    pub fn attachment_bytes(&self, n: u64) {
+        self.attachment_bytes_received
+            .fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Record one command: its label, response and how long it took
+    pub fn observe_command(&self, command: &str, response: &str, elapsed: Duration) {
+        let error = response_error_code(response);
+        let mut stats = self.commands.lock().unwrap();
+
+        let label =
+            if stats.requests.contains_key(command) || stats.requests.len() < MAX_COMMAND_LABELS {
+                command.to_string()
+            } else {
+                "OTHER".to_string()
+            };
+        *stats.requests.entry(label.clone()).or_default() += 1;
+        stats
+            .latency
+            .entry(label.clone())
+            .or_insert_with(|| Histogram::new(LATENCY_BUCKETS))
+            .observe(elapsed.as_secs_f64());
+        if let Some(code) = error {
+            *stats.errors.entry((label, code)).or_default() += 1;
+        }
+    }
+
+    /// Open connections
+    pub fn connections_open(&self) -> i64 {
+        self.connections_open.load(Ordering::Relaxed)
+    }
+
+    /// Open streams across all connections
+    pub fn streams_open(&self) -> i64 {
+        self.streams_open.load(Ordering::Relaxed)
+    }
+
+    /// Requests and errors per command label, sorted by label
+    pub fn command_counts(&self) -> Vec<CommandCount> {
+        let stats = self.commands.lock().unwrap();
+        sorted(&stats.requests)
+            .into_iter()
+            .map(|(command, requests)| CommandCount {
+                command: command.clone(),
+                requests: *requests,
+                errors: stats
+                    .errors
+                    .iter()
+                    .filter(|((label, _), _)| label == command)
+                    .map(|(_, n)| n)
+                    .sum(),
+            })
+            .collect()
+    }
+
+    /// Render all metrics in the Prometheus text format
+    pub fn render(&self, sessions: &SessionManager) -> String {
+        let mut out = String::new();
+
+        gauge(
+            &mut out,
+            "wmtp_sessions_active",
+            "Sessions in the session store",
+            sessions.active_count() as i64,
+        );
+        gauge(
+            &mut out,
+            "wmtp_sessions_authenticated",
+            "Authenticated sessions",
+            sessions.authenticated_count() as i64,
+        );
+        gauge(
+            &mut out,
+            "wmtp_connections_open",
+            "Open WebTransport connections",
+            self.connections_open.load(Ordering::Relaxed),
+        );
+        gauge(
+            &mut out,
+            "wmtp_streams_open",
+            "Open streams across all connections",
+            self.streams_open.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "wmtp_heartbeat_write_failures_total",
+            "Heartbeats that could not be written",
+            self.heartbeat_write_failures.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "wmtp_outbound_frames_dropped_total",
+            "Frames dropped because a connection's outbound queue was full",
+            self.outbound_frames_dropped.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "wmtp_slow_consumer_disconnects_total",
+            "Connections closed because the client stopped reading",
+            self.slow_consumer_disconnects.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "wmtp_attachment_bytes_received_total",
+            "Attachment bytes received",
+            self.attachment_bytes_received.load(Ordering::Relaxed),
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP wmtp_streams_per_connection Streams opened per closed connection"
+        );
+        let _ = writeln!(out, "# TYPE wmtp_streams_per_connection histogram");
+        self.streams_per_connection.lock().unwrap().render(
+            &mut out,
+            "wmtp_streams_per_connection",
+            "",
+        );
+
+        let stats = self.commands.lock().unwrap();
+        let _ = writeln!(out, "# HELP wmtp_commands_total Commands processed");
+        let _ = writeln!(out, "# TYPE wmtp_commands_total counter");
+        for (command, n) in sorted(&stats.requests) {
+            let _ = writeln!(out, "wmtp_commands_total{{command=\"{command}\"}} {n}");
+        }
+        let _ = writeln!(
+            out,
+            "# HELP wmtp_command_errors_total Commands answered with an error, by code"
+        );
+        let _ = writeln!(out, "# TYPE wmtp_command_errors_total counter");
+        for ((command, code), n) in sorted(&stats.errors) {
+            let _ = writeln!(
+                out,
+                "wmtp_command_errors_total{{command=\"{command}\",code=\"{code}\"}} {n}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP wmtp_command_duration_seconds Command processing time"
+        );
+        let _ = writeln!(out, "# TYPE wmtp_command_duration_seconds histogram");
+        for (command, histogram) in sorted(&stats.latency) {
+            histogram.render(
+                &mut out,
+                "wmtp_command_duration_seconds",
+                &format!("command=\"{command}\""),
+            );
+        }
+
+        out
+    }
+}
+
+fn sorted<K: Ord, V>(map: &HashMap<K, V>) -> Vec<(&K, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: i64) {
+    let _ = writeln!(
+        out,
+        "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}"
+    );
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(
+        out,
+        "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}"
+    );
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::{create_session_store, WmtpSession};
+
+    #[test]
+    fn test_labels_and_error_codes() {
+        let ok = r#"{"status":"OK","cmd":"PONG"}"#;
+        assert_eq!(command_label(r#"{"cmd":"ping"}"#, ok), "PING");
+        assert_eq!(command_label("not json", ok), "INVALID");
+        assert_eq!(command_label(r#"{"cmd":"x\" } evil"}"#, ok), "INVALID");
+        assert_eq!(
+            command_label(r#"{"cmd":"NOPE"}"#, r#"{"status":"ERR","cmd":"UNKNOWN"}"#),
+            "UNKNOWN"
+        );
+
+        assert_eq!(
+            response_error_code(r#"{"status":"ERR","code":2002}"#),
+            Some(2002)
+        );
+        assert_eq!(response_error_code(r#"{"status":"OK"}"#), None);
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        let manager = SessionManager::new(create_session_store(), 3600);
+        manager.insert(WmtpSession::new_authenticated("t".to_string(), "u@test.com".to_string()));
+
+        metrics.connection_opened();
+        metrics.stream_opened();
+        metrics.heartbeat_write_failed();
+        metrics.slow_consumer_disconnected();
+        metrics.attachment_bytes(1024);
+        metrics.observe_command("PING", r#"{"status":"OK"}"#, Duration::from_millis(2));
+        metrics.observe_command(
+            "MB_LIST",
+            r#"{"status":"ERR","code":2002}"#,
+            Duration::from_millis(20),
+        );
+
+        let out = metrics.render(&manager);
+        assert!(out.contains("wmtp_sessions_authenticated 1\n"));
+        assert!(out.contains("wmtp_connections_open 1\n"));
+        assert!(out.contains("wmtp_attachment_bytes_received_total 1024\n"));
+        assert!(out.contains("wmtp_heartbeat_write_failures_total 1\n"));
+        assert!(out.contains("wmtp_slow_consumer_disconnects_total 1\n"));
+        assert!(out.contains("wmtp_commands_total{command=\"PING\"} 1\n"));
+        assert!(out.contains("wmtp_command_errors_total{command=\"MB_LIST\",code=\"2002\"} 1\n"));
+        assert!(
+            out.contains("wmtp_command_duration_seconds_bucket{command=\"PING\",le=\"0.005\"} 1\n")
+        );
+        assert!(
+            out.contains("wmtp_command_duration_seconds_bucket{command=\"PING\",le=\"0.001\"} 0\n")
+        );
+
+        let counts = metrics.command_counts();
+        assert_eq!(counts.len(), 2);
+        assert_eq!((counts[0].command.as_str(), counts[0].requests, counts[0].errors), ("MB_LIST", 1, 1));
+        assert_eq!((counts[1].command.as_str(), counts[1].errors), ("PING", 0));
+
+        metrics.connection_closed(3);
+        let out = metrics.render(&manager);
+        assert!(out.contains("wmtp_connections_open 0\n"));
+        assert!(out.contains("wmtp_streams_per_connection_bucket{le=\"4\"} 1\n"));
+        assert!(out.contains("wmtp_streams_per_connection_count 1\n"));
+    }
+
+    #[test]
+    fn test_command_label_cap() {
+        let metrics = Metrics::default();
+        for i in 0..MAX_COMMAND_LABELS + 5 {
+            metrics.observe_command(&format!("CMD_{i}"), "{}", Duration::ZERO);
+        }
+        let stats = metrics.commands.lock().unwrap();
+        assert_eq!(stats.requests.len(), MAX_COMMAND_LABELS + 1);
+        assert_eq!(stats.requests["OTHER"], 5);
+    }
+}
@@ -0,0 +1,441 @@
+//! Per-connection outbound queue
+//!
+//! Everything written to a connection's control stream goes through one
+//! bounded queue drained by a single writer task, so a slow client never
+//! blocks command processing and pushes from other connections always have
+//! somewhere to go. The queue has two lanes, drained strictly in order:
+//!
+//! - **control**: command responses and heartbeats
+//! - **bulk**: frames pushed by the server (events, notices)
+//!
+//! A close request overtakes both lanes. Responses wait for room in the
+//! control lane (which stops the connection from reading more commands);
+//! heartbeats and pushes are dropped when their lane is full. A connection
+//! with a lane that stays full for `slow_consumer_timeout` is a slow consumer
+//! and is disconnected with `close_codes::SLOW_CONSUMER`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::error::{TryRecvError, TrySendError};
+use tokio::sync::{mpsc, Notify};
+
+use crate::connection::{ConnectionStats, PushFrame};
+
+/// Queue sizing and the slow-consumer policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutboundConfig {
+    /// Frames buffered per lane
+    pub capacity: usize,
+
+    /// Disconnect once the queue has been full for this long
+    pub slow_consumer_timeout: Duration,
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 256,
+            slow_consumer_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Lane a frame is queued on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    Control,
+    Bulk,
+}
+
+impl Lane {
+    fn index(self) -> usize {
+        match self {
+            Lane::Control => 0,
+            Lane::Bulk => 1,
+        }
+    }
+}
+
+/// Why a frame could not be queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The lane is full (the frame was dropped)
+    Full,
+
+    /// The writer is gone (the connection is closing)
+    Closed,
+
+    /// The client has not read anything for `slow_consumer_timeout`
+    SlowConsumer,
+}
+
+struct Shared {
+    // Per lane: set when an enqueue first found the lane full, cleared by the
+    // next success on that same lane
+    full_since: Mutex<[Option<Instant>; 2]>,
+    // `Some(final frame)` once a close was requested
+    close: Mutex<Option<Option<String>>>,
+    close_requested: Notify,
+    dropped: AtomicU64,
+}
+
+impl Shared {
+    fn mark_full(&self, lane: Lane, now: Instant) {
+        self.full_since.lock().unwrap()[lane.index()].get_or_insert(now);
+    }
+
+    fn mark_drained(&self, lane: Lane) {
+        self.full_since.lock().unwrap()[lane.index()] = None;
+    }
+}
+
+/// Producer side: cheap to clone, one per place that writes to the connection
+#[derive(Clone)]
+pub struct OutboundSender {
+    control: mpsc::Sender<String>,
+    bulk: mpsc::Sender<String>,
+    shared: Arc<Shared>,
+    config: OutboundConfig,
+}
+
+/// Consumer side, owned by the writer task
+pub struct OutboundQueue {
+    control: mpsc::Receiver<String>,
+    bulk: mpsc::Receiver<String>,
+    shared: Arc<Shared>,
+}
+
+/// Create the queue of one connection
+pub fn channel(config: OutboundConfig) -> (OutboundSender, OutboundQueue) {
+    let capacity = config.capacity.max(1);
+    let (control_tx, control_rx) = mpsc::channel(capacity);
+    let (bulk_tx, bulk_rx) = mpsc::channel(capacity);
+    let shared = Arc::new(Shared {
+        full_since: Mutex::new([None; 2]),
+        close: Mutex::new(None),
+        close_requested: Notify::new(),
+        dropped: AtomicU64::new(0),
+    });
+    let sender = OutboundSender {
+        control: control_tx,
+        bulk: bulk_tx,
+        shared: shared.clone(),
+        config,
+    };
+    let queue = OutboundQueue {
+        control: control_rx,
+        bulk: bulk_rx,
+        shared,
+    };
+    (sender, queue)
+}
+
+impl OutboundSender {
+    /// Queue a frame without waiting; a full lane drops the frame
+    pub fn try_send(&self, lane: Lane, text: String) -> Result<(), SendError> {
+        let tx = match lane {
+            Lane::Control => &self.control,
+            Lane::Bulk => &self.bulk,
+        };
+        match tx.try_send(text) {
+            Ok(()) => {
+                self.shared.mark_drained(lane);
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => {
+                self.shared.mark_full(lane, Instant::now());
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                Err(SendError::Full)
+            }
+            Err(TrySendError::Closed(_)) => Err(SendError::Closed),
+        }
+    }
+
+    /// Queue a command response, waiting up to `slow_consumer_timeout` for room
+    pub async fn send_response(&self, text: String) -> Result<(), SendError> {
+        let permit = match self.control.try_reserve() {
+            Ok(permit) => permit,
+            Err(TrySendError::Closed(_)) => return Err(SendError::Closed),
+            Err(TrySendError::Full(_)) => {
+                self.shared.mark_full(Lane::Control, Instant::now());
+                match tokio::time::timeout(
+                    self.config.slow_consumer_timeout,
+                    self.control.reserve(),
+                )
+                .await
+                {
+                    Ok(Ok(permit)) => permit,
+                    Ok(Err(_)) => return Err(SendError::Closed),
+                    Err(_) => return Err(SendError::SlowConsumer),
+                }
+            }
+        };
+        permit.send(text);
+        self.shared.mark_drained(Lane::Control);
+        Ok(())
+    }
+
+    /// Queue a pushed frame (`Frame` on the bulk lane, `Close` ahead of everything)
+    pub fn push(&self, frame: PushFrame) -> bool {
+        match frame {
+            PushFrame::Frame(text) => self.try_send(Lane::Bulk, text).is_ok(),
+            PushFrame::Close(last) => self.close(last),
+        }
+    }
+
+    /// Ask the writer to send `last` (if any) and stop; false if already closed
+    pub fn close(&self, last: Option<String>) -> bool {
+        if self.control.is_closed() {
+            return false;
+        }
+        let mut close = self.shared.close.lock().unwrap();
+        if close.is_none() {
+            *close = Some(last);
+            self.shared.close_requested.notify_one();
+        }
+        true
+    }
+
+    /// Whether either lane has stayed full for longer than the slow-consumer timeout
+    pub fn is_slow_consumer(&self) -> bool {
+        self.is_slow_consumer_at(Instant::now())
+    }
+
+    fn is_slow_consumer_at(&self, now: Instant) -> bool {
+        self.shared
+            .full_since
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .any(|since| now.saturating_duration_since(*since) >= self.config.slow_consumer_timeout)
+    }
+
+    /// Frames dropped because their lane was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl OutboundQueue {
+    /// Next frame to write: a close request, then control, then bulk
+    ///
+    /// `None` once every sender is gone and both lanes are empty.
+    pub async fn recv(&mut self) -> Option<PushFrame> {
+        let (mut control_open, mut bulk_open) = (true, true);
+        while control_open || bulk_open {
+            tokio::select! {
+                biased;
+                _ = self.shared.close_requested.notified() => {
+                    if let Some(last) = self.shared.close.lock().unwrap().take() {
+                        return Some(PushFrame::Close(last));
+                    }
+                }
+                text = self.control.recv(), if control_open => match text {
+                    Some(text) => return Some(PushFrame::Frame(text)),
+                    None => control_open = false,
+                },
+                text = self.bulk.recv(), if bulk_open => match text {
+                    Some(text) => return Some(PushFrame::Frame(text)),
+                    None => bulk_open = false,
+                },
+            }
+        }
+        None
+    }
+
+    /// Next frame, without waiting
+    pub fn try_recv(&mut self) -> Result<PushFrame, TryRecvError> {
+        if let Some(last) = self.shared.close.lock().unwrap().take() {
+            return Ok(PushFrame::Close(last));
+        }
+        match self.control.try_recv() {
+            Ok(text) => Ok(PushFrame::Frame(text)),
+            Err(_) => self.bulk.try_recv().map(PushFrame::Frame),
+        }
+    }
+}
+
+/// How the writer task ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriterExit {
+    /// A close was requested; the connection should be closed now
+    Close,
+
+    /// Every sender was dropped
+    Finished,
+}
+
+/// Drain the queue into `out` until a close is requested or all senders are gone
+///
+/// Every frame written is counted in `stats`.
+pub async fn run_writer<W>(
+    mut queue: OutboundQueue,
+    mut out: W,
+    stats: &ConnectionStats,
+) -> std::io::Result<WriterExit>
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(frame) = queue.recv().await {
+        match frame {
+            PushFrame::Frame(text) => {
+                out.write_all(text.as_bytes()).await?;
+                stats.frame_sent(text.len());
+            }
+            PushFrame::Close(last) => {
+                if let Some(text) = last {
+                    // Best effort: the client may already be gone
+                    if out.write_all(text.as_bytes()).await.is_ok() {
+                        stats.frame_sent(text.len());
+                    }
+                }
+                return Ok(WriterExit::Close);
+            }
+        }
+    }
+    Ok(WriterExit::Finished)
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small() -> OutboundConfig {
+        OutboundConfig {
+            capacity: 2,
+            slow_consumer_timeout: Duration::from_millis(50),
+        }
+    }
+
+    #[test]
+    fn test_control_lane_first_and_close_overtakes() {
+        let (tx, mut rx) = channel(small());
+        tx.try_send(Lane::Bulk, "event".to_string()).unwrap();
+        tx.try_send(Lane::Control, "response".to_string()).unwrap();
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            PushFrame::Frame("response".to_string())
+        );
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            PushFrame::Frame("event".to_string())
+        );
+
+        tx.try_send(Lane::Control, "late".to_string()).unwrap();
+        assert!(tx.close(Some("bye".to_string())));
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            PushFrame::Close(Some("bye".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_full_lane_drops_and_marks_slow_consumer() {
+        let (tx, mut rx) = channel(small());
+        assert!(tx.push(PushFrame::Frame("1".to_string())));
+        assert!(tx.push(PushFrame::Frame("2".to_string())));
+        assert!(!tx.push(PushFrame::Frame("3".to_string())));
+        assert_eq!(tx.dropped(), 1);
+
+        // Full, but not for long enough yet
+        let now = Instant::now();
+        assert!(!tx.is_slow_consumer_at(now));
+        assert!(tx.is_slow_consumer_at(now + Duration::from_millis(60)));
+
+        // Room again: the next enqueue clears the mark
+        rx.try_recv().unwrap();
+        tx.try_send(Lane::Bulk, "4".to_string()).unwrap();
+        assert!(!tx.is_slow_consumer_at(now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_full_control_lane_not_cleared_by_bulk() {
+        let (tx, mut rx) = channel(small());
+        tx.try_send(Lane::Control, "1".to_string()).unwrap();
+        tx.try_send(Lane::Control, "2".to_string()).unwrap();
+        assert_eq!(
+            tx.try_send(Lane::Control, "3".to_string()),
+            Err(SendError::Full)
+        );
+
+        // Bulk frames keep flowing while nothing is read from the control lane
+        let now = Instant::now();
+        for i in 0..4 {
+            tx.try_send(Lane::Bulk, format!("event {i}")).unwrap();
+            assert_eq!(rx.bulk.try_recv().unwrap(), format!("event {i}"));
+        }
+        assert!(tx.is_slow_consumer_at(now + Duration::from_millis(60)));
+
+        // Room on the control lane clears its mark
+        rx.try_recv().unwrap();
+        tx.try_send(Lane::Control, "3".to_string()).unwrap();
+        assert!(!tx.is_slow_consumer_at(now + Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn test_response_waits_then_times_out() {
+        let (tx, mut rx) = channel(small());
+        tx.send_response("a".to_string()).await.unwrap();
+        tx.send_response("b".to_string()).await.unwrap();
+        assert_eq!(
+            tx.send_response("c".to_string()).await,
+            Err(SendError::SlowConsumer)
+        );
+
+        rx.try_recv().unwrap();
+        tx.send_response("c".to_string()).await.unwrap();
+
+        drop(rx);
+        assert_eq!(
+            tx.send_response("d".to_string()).await,
+            Err(SendError::Closed)
+        );
+        assert!(!tx.close(None));
+    }
+
+    #[tokio::test]
+    async fn test_writer_drains_in_priority_order() {
+        let (tx, rx) = channel(OutboundConfig::default());
+        tx.try_send(Lane::Bulk, "b1 ".to_string()).unwrap();
+        tx.try_send(Lane::Control, "c1 ".to_string()).unwrap();
+        tx.try_send(Lane::Bulk, "b2 ".to_string()).unwrap();
+        tx.try_send(Lane::Control, "c2 ".to_string()).unwrap();
+        drop(tx);
+
+        let mut out = Vec::new();
+        let stats = ConnectionStats::default();
+        assert_eq!(
+            run_writer(rx, &mut out, &stats).await.unwrap(),
+            WriterExit::Finished
+        );
+        assert_eq!(out, b"c1 c2 b1 b2 ");
+        let snapshot = stats.snapshot();
+        assert_eq!((snapshot.frames_out, snapshot.bytes_out), (4, 12));
+    }
+
+    #[tokio::test]
+    async fn test_writer_stops_on_close() {
+        let (tx, rx) = channel(OutboundConfig::default());
+        let writer = tokio::spawn(async move {
+            let mut out = Vec::new();
+            let exit = run_writer(rx, &mut out, &ConnectionStats::default())
+                .await
+                .unwrap();
+            (exit, out)
+        });
+        tx.try_send(Lane::Bulk, "queued ".to_string()).unwrap();
+        tx.close(Some("bye".to_string()));
+
+        let (exit, out) = writer.await.unwrap();
+        assert_eq!(exit, WriterExit::Close);
+        assert!(out.ends_with(b"bye"));
+    }
+}
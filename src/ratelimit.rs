@@ -0,0 +1,403 @@
+//! Token-bucket rate limiting
+//!
+//! Commands are charged against two buckets: one per session and one per
+//! authenticated user (shared by all of the user's sessions). Commands sent
+//! without an existing session (`INIT`, unknown or stale tokens) are charged
+//! to a bucket per remote IP instead, with the session limits. Each command
+//! costs a configurable number of tokens (1 by default, 0 = never limited).
+//! A command that would overdraw either bucket is refused with
+//! `RATE_LIMITED` and told how long to wait.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::commands::Response;
+use crate::error::codes;
+use crate::logging;
+
+/// A token bucket: `burst` tokens, refilled at `rate` tokens per second
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Full bucket
+    pub fn new(rate: f64, burst: f64, now: Instant) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    /// Change rate and burst, keeping the current fill (capped at the new burst)
+    pub fn reconfigure(&mut self, rate: f64, burst: f64, now: Instant) {
+        self.refill(now);
+        self.rate = rate;
+        self.burst = burst;
+        self.tokens = self.tokens.min(burst);
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+    }
+
+    /// Take `cost` tokens, or say how long until they would be available
+    pub fn try_take(&mut self, cost: f64, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+        if self.tokens >= cost {
+            self.tokens -= cost;
+            return Ok(());
+        }
+        if self.rate <= 0.0 || cost > self.burst {
+            // Never satisfiable at this cost; ask the caller to back off a full window
+            return Err(Duration::from_secs(60));
+        }
+        Err(Duration::from_secs_f64((cost - self.tokens) / self.rate))
+    }
+
+    /// Return tokens taken for a command that was refused elsewhere
+    pub fn refund(&mut self, cost: f64) {
+        self.tokens = (self.tokens + cost).min(self.burst);
+    }
+
+    /// Whether the bucket is full (idle long enough to be forgotten)
+    pub fn is_full(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * self.rate >= self.burst
+    }
+}
+
+// ============================================================================
+// COMMAND RATE LIMITS
+// ============================================================================
+
+/// Command costs used when nothing else is configured
+pub const DEFAULT_COMMAND_COSTS: &[(&str, u32)] = &[
+    ("MSG_SEND", 5),
+    ("MSG_BULK_ACTION", 5),
+    ("SEARCH_GLOBAL", 5),
+    ("SEARCH_ADV", 3),
+    ("ATTACH_UPLOAD_INIT", 3),
+];
+
+/// Per-session and per-user limits (a rate of 0 disables that bucket)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimits {
+    pub session_rate: u32,
+    pub session_burst: u32,
+    pub user_rate: u32,
+    pub user_burst: u32,
+    /// Upper-cased command name -> cost (commands not listed cost 1)
+    pub costs: HashMap<String, u32>,
+}
+
+impl RateLimits {
+    /// Tokens `command` costs
+    pub fn cost(&self, command: &str) -> u32 {
+        self.costs.get(command).copied().unwrap_or(1)
+    }
+}
+
+/// Parse a `COMMAND=COST` entry
+pub fn parse_command_cost(entry: &str) -> Result<(String, u32), String> {
+    let (command, cost) = entry
+        .split_once('=')
+        .ok_or_else(|| format!("`{entry}` is not COMMAND=COST"))?;
+    let command = command.trim().to_uppercase();
+    if command.is_empty() {
+        return Err(format!("`{entry}` has no command name"));
+    }
+    let cost = cost
+        .trim()
+        .parse()
+        .map_err(|e| format!("`{entry}`: cost is not a whole number: {e}"))?;
+    Ok((command, cost))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum BucketKey {
+    Session(String),
+    User(String),
+    Ip(IpAddr),
+}
+
+/// Token buckets for every active session and user
+#[derive(Default)]
+pub struct CommandLimiter {
+    buckets: Mutex<HashMap<BucketKey, TokenBucket>>,
+}
+
+/// Thread-safe command limiter type
+pub type SharedCommandLimiter = Arc<CommandLimiter>;
+
+/// Create an empty command limiter
+pub fn create_command_limiter() -> SharedCommandLimiter {
+    Arc::new(CommandLimiter::default())
+}
+
+impl CommandLimiter {
+    /// Charge a command; `Err` carries how long until it would be allowed
+    ///
+    /// `session` is the token of an existing session; without one the
+    /// command is charged to `ip`.
+    pub fn check(
+        &self,
+        limits: &RateLimits,
+        session: Option<&str>,
+        email: Option<&str>,
+        ip: IpAddr,
+        command: &str,
+    ) -> Result<(), Duration> {
+        self.check_at(limits, session, email, ip, command, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        limits: &RateLimits,
+        session: Option<&str>,
+        email: Option<&str>,
+        ip: IpAddr,
+        command: &str,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let cost = limits.cost(command) as f64;
+        if cost == 0.0 {
+            return Ok(());
+        }
+
+        // Keys are hashed so tokens are not kept in another map
+        let mut charges = Vec::new();
+        if limits.session_rate > 0 {
+            let key = match session {
+                Some(token) => BucketKey::Session(logging::session_id(token)),
+                None => BucketKey::Ip(ip),
+            };
+            charges.push((key, limits.session_rate, limits.session_burst));
+        }
+        if let (true, Some(email)) = (limits.user_rate > 0, email) {
+            charges.push((
+                BucketKey::User(email.to_lowercase()),
+                limits.user_rate,
+                limits.user_burst,
+            ));
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut taken = Vec::new();
+        for (key, rate, burst) in charges {
+            let (rate, burst) = (rate as f64, burst.max(rate) as f64);
+            let bucket = buckets
+                .entry(key.clone())
+                .or_insert_with(|| TokenBucket::new(rate, burst, now));
+            if bucket.rate != rate || bucket.burst != burst {
+                bucket.reconfigure(rate, burst, now);
+            }
+            if let Err(wait) = bucket.try_take(cost, now) {
+                // All or nothing: give back what the other bucket already took
+                for key in taken {
+                    if let Some(bucket) = buckets.get_mut(&key) {
+                        bucket.refund(cost);
+                    }
+                }
+                return Err(wait);
+            }
+            taken.push(key);
+        }
+        Ok(())
+    }
+
+    /// Forget buckets that have refilled completely; returns how many were dropped
+    pub fn prune(&self) -> usize {
+        self.prune_at(Instant::now())
+    }
+
+    fn prune_at(&self, now: Instant) -> usize {
+        let mut buckets = self.buckets.lock().unwrap();
+        let before = buckets.len();
+        buckets.retain(|_, bucket| !bucket.is_full(now));
+        before - buckets.len()
+    }
+
+    /// Error response for a throttled command
+    pub fn refusal(command: &str, wait: Duration) -> Response {
+        let retry_after_ms = wait.as_millis().max(1) as u64;
+        Response::err(command, "RATE_LIMITED", codes::RATE_LIMITED).with_data(serde_json::json!({
+            "retry_after": retry_after_ms.div_ceil(1000),
+            "retry_after_ms": retry_after_ms,
+        }))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_refills() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 3.0, start);
+
+        assert!(bucket.try_take(1.0, start).is_ok());
+        assert!(bucket.try_take(2.0, start).is_ok());
+        let wait = bucket.try_take(1.0, start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        assert!(!bucket.is_full(start));
+
+        assert!(bucket
+            .try_take(1.0, start + Duration::from_millis(500))
+            .is_ok());
+        assert!(bucket.is_full(start + Duration::from_secs(10)));
+
+        // More than the burst can never be satisfied
+        assert_eq!(
+            bucket.try_take(5.0, start + Duration::from_secs(10)),
+            Err(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn test_reconfigure_caps_tokens() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1.0, 10.0, start);
+        bucket.reconfigure(1.0, 2.0, start);
+        assert!(bucket.try_take(2.0, start).is_ok());
+        assert!(bucket.try_take(1.0, start).is_err());
+    }
+
+    fn limits() -> RateLimits {
+        RateLimits {
+            session_rate: 1,
+            session_burst: 3,
+            user_rate: 1,
+            user_burst: 4,
+            costs: [("MSG_SEND".to_string(), 2), ("PING".to_string(), 0)]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn test_session_and_user_buckets() {
+        let limiter = CommandLimiter::default();
+        let limits = limits();
+        let now = Instant::now();
+        let user = Some("a@test.com");
+
+        // Session A spends 3 of its 3 tokens (and 3 of the user's 4)
+        assert!(limiter
+            .check_at(&limits, Some("A"), user, IP, "MSG_SEND", now)
+            .is_ok());
+        assert!(limiter
+            .check_at(&limits, Some("A"), user, IP, "MB_LIST", now)
+            .is_ok());
+        assert!(limiter
+            .check_at(&limits, Some("A"), user, IP, "MB_LIST", now)
+            .is_err());
+
+        // Session B has its own bucket but shares the user's
+        assert!(limiter
+            .check_at(&limits, Some("B"), user, IP, "MB_LIST", now)
+            .is_ok());
+        let wait = limiter
+            .check_at(&limits, Some("B"), user, IP, "MSG_SEND", now)
+            .unwrap_err();
+        assert_eq!(wait, Duration::from_secs(2));
+
+        // The refused MSG_SEND did not drain session B
+        assert!(limiter
+            .check_at(&limits, Some("B"), None, IP, "MSG_SEND", now)
+            .is_ok());
+
+        // Zero-cost commands are never limited
+        for _ in 0..10 {
+            assert!(limiter
+                .check_at(&limits, Some("A"), user, IP, "PING", now)
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn test_requests_without_session_charge_ip() {
+        let limiter = CommandLimiter::default();
+        let limits = limits();
+        let now = Instant::now();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+
+        for _ in 0..3 {
+            assert!(limiter
+                .check_at(&limits, None, None, IP, "INIT", now)
+                .is_ok());
+        }
+        assert!(limiter
+            .check_at(&limits, None, None, IP, "INIT", now)
+            .is_err());
+        // Other addresses and existing sessions have their own buckets
+        assert!(limiter
+            .check_at(&limits, None, None, other, "INIT", now)
+            .is_ok());
+        assert!(limiter
+            .check_at(&limits, Some("A"), None, IP, "MB_LIST", now)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_limits_follow_reload_and_prune() {
+        let limiter = CommandLimiter::default();
+        let mut limits = limits();
+        let now = Instant::now();
+        assert!(limiter
+            .check_at(&limits, Some("A"), None, IP, "MSG_SEND", now)
+            .is_ok());
+        assert!(limiter
+            .check_at(&limits, Some("A"), None, IP, "MSG_SEND", now)
+            .is_err());
+
+        limits.session_rate = 0;
+        assert!(limiter
+            .check_at(&limits, Some("A"), None, IP, "MSG_SEND", now)
+            .is_ok());
+        // New limits apply to the existing bucket from the next command on
+        limits.session_rate = 10;
+        limits.session_burst = 10;
+        let later = now + Duration::from_secs(1);
+        assert!(limiter
+            .check_at(&limits, Some("A"), None, IP, "MSG_SEND", later)
+            .is_ok());
+
+        assert_eq!(limiter.prune_at(later), 0);
+        assert_eq!(limiter.prune_at(later + Duration::from_secs(1)), 1);
+    }
+
+    #[test]
+    fn test_parse_command_cost_and_refusal() {
+        assert_eq!(
+            parse_command_cost(" msg_send = 5"),
+            Ok(("MSG_SEND".to_string(), 5))
+        );
+        assert!(parse_command_cost("MSG_SEND").is_err());
+        assert!(parse_command_cost("=3").is_err());
+        assert!(parse_command_cost("MSG_SEND=-1").is_err());
+
+        let resp = CommandLimiter::refusal("MSG_SEND", Duration::from_millis(1500));
+        assert_eq!(resp.code, Some(codes::RATE_LIMITED));
+        let data = resp.data.unwrap();
+        assert_eq!(data["retry_after"], 2);
+        assert_eq!(data["retry_after_ms"], 1500);
+    }
+}
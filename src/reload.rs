@@ -0,0 +1,385 @@
+//! Hot configuration reload
+//!
+//! On `SIGHUP` (or an admin request) the configuration is re-read from the
+//! same sources it was loaded from and validated. If it is valid, the
+//! runtime-tunable settings are swapped in at once: log level, session
+//! caps, session timeouts, heartbeat interval, the guest policy, command
+//! rate limits, maintenance mode and the TLS file paths (picked up by the certificate reloader). Settings that
+//! only take effect at startup (bind address, storage, ...) keep their
+//! running value and are reported as needing a restart.
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::config::{Config, KEYS};
+use crate::error::{WmtpError, WmtpResult};
+use crate::guest::GuestPolicy;
+use crate::ratelimit::RateLimits;
+use crate::session::SessionManager;
+
+/// Settings applied to a running server by a reload
+pub const LIVE_KEYS: &[&str] = &[
+    "log_level",
+    "cert_path",
+    "key_path",
+    "session_timeout",
+    "heartbeat_interval",
+    "max_sessions_per_user",
+    "max_sessions_per_ip",
+    "session_limit_policy",
+    "guest_session_timeout",
+    "guest_commands",
+    "contact_mailbox",
+    "admin_users",
+    "shutdown_grace",
+    "maintenance",
+    "maintenance_reason",
+    "maintenance_eta",
+    "session_rate",
+    "session_burst",
+    "user_rate",
+    "user_burst",
+    "command_costs",
+];
+
+/// Re-reads configuration from its original sources (file, env, CLI)
+pub type ConfigSource = Box<dyn Fn() -> WmtpResult<Config> + Send + Sync>;
+
+/// Installs a new log filter; returns a description of the problem on failure
+pub type LogLevelSetter = Box<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
+/// Hooks the binary hands to the server so it can reload itself
+#[derive(Default)]
+pub struct RuntimeHooks {
+    /// Where to re-read configuration from (reload is disabled if `None`)
+    pub config_source: Option<ConfigSource>,
+
+    /// How to change the log filter (log level is not reloadable if `None`)
+    pub set_log_level: Option<LogLevelSetter>,
+}
+
+/// Result of a reload, as reported to the operator
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReloadOutcome {
+    /// Changed settings now in effect
+    pub applied: Vec<String>,
+
+    /// Changed settings that keep their old value until the server restarts
+    pub requires_restart: Vec<String>,
+}
+
+impl ReloadOutcome {
+    /// Whether the new configuration differed from the running one
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.requires_restart.is_empty()
+    }
+}
+
+/// Names of the settings that differ between two configurations
+pub fn changed_keys(old: &Config, new: &Config) -> Vec<&'static str> {
+    let (Ok(toml::Value::Table(old)), Ok(toml::Value::Table(new))) =
+        (toml::Value::try_from(old), toml::Value::try_from(new))
+    else {
+        return Vec::new();
+    };
+    KEYS.iter()
+        .copied()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .collect()
+}
+
+/// The configuration the server should run with after a reload
+///
+/// Live settings come from `new`; everything else stays as in `running`.
+pub fn merge_live(running: &Config, new: &Config) -> Config {
+    let mut merged = running.clone();
+    merged.log_level = new.log_level.clone();
+    merged.cert_path = new.cert_path.clone();
+    merged.key_path = new.key_path.clone();
+    merged.session_timeout = new.session_timeout;
+    merged.heartbeat_interval = new.heartbeat_interval;
+    merged.max_sessions_per_user = new.max_sessions_per_user;
+    merged.max_sessions_per_ip = new.max_sessions_per_ip;
+    merged.session_limit_policy = new.session_limit_policy;
+    merged.guest_session_timeout = new.guest_session_timeout;
+    merged.guest_commands = new.guest_commands.clone();
+    merged.contact_mailbox = new.contact_mailbox.clone();
+    merged.admin_users = new.admin_users.clone();
+    merged.shutdown_grace = new.shutdown_grace;
+    merged.maintenance = new.maintenance;
+    merged.maintenance_reason = new.maintenance_reason.clone();
+    merged.maintenance_eta = new.maintenance_eta.clone();
+    merged.session_rate = new.session_rate;
+    merged.session_burst = new.session_burst;
+    merged.user_rate = new.user_rate;
+    merged.user_burst = new.user_burst;
+    merged.command_costs = new.command_costs.clone();
+    merged
+}
+
+/// Configuration currently in effect, shared by every connection
+pub struct LiveConfig {
+    config: watch::Sender<Arc<Config>>,
+    guest_policy: RwLock<Arc<GuestPolicy>>,
+    rate_limits: RwLock<Arc<RateLimits>>,
+}
+
+/// Thread-safe live configuration type
+pub type SharedLiveConfig = Arc<LiveConfig>;
+
+impl LiveConfig {
+    /// Start from the configuration the server was launched with
+    pub fn new(config: Config) -> Self {
+        let guest_policy = Arc::new(config.guest_policy());
+        let rate_limits = Arc::new(config.rate_limits());
+        let (config, _) = watch::channel(Arc::new(config));
+        Self {
+            config,
+            guest_policy: RwLock::new(guest_policy),
+            rate_limits: RwLock::new(rate_limits),
+        }
+    }
+
+    /// Current configuration
+    pub fn config(&self) -> Arc<Config> {
+        self.config.borrow().clone()
+    }
+
+    /// Current guest policy
+    pub fn guest_policy(&self) -> Arc<GuestPolicy> {
+        self.guest_policy.read().unwrap().clone()
+    }
+
+    /// Current command rate limits
+    pub fn rate_limits(&self) -> Arc<RateLimits> {
+        self.rate_limits.read().unwrap().clone()
+    }
+
+    /// Current heartbeat interval
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.config.borrow().heartbeat_interval)
+    }
+
+    /// Get notified whenever the configuration is swapped
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Config>> {
+        self.config.subscribe()
+    }
+
+    /// Swap in a new configuration
+    fn replace(&self, config: Config) {
+        *self.guest_policy.write().unwrap() = Arc::new(config.guest_policy());
+        *self.rate_limits.write().unwrap() = Arc::new(config.rate_limits());
+        self.config.send_replace(Arc::new(config));
+    }
+}
+
+/// Performs reloads (from `SIGHUP` or an admin request)
+pub struct ConfigReloader {
+    live: SharedLiveConfig,
+    session_manager: Arc<SessionManager>,
+    hooks: RuntimeHooks,
+    // Serializes concurrent reloads
+    reloading: Mutex<()>,
+}
+
+impl ConfigReloader {
+    /// Create a reloader for a running server
+    pub fn new(live: SharedLiveConfig, session_manager: Arc<SessionManager>, hooks: RuntimeHooks) -> Self {
+        Self {
+            live,
+            session_manager,
+            hooks,
+            reloading: Mutex::new(()),
+        }
+    }
+
+    /// Re-read, validate and apply the configuration
+    ///
+    /// On any error the running configuration is left untouched.
+    pub fn reload(&self) -> WmtpResult<ReloadOutcome> {
+        let source = self
+            .hooks
+            .config_source
+            .as_ref()
+            .ok_or_else(|| WmtpError::Config("configuration reload is not available".to_string()))?;
+        let new = source()?;
+        new.validate()?;
+        self.apply(new)
+    }
+
+    /// Apply an already validated configuration
+    pub fn apply(&self, new: Config) -> WmtpResult<ReloadOutcome> {
+        let _guard = self.reloading.lock().unwrap();
+        let running = self.live.config();
+
+        let mut outcome = ReloadOutcome::default();
+        for key in changed_keys(&running, &new) {
+            if LIVE_KEYS.contains(&key) {
+                outcome.applied.push(key.to_string());
+            } else {
+                outcome.requires_restart.push(key.to_string());
+            }
+        }
+
+        let mut merged = merge_live(&running, &new);
+        if merged.log_level != running.log_level {
+            let set = self.hooks.set_log_level.as_ref().ok_or_else(|| {
+                WmtpError::Config("log_level: cannot be changed without a restart".to_string())
+            });
+            if let Err(e) = set.and_then(|set| {
+                set(&new.log_level).map_err(|e| WmtpError::Config(format!("log_level: {e}")))
+            }) {
+                // Keep the rest of the reload; report the level as not applied
+                tracing::warn!("{e}");
+                merged.log_level = running.log_level.clone();
+                outcome.applied.retain(|k| k != "log_level");
+                outcome.requires_restart.push("log_level".to_string());
+            }
+        }
+
+        self.session_manager
+            .set_timeouts(merged.session_timeout, merged.guest_session_timeout);
+        self.session_manager.set_limits(merged.session_limits());
+        self.live.replace(merged);
+
+        Ok(outcome)
+    }
+}
+
+/// Log the outcome of a reload
+pub fn log_outcome(result: &WmtpResult<ReloadOutcome>) {
+    match result {
+        Ok(outcome) if outcome.is_empty() => {
+            tracing::info!("Configuration reloaded: no changes");
+        }
+        Ok(outcome) => {
+            if !outcome.applied.is_empty() {
+                tracing::info!("Configuration reloaded: applied {}", outcome.applied.join(", "));
+            }
+            if !outcome.requires_restart.is_empty() {
+                tracing::warn!(
+                    "Configuration reloaded: restart required for {}",
+                    outcome.requires_restart.join(", ")
+                );
+            }
+        }
+        Err(e) => tracing::error!("Configuration reload failed, keeping current settings: {e}"),
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::{create_session_store, SessionLimitPolicy};
+
+    fn setup(hooks: RuntimeHooks) -> (SharedLiveConfig, Arc<SessionManager>, ConfigReloader) {
+        let config = Config::default();
+        let live = Arc::new(LiveConfig::new(config.clone()));
+        let manager = Arc::new(
+            SessionManager::new(create_session_store(), config.session_timeout)
+                .with_limits(config.session_limits()),
+        );
+        let reloader = ConfigReloader::new(live.clone(), manager.clone(), hooks);
+        (live, manager, reloader)
+    }
+
+    #[test]
+    fn test_changed_keys() {
+        let old = Config::default();
+        let new = Config {
+            port: 5000,
+            contact_mailbox: Some("contact@wmtp.online".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(changed_keys(&old, &new), vec!["port", "contact_mailbox"]);
+        assert!(changed_keys(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_apply_live_and_restart_settings() {
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let seen = levels.clone();
+        let (live, manager, reloader) = setup(RuntimeHooks {
+            config_source: None,
+            set_log_level: Some(Box::new(move |level| {
+                seen.lock().unwrap().push(level.to_string());
+                Ok(())
+            })),
+        });
+        let changes = live.subscribe();
+
+        let new = Config {
+            port: 5000,
+            heartbeat_interval: 15,
+            max_sessions_per_user: 3,
+            session_limit_policy: SessionLimitPolicy::EvictLeastRecentlyActive,
+            guest_commands: vec!["PING".to_string()],
+            session_rate: 1,
+            log_level: "warn".to_string(),
+            ..Config::default()
+        };
+        let outcome = reloader.apply(new).unwrap();
+
+        assert_eq!(
+            outcome.applied,
+            vec![
+                "heartbeat_interval",
+                "max_sessions_per_user",
+                "session_limit_policy",
+                "session_rate",
+                "guest_commands",
+                "log_level"
+            ]
+        );
+        assert_eq!(outcome.requires_restart, vec!["port"]);
+
+        assert!(changes.has_changed().unwrap());
+        assert_eq!(live.heartbeat_interval(), Duration::from_secs(15));
+        assert_eq!(live.config().port, Config::default().port);
+        assert!(!live.guest_policy().allows("AUTH", &serde_json::Value::Null));
+        assert_eq!(live.rate_limits().session_rate, 1);
+        assert_eq!(manager.limits().max_per_user, 3);
+        assert_eq!(manager.limits().policy, SessionLimitPolicy::EvictLeastRecentlyActive);
+        assert_eq!(*levels.lock().unwrap(), vec!["warn".to_string()]);
+    }
+
+    #[test]
+    fn test_log_level_without_hook_needs_restart() {
+        let (live, _, reloader) = setup(RuntimeHooks::default());
+        let new = Config {
+            log_level: "warn".to_string(),
+            session_timeout: 600,
+            ..Config::default()
+        };
+        let outcome = reloader.apply(new).unwrap();
+        assert_eq!(outcome.applied, vec!["session_timeout"]);
+        assert_eq!(outcome.requires_restart, vec!["log_level"]);
+        assert_eq!(live.config().log_level, Config::default().log_level);
+        assert_eq!(live.config().session_timeout, 600);
+    }
+
+    #[test]
+    fn test_invalid_reload_keeps_running_config() {
+        let (live, _, reloader) = setup(RuntimeHooks {
+            config_source: Some(Box::new(|| {
+                Ok(Config {
+                    heartbeat_interval: 0,
+                    ..Config::default()
+                })
+            })),
+            set_log_level: None,
+        });
+        assert!(reloader.reload().is_err());
+        assert_eq!(live.config().heartbeat_interval, Config::default().heartbeat_interval);
+
+        let (_, _, reloader) = setup(RuntimeHooks::default());
+        assert!(matches!(reloader.reload(), Err(WmtpError::Config(_))));
+    }
+}
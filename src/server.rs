@@ -1,13 +1,14 @@
 // src/server.rs
 use anyhow::Result;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
-use tokio::time::interval;
-use tracing::{error, info, warn};
+use tokio::time::{interval, interval_at, Instant};
+use tracing::{debug, error, info, warn, Instrument};
 
 use chrono::{DateTime, Utc};
-use wtransport::{Endpoint, Identity, ServerConfig};
+use wtransport::{Connection, Endpoint, ServerConfig, VarInt};
 use wtransport::endpoint::IncomingSession;
 use wtransport::stream::{RecvStream, SendStream};
 
@@ -25,7 +26,26 @@ use crate::comm::{cmd, Request, Response};
 use crate::commands::connections::list::handler as connection_list_handler;
 
 // session imports
-use crate::session::{create_session_store, SessionStore};
+use crate::admin::{self, AdminContext, BoxFuture, Directory};
+use crate::accept::{self, ConnectionIds, ServerEndpoint, SharedConnectionIds};
+use crate::admission;
+use crate::listener::{create_listener, Listener, SharedListener};
+use crate::lockout::{create_lockouts, Lockouts, SharedLockouts};
+use crate::maintenance::{self, create_maintenance, SharedMaintenance};
+use crate::ratelimit::{create_command_limiter, CommandLimiter, SharedCommandLimiter};
+use crate::audit::{self, AuditLog, AuditWriter, SharedAuditWriter};
+use crate::error::{close_codes, codes, WmtpError, WmtpResult};
+use crate::config::Config;
+use crate::reload::{self, ConfigReloader, LiveConfig, RuntimeHooks, SharedLiveConfig};
+use crate::health::{self, create_health, Check, Health, SharedHealth};
+use crate::http::{self, HttpHandler, HttpResponse};
+use crate::logging;
+use crate::status::{self, StatusSources};
+use crate::metrics::{self, create_metrics, Metrics, SharedMetrics};
+use crate::tls::{self, CertReloader, DevCert};
+use crate::session::{
+    create_session_store, Admission, SessionCause, SessionManager, SessionStore, TracingObserver,
+};
 use crate::commands::sessions::init::handler as init_handler;
 use crate::commands::sessions::auth::handler as auth_handler;
 use crate::commands::sessions::resume::handler as resume_handler;
@@ -65,25 +85,148 @@ use crate::commands::attachments::attach_upload_init::handler as attach_upload_i
 use crate::commands::attachments::attach_upload_init::handler::PendingUpload;
 use crate::commands::attachments::attach_get::handler as attach_get_handler;
 
-use crate::connection::{ConnectionStore, create_connection_store, make_connection_info};
+use crate::commands::{cmd as wmtp_cmd, notices};
+use crate::shutdown::{self, create_shutdown, SharedShutdown, Shutdown};
+use crate::linking::{self, create_pairing_registry, SharedPairingRegistry};
+use crate::connection::{
+    bind_connection_info, create_connection_index, create_connection_store, make_connection_info,
+    set_client_version, unbind_connection_info, ConnectionIndex, ConnectionStats, ConnectionStore,
+    SharedConnectionIndex,
+};
+use crate::outbound::{self, Lane, OutboundSender, SendError, WriterExit};
+
+pub async fn run_server(config: Config) -> Result<()> {
+    run_server_with_hooks(config, RuntimeHooks::default()).await
+}
 
-pub async fn run_server() -> Result<()> {
+/// Run the server; `hooks` let it reload its configuration on SIGHUP
+pub async fn run_server_with_hooks(config: Config, hooks: RuntimeHooks) -> Result<()> {
     let start_time = SystemTime::now();
-    let port = 4433;
-    let cert_path = "C:/Drive_D/webdev/WMTP/certs/cert.pem";
-    let key_path = "C:/Drive_D/webdev/WMTP/certs/key.pem";
 
     // Session & connection stores
     let sessions: SessionStore = create_session_store();
     let connections: ConnectionStore = create_connection_store();
-    let mut next_conn_id: u64 = 1;
+
+    // Session <-> connection index (push fan-out, closing on SESSION_KILL)
+    let index: SharedConnectionIndex = create_connection_index();
+
+    // Session lifecycle observers are registered here, before the manager is shared
+    let session_manager = Arc::new(
+        SessionManager::new(sessions.clone(), config.session_timeout)
+            .with_guest_timeout(config.guest_session_timeout)
+            .with_limits(config.session_limits())
+            .with_observer(Arc::new(TracingObserver)),
+    );
+    let pairings: SharedPairingRegistry = create_pairing_registry(config.link_code_ttl);
+
+    // Sessions saved by the previous graceful shutdown
+    if let Some(path) = &config.session_state_file {
+        match session_manager.restore_from(path) {
+            Ok(0) => {}
+            Ok(n) => info!("Restored {n} session(s) from {}", path.display()),
+            Err(e) => warn!("Could not restore sessions from {}: {e}", path.display()),
+        }
+    }
+    let shutdown: SharedShutdown = create_shutdown();
+    let metrics: SharedMetrics = create_metrics();
+    let health: SharedHealth = create_health(shutdown.clone());
+    // Admission caps are server-wide, each listener's own caps apply on top;
+    // sessions are shared by all listeners
+    let admission = admission::create_admission(config.admission_limits());
+    let listeners: Vec<SharedListener> = config
+        .listener_specs()?
+        .into_iter()
+        .map(|spec| create_listener(spec, &admission))
+        .collect();
+    let limiter: SharedCommandLimiter = create_command_limiter();
+    let lockouts: SharedLockouts = create_lockouts(config.lockout_policy());
+    let maintenance: SharedMaintenance = create_maintenance();
+    if config.maintenance {
+        maintenance.apply_config(&config);
+        warn!("Starting in maintenance mode");
+    }
+    let audit_log: Option<SharedAuditWriter> = match &config.audit_log {
+        Some(path) => {
+            let key_file = config.audit_key_file.as_deref().ok_or_else(|| {
+                WmtpError::Config("audit_key_file: required when audit_log is set".to_string())
+            })?;
+            info!("Audit log: {}", path.display());
+            Some(audit::spawn_writer(AuditLog::open(path, key_file)?)?)
+        }
+        None => None,
+    };
+
+    // Runtime-tunable settings, swapped atomically on reload
+    let live: SharedLiveConfig = Arc::new(LiveConfig::new(config.clone()));
+    let reloader = Arc::new(ConfigReloader::new(live.clone(), session_manager.clone(), hooks));
+
+    // Maintenance follows config reloads that change it; every change is
+    // pushed to connected clients
+    {
+        let maintenance = maintenance.clone();
+        let mut config_changes = live.subscribe();
+        tokio::spawn(async move {
+            let mut previous = config_changes.borrow_and_update().clone();
+            while config_changes.changed().await.is_ok() {
+                let current = config_changes.borrow_and_update().clone();
+                if maintenance::config_changed(&previous, &current) {
+                    maintenance.apply_config(&current);
+                }
+                previous = current;
+            }
+        });
+    }
+    {
+        let index = index.clone();
+        let mut changes = maintenance.subscribe();
+        tokio::spawn(async move {
+            while changes.changed().await.is_ok() {
+                let window = changes.borrow_and_update().clone();
+                match &window {
+                    Some(w) => warn!("Maintenance mode on: {}", w.reason),
+                    None => info!("Maintenance mode off"),
+                }
+                let notified = index.broadcast(&notices::maintenance(window.as_ref()).to_json());
+                debug!("Maintenance notice sent to {notified} connection(s)");
+            }
+        });
+    }
+
+    // Periodic sweep so idle sessions expire (and observers hear about it)
+    {
+        let session_manager = session_manager.clone();
+        let pairings = pairings.clone();
+        let index = index.clone();
+        let connections = connections.clone();
+        let limiter = limiter.clone();
+        let lockouts = lockouts.clone();
+        tokio::spawn(async move {
+            let mut sweep = interval(Duration::from_secs(60));
+            loop {
+                sweep.tick().await;
+                let removed = session_manager.cleanup_expired();
+                if removed > 0 {
+                    info!("Expired {removed} idle session(s)");
+                }
+                pairings.purge_expired();
+                limiter.prune();
+                lockouts.prune();
+                for token in index.stale_sessions(|t| session_manager.exists(t)) {
+                    index.unbind_session(&token);
+                    unbind_connection_info(&connections, &token);
+                }
+            }
+        });
+    }
 
     // MongoDB client and mailbox repo
     let mongo_client = Client::with_options(
-        ClientOptions::parse("mongodb://localhost:27017").await?
+        ClientOptions::parse(&config.storage_uri).await?
     )?;
-    let db = mongo_client.database("wmtp");
+    let db = mongo_client.database(&config.storage_db);
     let db_arc = Arc::new(db.clone());
+    // Users and mailboxes as operators manage them (also checked for disabled users)
+    let directory: Arc<dyn Directory> = Arc::new(MongoDirectory::new(&db));
 
     let users_coll: Collection<UserDoc> = db_arc.collection::<UserDoc>("users");
     let mailbox_repo = Arc::new(MailboxRepository::new(&db));
@@ -92,54 +235,446 @@ pub async fn run_server() -> Result<()> {
     // attachments uploads collection
     let uploads_coll: Collection<PendingUpload> = db.collection::<PendingUpload>("uploads");
 
-    // TLS identity and WebTransport endpoint
-    let identity = Identity::load_pemfiles(cert_path, key_path).await?;
-    let server_config = ServerConfig::builder()
-        .with_bind_default(port)
-        .with_identity(&identity)
-        .max_idle_timeout(Some(Duration::from_secs(60)))?
-        .keep_alive_interval(Some(Duration::from_secs(10)))
-        .build();
-
-    let endpoint = Endpoint::server(server_config)?;
-    info!("WMTP server running on https://localhost:{port}");
+    // Storage health for the readiness probe
+    {
+        let db = db_arc.clone();
+        let health = health.clone();
+        tokio::spawn(async move {
+            let mut ping = interval(health::STORAGE_PING_INTERVAL);
+            loop {
+                ping.tick().await;
+                let pinged = tokio::time::timeout(Duration::from_secs(5), db.run_command(doc! { "ping": 1 }));
+                let check = match pinged.await {
+                    Ok(Ok(_)) => Check::pass("ping ok"),
+                    Ok(Err(e)) => Check::fail(format!("ping failed: {e}")),
+                    Err(_) => Check::fail("ping timed out"),
+                };
+                if !check.ok {
+                    warn!("Storage health check failed: {}", check.detail);
+                }
+                health.record_storage(check);
+            }
+        });
+    }
 
-    let heartbeat_interval: u64 = 5; // seconds
+    // Local admin socket for wmtpctl
+    #[cfg(unix)]
+    if let Some(path) = &config.admin_socket {
+        let ctx = Arc::new(AdminContext {
+            sessions: session_manager.clone(),
+            index: index.clone(),
+            connections: connections.clone(),
+            lockouts: lockouts.clone(),
+            maintenance: maintenance.clone(),
+            live: live.clone(),
+            reloader: reloader.clone(),
+            directory: Some(directory.clone()),
+            audit_log: audit_log.clone(),
+        });
+        let path = path.clone();
+        tokio::spawn(async move {
+            if let Err(e) = admin::serve(path, ctx).await {
+                error!("Admin socket stopped: {:?}", e);
+            }
+        });
+    }
 
-    loop {
-        let incoming: IncomingSession = endpoint.accept().await;
-        let sessions = sessions.clone();
-        let connections = connections.clone();
-        let conn_id = next_conn_id;
-        next_conn_id += 1;
+    // TLS identity (from cert_path/key_path, or generated for development)
+    // and a WebTransport endpoint per listener
+    let (identity, dev_cert) =
+        if config.dev_cert && !(config.cert_path.exists() && config.key_path.exists()) {
+            let (dev, identity) = DevCert::generate(&config)?;
+            warn!("No certificate at {:?}; using a generated development certificate", config.cert_path);
+            (identity, Some(Arc::new(dev)))
+        } else {
+            (tls::load_identity(&config.cert_path, &config.key_path).await?, None)
+        };
+    let certs = Arc::new(CertReloader::new(&config.cert_path, &config.key_path, &identity));
+
+    // `accept_shards` endpoints per listener, in the order of the server configs
+    let shards = config.accept_shards.max(1);
+    let shard_listeners: Vec<(SharedListener, usize)> = listeners
+        .iter()
+        .flat_map(|listener| (0..shards).map(move |shard| (listener.clone(), shard)))
+        .collect();
+    let mut endpoints: Vec<Arc<ServerEndpoint>> = Vec::with_capacity(shard_listeners.len());
+    for ((listener, shard), server_config) in shard_listeners.iter().zip(tls::server_configs(&config, &identity)?) {
+        let endpoint = Endpoint::server(server_config)
+            .map_err(|e| anyhow::anyhow!("listener {} (shard {shard}) on {}: {e}", listener.name(), listener.spec.addr))?;
+        info!("Listener {} shard {shard} bound to {}", listener.name(), endpoint.local_addr()?);
+        endpoints.push(Arc::new(endpoint));
+    }
+    info!(
+        "WMTP server running on https://{}:{} ({} listener(s), {shards} accept shard(s) each)",
+        config.domain,
+        config.port,
+        listeners.len()
+    );
+    info!("TLS certificate SHA-256 {}", certs.fingerprint());
+    health.record_tls(Check::pass(format!("SHA-256 {}", certs.fingerprint())));
+
+    if let Some(dev) = &dev_cert {
+        info!("Development certificate hash (base64): {}", dev.hash());
+        serve_dev_cert(dev.clone(), endpoints.clone(), live.clone(), &config.dev_cert_addr).await?;
+    }
+    if let Some(addr) = &config.metrics_addr {
+        serve_metrics(metrics.clone(), session_manager.clone(), addr).await?;
+    }
+    if let Some(addr) = &config.health_addr {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        info!("Health probes served at http://{addr}/livez and /readyz");
+        let handler = health.handler();
+        tokio::spawn(async move {
+            if let Err(e) = http::serve(listener, handler).await {
+                error!("Health endpoint stopped: {:?}", e);
+            }
+        });
+    }
 
-        let start_time_clone = start_time;
-        let mailbox_repo = mailbox_repo.clone();
-        let users_coll_cloned = users_coll.clone();
-        let uploads_coll_cloned = uploads_coll.clone();
-        let messages_coll_cloned = messages_coll.clone();
-        let db_cloned = db_arc.clone();
+    // SIGHUP: reload configuration, then the TLS identity
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let reloader = reloader.clone();
+        let endpoints = endpoints.clone();
+        let certs = certs.clone();
+        let live = live.clone();
+        let health = health.clone();
+        let tls_from_files = dev_cert.is_none();
+        let mut hangup = signal(SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("SIGHUP received, reloading configuration");
+                reload::log_outcome(&reloader.reload());
+                if tls_from_files {
+                    reload_tls(&endpoints, &certs, &live, &health, true).await;
+                }
+            }
+        });
+    }
 
+    // Pick up renewed certificates without a signal
+    if dev_cert.is_none() && config.tls_watch_interval > 0 {
+        let endpoints = endpoints.clone();
+        let certs = certs.clone();
+        let live = live.clone();
+        let health = health.clone();
+        let every = Duration::from_secs(config.tls_watch_interval);
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(
-                incoming,
-                sessions,
-                connections,
-                conn_id,
-                heartbeat_interval,
-                start_time_clone,
-                mailbox_repo,
-                users_coll_cloned,
-                uploads_coll_cloned,
-                messages_coll_cloned,
-                db_cloned,
-            )
-            .await
-            {
-                error!("Connection error: {:?}", e);
+            let mut watch = interval_at(Instant::now() + every, every);
+            loop {
+                watch.tick().await;
+                reload_tls(&endpoints, &certs, &live, &health, false).await;
             }
         });
     }
+
+    health.set_accepting(true);
+
+    // Every connection sees the same sessions, connections and counters
+    let ctx = Arc::new(ConnectionContext {
+        sessions: sessions.clone(),
+        session_manager: session_manager.clone(),
+        live: live.clone(),
+        shutdown: shutdown.clone(),
+        metrics: metrics.clone(),
+        health: health.clone(),
+        audit_log: audit_log.clone(),
+        limiter: limiter.clone(),
+        lockouts: lockouts.clone(),
+        maintenance: maintenance.clone(),
+        pairings: pairings.clone(),
+        index: index.clone(),
+        connections: connections.clone(),
+        start_time,
+        mailbox_repo: mailbox_repo.clone(),
+        users_coll: users_coll.clone(),
+        uploads_coll: uploads_coll.clone(),
+        messages_coll: messages_coll.clone(),
+        db: db_arc.clone(),
+        directory: directory.clone(),
+    });
+
+    // Every endpoint has its own accept loop, which starts each connection
+    // itself; connection ids are unique across all of them
+    let conn_ids: SharedConnectionIds = Arc::new(ConnectionIds::default());
+    let mut accept_loops = Vec::with_capacity(endpoints.len());
+    for ((listener, shard), endpoint) in shard_listeners.iter().zip(&endpoints) {
+        let name = if shards > 1 {
+            format!("{} shard {shard}", listener.name())
+        } else {
+            listener.name().to_string()
+        };
+        let listener = listener.clone();
+        let ctx = ctx.clone();
+        let accept_loop = accept::spawn_accept_loop(endpoint.clone(), conn_ids.clone(), move |incoming, conn_id| {
+            let ctx = ctx.clone();
+            let listener = listener.clone();
+            async move {
+                if let Err(e) = handle_connection(incoming, ctx, listener, conn_id).await {
+                    error!("Connection error: {:?}", e);
+                }
+            }
+            .instrument(logging::connection_span(conn_id))
+        });
+        accept_loops.push(accept_loop.abort_handle());
+        health.watch_accept_loop(&name, accept_loop);
+    }
+
+    termination_signal().await;
+
+    // Graceful shutdown: no new connections, tell clients, drain, save sessions
+    let grace = live.config().shutdown_grace;
+    info!("Shutting down: draining for up to {grace}s");
+    health.set_accepting(false);
+    for endpoint in &endpoints {
+        endpoint.reject_new_connections();
+    }
+    for accept_loop in &accept_loops {
+        accept_loop.abort();
+    }
+    shutdown.begin();
+
+    let notice = notices::server_shutdown(
+        grace,
+        shutdown::RECONNECT_AFTER_SECS,
+        config.session_state_file.is_some(),
+    )
+    .to_json();
+    let notified = index.broadcast(&notice);
+    info!("Shutdown notice sent to {notified} connection(s)");
+
+    if !shutdown.wait_idle(Duration::from_secs(grace)).await {
+        warn!("Shutdown deadline passed with {} command(s)/upload(s) still running", shutdown.in_flight());
+    }
+    index.close_all();
+
+    if let Some(path) = &config.session_state_file {
+        match session_manager.save_to(path) {
+            Ok(n) => info!("Saved {n} session(s) to {}", path.display()),
+            Err(e) => error!("Could not save sessions to {}: {e}", path.display()),
+        }
+    }
+    if let Some(audit_log) = audit_log {
+        let _ = tokio::task::spawn_blocking(move || audit_log.flush()).await;
+    }
+    info!("Shutdown complete");
+    Ok(())
+}
+
+// Resolves on SIGTERM (or Ctrl-C)
+async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = term.recv() => info!("SIGTERM received"),
+                    _ = tokio::signal::ctrl_c() => info!("Interrupt received"),
+                }
+                return;
+            }
+            Err(e) => warn!("Cannot listen for SIGTERM: {:?}", e),
+        }
+    }
+    if tokio::signal::ctrl_c().await.is_ok() {
+        info!("Interrupt received");
+    } else {
+        std::future::pending::<()>().await;
+    }
+}
+
+// Give every endpoint its new server configuration (same order as `tls::server_configs`)
+fn apply_server_configs(endpoints: &[Arc<ServerEndpoint>], configs: Vec<ServerConfig>) -> std::io::Result<()> {
+    for (endpoint, server_config) in endpoints.iter().zip(configs) {
+        endpoint.reload_config(server_config, false)?;
+    }
+    Ok(())
+}
+
+// Swap the endpoints' TLS identity; existing connections are unaffected
+async fn reload_tls(
+    endpoints: &[Arc<ServerEndpoint>],
+    certs: &CertReloader,
+    live: &LiveConfig,
+    health: &Health,
+    force: bool,
+) {
+    // A failed reload keeps serving the previous certificate, so stays ready
+    match certs.reload(&live.config(), force).await {
+        Ok(Some((_, server_configs))) => match apply_server_configs(endpoints, server_configs) {
+            Ok(()) => {
+                info!("TLS identity reloaded, certificate SHA-256 {}", certs.fingerprint());
+                health.record_tls(Check::pass(format!("SHA-256 {}", certs.fingerprint())));
+            }
+            Err(e) => error!("Failed to apply reloaded TLS identity: {:?}", e),
+        },
+        Ok(None) => {}
+        Err(e) => {
+            error!("TLS reload failed, keeping current identity: {e}");
+            health.record_tls(Check::pass(format!(
+                "SHA-256 {} (reload failed: {e})",
+                certs.fingerprint()
+            )));
+        }
+    }
+}
+
+// Prometheus scrape endpoint on a loopback address
+async fn serve_metrics(metrics: SharedMetrics, session_manager: Arc<SessionManager>, addr: &str) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("Metrics served at http://{addr}/metrics");
+
+    let handler: HttpHandler = Arc::new(move |path| match path {
+        "/metrics" => HttpResponse {
+            status: 200,
+            content_type: "text/plain; version=0.0.4; charset=utf-8",
+            body: metrics.render(&session_manager),
+            any_origin: false,
+        },
+        _ => HttpResponse::not_found(),
+    });
+    tokio::spawn(async move {
+        if let Err(e) = http::serve(listener, handler).await {
+            error!("Metrics endpoint stopped: {:?}", e);
+        }
+    });
+    Ok(())
+}
+
+// Development certificate: serve its hash on a loopback HTTP endpoint and
+// replace it well before it expires
+async fn serve_dev_cert(
+    dev: Arc<DevCert>,
+    endpoints: Vec<Arc<ServerEndpoint>>,
+    live: SharedLiveConfig,
+    addr: &str,
+) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("Certificate hash served at http://{addr}/cert-hash");
+
+    let handler_dev = dev.clone();
+    let handler: HttpHandler = Arc::new(move |path| match path {
+        // Fetched by browser clients on other origins (the dev web client)
+        "/cert-hash" => HttpResponse::json(200, &handler_dev.hash_info()).with_any_origin(),
+        _ => HttpResponse::not_found(),
+    });
+    tokio::spawn(async move {
+        if let Err(e) = http::serve(listener, handler).await {
+            error!("Certificate hash endpoint stopped: {:?}", e);
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut check = interval(Duration::from_secs(3600));
+        loop {
+            check.tick().await;
+            if !dev.due() {
+                continue;
+            }
+            match dev.rotate(&live.config()) {
+                Ok((_, server_configs)) => match apply_server_configs(&endpoints, server_configs) {
+                    Ok(()) => info!("Development certificate rotated, hash (base64): {}", dev.hash()),
+                    Err(e) => error!("Failed to apply rotated certificate: {:?}", e),
+                },
+                Err(e) => error!("Development certificate rotation failed: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+// Users and mailboxes managed through wmtpctl
+struct MongoDirectory {
+    users: Collection<mongodb::bson::Document>,
+    mailboxes: Collection<mongodb::bson::Document>,
+}
+
+impl MongoDirectory {
+    fn new(db: &Database) -> Self {
+        Self {
+            users: db.collection("users"),
+            mailboxes: db.collection("mailboxes"),
+        }
+    }
+}
+
+fn storage_error(e: mongodb::error::Error) -> WmtpError {
+    WmtpError::Internal(format!("storage: {e}"))
+}
+
+impl Directory for MongoDirectory {
+    fn create_user<'a>(&'a self, email: &'a str, username: Option<&'a str>) -> BoxFuture<'a, WmtpResult<bool>> {
+        Box::pin(async move {
+            if self.users.find_one(doc! { "email": email }).await.map_err(storage_error)?.is_some() {
+                return Ok(false);
+            }
+            let username = username.unwrap_or_else(|| email.split('@').next().unwrap_or(email));
+            self.users
+                .insert_one(doc! {
+                    "email": email,
+                    "username": username,
+                    "disabled": false,
+                    "created_at": Utc::now().to_rfc3339(),
+                })
+                .await
+                .map_err(storage_error)?;
+            Ok(true)
+        })
+    }
+
+    fn set_user_disabled<'a>(&'a self, email: &'a str, disabled: bool) -> BoxFuture<'a, WmtpResult<bool>> {
+        Box::pin(async move {
+            let result = self
+                .users
+                .update_one(doc! { "email": email }, doc! { "$set": { "disabled": disabled } })
+                .await
+                .map_err(storage_error)?;
+            Ok(result.matched_count > 0)
+        })
+    }
+
+    fn is_user_disabled<'a>(&'a self, email: &'a str) -> BoxFuture<'a, WmtpResult<bool>> {
+        Box::pin(async move {
+            let filter = doc! { "email": email, "disabled": true };
+            Ok(self.users.find_one(filter).await.map_err(storage_error)?.is_some())
+        })
+    }
+
+    fn delete_user<'a>(&'a self, email: &'a str) -> BoxFuture<'a, WmtpResult<bool>> {
+        Box::pin(async move {
+            let result = self.users.delete_one(doc! { "email": email }).await.map_err(storage_error)?;
+            if result.deleted_count > 0 {
+                self.mailboxes.delete_many(doc! { "owner": email }).await.map_err(storage_error)?;
+            }
+            Ok(result.deleted_count > 0)
+        })
+    }
+
+    fn create_mailbox<'a>(&'a self, email: &'a str, name: &'a str) -> BoxFuture<'a, WmtpResult<bool>> {
+        Box::pin(async move {
+            let filter = doc! { "owner": email, "name": name };
+            if self.mailboxes.find_one(filter.clone()).await.map_err(storage_error)?.is_some() {
+                return Ok(false);
+            }
+            self.mailboxes.insert_one(filter).await.map_err(storage_error)?;
+            Ok(true)
+        })
+    }
+
+    fn delete_mailbox<'a>(&'a self, email: &'a str, name: &'a str) -> BoxFuture<'a, WmtpResult<bool>> {
+        Box::pin(async move {
+            let result = self
+                .mailboxes
+                .delete_one(doc! { "owner": email, "name": name })
+                .await
+                .map_err(storage_error)?;
+            Ok(result.deleted_count > 0)
+        })
+    }
 }
 
 // PING
@@ -196,72 +731,169 @@ fn make_hb_response(start_time: SystemTime) -> String {
         .to_json()
 }
 
-async fn handle_connection(
-    incoming: IncomingSession,
+/// Shared state every connection handler gets
+struct ConnectionContext {
     sessions: SessionStore,
+    session_manager: Arc<SessionManager>,
+    live: SharedLiveConfig,
+    shutdown: SharedShutdown,
+    metrics: SharedMetrics,
+    health: SharedHealth,
+    audit_log: Option<SharedAuditWriter>,
+    limiter: SharedCommandLimiter,
+    lockouts: SharedLockouts,
+    maintenance: SharedMaintenance,
+    pairings: SharedPairingRegistry,
+    index: SharedConnectionIndex,
     connections: ConnectionStore,
-    conn_id: u64,
-    hb_interval: u64,
     start_time: SystemTime,
     mailbox_repo: Arc<MailboxRepository>,
     users_coll: Arc<Collection<UserDoc>>,
     uploads_coll: Collection<PendingUpload>,
     messages_coll: Collection<Message>,
     db: Arc<Database>,
+    directory: Arc<dyn Directory>,
+}
+
+async fn handle_connection(
+    incoming: IncomingSession,
+    ctx: Arc<ConnectionContext>,
+    listener: SharedListener,
+    conn_id: u64,
 ) -> Result<()> {
+    let ConnectionContext {
+        sessions,
+        live,
+        shutdown,
+        metrics,
+        index,
+        connections,
+        uploads_coll,
+        ..
+    } = &*ctx;
     let session_request = incoming.await?;
     let remote = session_request.remote_address();
+    logging::record_remote(remote);
+
+    // Over a cap: accept only to close with a code the client can read
+    let permit = match listener.admission.admit(remote.ip()) {
+        Ok(permit) => permit,
+        Err(refusal) => {
+            warn!("Refusing connection on {}: {}", listener.name(), refusal.reason());
+            let connection = session_request.accept().await?;
+            connection.close(VarInt::from_u32(refusal.close_code()), refusal.reason().as_bytes());
+            return Ok(());
+        }
+    };
     let connection = Arc::new(session_request.accept().await?);
 
+    // 1) control stream; the connection is listed only once it has one, so
+    // a failed handshake leaves nothing behind in the store
+    let (control_send, control_recv) = connection.accept_bi().await?;
+    let info = make_connection_info(conn_id, Some(remote));
+    let stats = info.stats.clone();
+    connections.lock().unwrap().insert(conn_id, info);
+    stats.record_path(connection.rtt(), connection.max_datagram_size());
+    let control_slot = permit.open_stream();
+    metrics.connection_opened();
+    metrics.stream_opened();
+    stats.stream_opened();
+    let mut streams_opened: u64 = 1;
+
+    // Everything written to the control stream goes through this queue,
+    // drained by a single writer task
+    let (outbound_tx, outbound_rx) = outbound::channel(live.config().outbound_config());
+    index.register(conn_id, outbound_tx.clone());
+    let outbound_stats = outbound_tx.clone();
     {
-        let mut store = connections.lock().unwrap();
-        store.insert(conn_id, make_connection_info(conn_id, Some(remote)));
+        let connection = connection.clone();
+        let shutdown = shutdown.clone();
+        let stats = stats.clone();
+        tokio::spawn(async move {
+            match outbound::run_writer(outbound_rx, control_send, &stats).await {
+                Ok(WriterExit::Close) if shutdown.is_draining() => {
+                    info!("Closing connection {conn_id}: server shutting down");
+                    connection.close(VarInt::from_u32(close_codes::NORMAL), b"server shutdown");
+                }
+                Ok(WriterExit::Close) => {
+                    info!("Closing connection {conn_id}: session closed by server");
+                    connection.close(VarInt::from_u32(close_codes::NORMAL), b"session closed");
+                }
+                Ok(WriterExit::Finished) => {}
+                Err(e) => {
+                    warn!("Control stream write failed: {:?}", e);
+                    connection.close(VarInt::from_u32(close_codes::NORMAL), b"write failed");
+                }
+            }
+        }.in_current_span());
     }
 
-    // 1) control stream
-    let (control_send, control_recv) = connection.accept_bi().await?;
-
-    let sessions_clone = sessions.clone();
-    let connections_clone = connections.clone();
-    let mailbox_repo_clone = mailbox_repo.clone();
-    let users_coll_clone = users_coll.clone();
-    let uploads_coll_clone = uploads_coll.clone();
-    let messages_coll_clone = messages_coll.clone();
-    let db_clone = db.clone();
-
-    tokio::spawn(async move {
-        if let Err(e) = handle_control_stream(
-            control_send,
-            control_recv,
-            sessions_clone,
-            connections_clone,
-            conn_id,
-            hb_interval,
-            start_time,
-            mailbox_repo_clone,
-            users_coll_clone,
-            uploads_coll_clone,
-            messages_coll_clone,
-            db_clone,
-        )
-        .await
-        {
-            warn!("Control stream ended: {:?}", e);
-        }
-    });
+    {
+        let ctx = ctx.clone();
+        let listener = listener.clone();
+        let connection = connection.clone();
+        let stats = stats.clone();
+        tokio::spawn(async move {
+            let control = handle_control_stream(
+                &ctx,
+                &listener,
+                outbound_tx,
+                control_recv,
+                &connection,
+                &stats,
+                conn_id,
+                remote,
+            );
+            if let Err(e) = control.await {
+                warn!("Control stream ended: {:?}", e);
+            }
+            ctx.metrics.stream_closed();
+            stats.stream_closed();
+            drop(control_slot);
+        }.in_current_span());
+    }
 
     // 2) extra streams = attachment streams
     loop {
         match connection.accept_bi().await {
             Ok((send, recv)) => {
+                // No new uploads once draining; running ones are waited for
+                if shutdown.is_draining() {
+                    continue;
+                }
+                let Some(slot) = permit.open_stream() else {
+                    debug!("Stream limit reached, resetting stream");
+                    send.reset(VarInt::from_u32(close_codes::STREAM_LIMIT));
+                    recv.stop(VarInt::from_u32(close_codes::STREAM_LIMIT));
+                    continue;
+                };
                 let sessions_clone = sessions.clone();
                 let uploads_clone = uploads_coll.clone();
+                let metrics_clone = metrics.clone();
+                let stats_clone = stats.clone();
+                let upload = shutdown.track();
+                metrics.stream_opened();
+                stats.stream_opened();
+                streams_opened += 1;
 
                 tokio::spawn(async move {
-                    if let Err(e) = handle_attachment_stream(send, recv, sessions_clone, uploads_clone).await {
+                    if let Err(e) = handle_attachment_stream(
+                        send,
+                        recv,
+                        sessions_clone,
+                        uploads_clone,
+                        &metrics_clone,
+                        &stats_clone,
+                    )
+                    .await
+                    {
                         warn!("Attachment stream error: {:?}", e);
                     }
-                });
+                    metrics_clone.stream_closed();
+                    stats_clone.stream_closed();
+                    drop(slot);
+                    drop(upload);
+                }.in_current_span());
             }
             Err(e) => {
                 warn!("No more streams or error: {:?}", e);
@@ -270,6 +902,9 @@ async fn handle_connection(
         }
     }
 
+    index.unregister(conn_id);
+    metrics.connection_closed(streams_opened);
+    metrics.outbound_frames_dropped(outbound_stats.dropped());
     {
         let mut store = connections.lock().unwrap();
         store.remove(&conn_id);
@@ -284,6 +919,8 @@ async fn handle_attachment_stream(
     mut recv: RecvStream,
     _sessions: SessionStore,
     uploads_coll: Collection<PendingUpload>,
+    metrics: &Metrics,
+    stats: &ConnectionStats,
 ) -> Result<()> {
     let mut buf = [0u8; 8192];
     let mut text_buf = String::new();
@@ -298,6 +935,7 @@ async fn handle_attachment_stream(
                 return Ok(());
             }
         };
+        stats.bytes_received(n);
 
         let chunk = match std::str::from_utf8(&buf[..n]) {
             Ok(t) => t,
@@ -368,6 +1006,7 @@ let mut upload_stream = bucket
 
     // any leftover bytes after header newline
     if !text_buf.is_empty() {
+        metrics.attachment_bytes(text_buf.len() as u64);
         upload_stream
             .write_all(text_buf.as_bytes())
             .await
@@ -388,6 +1027,8 @@ let mut upload_stream = bucket
                 break;
             }
         };
+        metrics.attachment_bytes(n as u64);
+        stats.bytes_received(n);
 
         upload_stream
             .write_all(&buf[..n])
@@ -422,35 +1063,53 @@ let mut upload_stream = bucket
 
 // handle control stream
 async fn handle_control_stream(
-    mut send: SendStream,
+    ctx: &ConnectionContext,
+    listener: &Listener,
+    outbound: OutboundSender,
     mut recv: RecvStream,
-    sessions: SessionStore,
-    connections: ConnectionStore,
+    connection: &Connection,
+    stats: &ConnectionStats,
     conn_id: u64,
-    hb_interval: u64,
-    start_time: SystemTime,
-    mailbox_repo: Arc<MailboxRepository>,
-    users_coll: Arc<Collection<UserDoc>>,
-    uploads_coll: Collection<PendingUpload>,
-    messages_coll: Collection<Message>,
-    db: Arc<Database>,
+    remote: SocketAddr,
 ) -> Result<()> {
-    let mut heartbeat = interval(Duration::from_secs(hb_interval));
+    let ConnectionContext { live, shutdown, metrics, start_time, .. } = ctx;
+    // Heartbeat interval follows config reloads
+    let mut config_changes = live.subscribe();
+    let mut hb_every = live.heartbeat_interval();
+    let mut heartbeat = interval(hb_every);
     let mut buf = [0u8; 8192];
+    let mut request_seq: u64 = 0;
 
     loop {
         tokio::select! {
             _ = heartbeat.tick() => {
-                let hb = make_hb_response(start_time);
-                if let Err(e) = send.write_all(hb.as_bytes()).await {
-                    warn!("Failed to send heartbeat: {:?}", e);
+                stats.record_path(connection.rtt(), connection.max_datagram_size());
+                match outbound.try_send(Lane::Control, make_hb_response(*start_time)) {
+                    Ok(()) => {}
+                    Err(SendError::Closed) => break,
+                    Err(e) => {
+                        metrics.heartbeat_write_failed();
+                        debug!("Heartbeat not queued: {:?}", e);
+                    }
+                }
+                if outbound.is_slow_consumer() {
+                    close_slow_consumer(connection, metrics, conn_id);
                     break;
                 }
             }
 
+            Ok(()) = config_changes.changed() => {
+                let every = live.heartbeat_interval();
+                if every != hb_every {
+                    hb_every = every;
+                    heartbeat = interval_at(Instant::now() + every, every);
+                }
+            }
+
             result = recv.read(&mut buf) => {
                 match result {
                     Ok(Some(n)) if n > 0 => {
+                        stats.frame_received(n);
                         let text = match std::str::from_utf8(&buf[..n]) {
                             Ok(t) => t.trim(),
                             Err(_) => {
@@ -459,20 +1118,27 @@ async fn handle_control_stream(
                             }
                         };
 
-                        let response = process_command(
-                            text,
-                            &sessions,
-                            &connections,
-                            start_time,
-                            &mailbox_repo,
-                            &users_coll,
-                            &uploads_coll,
-                            &messages_coll,
-                            &db,
-                        ).await;
-
-                        if send.write_all(response.as_bytes()).await.is_err() {
-                            break;
+                        request_seq += 1;
+                        let span = logging::request_span(text, &format!("{conn_id}-{request_seq}"));
+                        let work = shutdown.track();
+                        let started = Instant::now();
+                        let response = process_command(text, ctx, listener, conn_id, remote)
+                            .instrument(span.clone())
+                            .await;
+                        drop(work);
+                        stats.command_processed();
+                        let elapsed = started.elapsed();
+                        logging::finish_request(&span, &response, elapsed);
+                        metrics.observe_command(&metrics::command_label(text, &response), &response, elapsed);
+
+                        // Waits while the queue is full, so a slow client stops being read
+                        match outbound.send_response(response).await {
+                            Ok(()) => {}
+                            Err(SendError::SlowConsumer) => {
+                                close_slow_consumer(connection, metrics, conn_id);
+                                break;
+                            }
+                            Err(_) => break,
                         }
                     }
                     Ok(Some(_)) => break,
@@ -489,29 +1155,134 @@ async fn handle_control_stream(
     Ok(())
 }
 
+// The client stopped reading: its outbound queue stayed full too long
+fn close_slow_consumer(connection: &Connection, metrics: &Metrics, conn_id: u64) {
+    warn!("Closing connection {conn_id}: slow consumer");
+    metrics.slow_consumer_disconnected();
+    connection.close(VarInt::from_u32(close_codes::SLOW_CONSUMER), b"slow consumer");
+}
+
+// Per-user / per-IP session caps, checked before INIT and AUTH run.
+// Returns an error response if the new session is refused. Under the evict
+// policy INIT makes room here; AUTH only once the login has succeeded.
+fn enforce_session_limits(
+    command: &str,
+    req: &Request,
+    token: &str,
+    remote: SocketAddr,
+    session_manager: &SessionManager,
+    index: &ConnectionIndex,
+) -> Option<String> {
+    let admission = match command {
+        cmd::INIT => session_manager.admit_ip(remote.ip()),
+        cmd::AUTH => {
+            let email = req.data.get("email").and_then(Value::as_str)?;
+            session_manager.admit_user(email, token)
+        }
+        _ => return None,
+    };
+
+    match admission {
+        Admission::Allowed => None,
+        Admission::Rejected(kind) => {
+            warn!("Session limit ({:?}) hit for {}", kind, remote);
+            Some(Admission::rejection_response(command, kind).to_json())
+        }
+        Admission::Evicted(kind, evicted) => {
+            index.notify_evicted(kind, evicted);
+            None
+        }
+    }
+}
+
+// An AUTH refused before it ran (session cap, rate limit, lockout) is
+// audited as a failed login carrying the refusal code
+fn audit_refused_login(
+    audit_log: Option<&AuditWriter>,
+    command: &str,
+    req: &Request,
+    response: &str,
+    remote: SocketAddr,
+) {
+    if command != cmd::AUTH {
+        return;
+    }
+    if let Some(audit_log) = audit_log {
+        if let Some(entry) = audit::entry_for_command(command, &req.data, response, None) {
+            audit_log.record_or_log(entry.with_remote(remote));
+        }
+    }
+}
+
+// Close every connection holding a killed session, then forget the session
+fn close_killed_session(token: &str, index: &ConnectionIndex, connections: &ConnectionStore) {
+    let notice = notices::session_killed(token).to_json();
+    index.close_session(token, Some(&notice));
+    index.unbind_session(token);
+    unbind_connection_info(connections, token);
+}
+
+// Session a SESSION_KILL removes (`target_token`, or `target`)
+fn kill_target(data: &Value) -> Option<&str> {
+    data.get("target_token")
+        .or_else(|| data.get("target"))
+        .and_then(Value::as_str)
+}
+
 async fn process_command(
     text: &str,
-    sessions: &SessionStore,
-    connections: &ConnectionStore,
-    start_time: SystemTime,
-    mailbox_repo: &MailboxRepository,
-    users_coll: &Collection<UserDoc>,
-    uploads_coll: &Collection<PendingUpload>,
-    messages_coll: &Collection<Message>,
-    db: &Database,
+    ctx: &ConnectionContext,
+    listener: &Listener,
+    conn_id: u64,
+    remote: SocketAddr,
 ) -> String {
-     eprintln!("[PROCESS_COMMAND] raw text: {}", text);
+    let ConnectionContext {
+        sessions,
+        session_manager,
+        live,
+        shutdown,
+        metrics,
+        health,
+        limiter,
+        lockouts,
+        maintenance,
+        pairings,
+        index,
+        connections,
+        mailbox_repo,
+        users_coll,
+        uploads_coll,
+        db,
+        ..
+    } = ctx;
+    let audit_log = ctx.audit_log.as_deref();
+    let directory = ctx.directory.as_ref();
+    let start_time = ctx.start_time;
+    debug!(request = %logging::redact_request(text), "request received");
 
     let req = match Request::from_json(text) {
         Ok(r) => r,
         Err(e) => {
-            eprintln!("[PROCESS_COMMAND] PARSE ERROR: {:?}", e);
+            debug!("Request is not valid JSON: {e}");
             return Response::err("PARSE", &format!("Invalid JSON: {}", e)).to_json();
         }
     };
 
     let command = req.cmd.to_uppercase();
-    eprintln!("[PROCESS_COMMAND] cmd: {}", command);
+
+    if shutdown.is_draining() {
+        return Shutdown::refusal(&command).to_json();
+    }
+
+    // Maintenance: no new logins or writes; reads and PING keep working
+    if let Some(refused) = maintenance.check(&command) {
+        return refused.to_json();
+    }
+
+    // Listeners may be restricted to a command set
+    if let Some(refused) = listener.check(&command) {
+        return refused.to_json();
+    }
 
     let token = req
         .data
@@ -520,18 +1291,117 @@ async fn process_command(
         .unwrap_or_default()
         .to_string();
 
-    match command.as_str() {
+    // Handlers mutate the store directly; diff the session around dispatch
+    // so lifecycle observers see the transition.
+    let lifecycle_token = req
+        .data
+        .get("session_token")
+        .or_else(|| req.data.get("token"))
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let before = session_manager.get(&lifecycle_token);
+
+    // SESSION_KILL of another session: its transition is reported separately
+    let target_before = if command == cmd::SESSION_KILL {
+        kill_target(&req.data)
+            .filter(|target| *target != lifecycle_token)
+            .and_then(|target| session_manager.get(target))
+    } else {
+        None
+    };
+
+    // Guests (no session, or not yet authenticated) only get the guest command set
+    if before.as_ref().map_or(true, |s| s.is_guest()) {
+        if let Some(refused) = live.guest_policy().check(&command, &req.data) {
+            return refused.to_json();
+        }
+    }
+
+    if let Some(refused) =
+        enforce_session_limits(&command, &req, &lifecycle_token, remote, session_manager, index)
+    {
+        audit_refused_login(audit_log, &command, &req, &refused, remote);
+        return refused;
+    }
+
+    // Charged per session and per user; without a session, per remote IP
+    let email = before.as_ref().and_then(|s| s.email.as_deref());
+    let session = before.as_ref().map(|s| s.token.as_str());
+    if let Err(wait) = limiter.check(&live.rate_limits(), session, email, remote.ip(), &command) {
+        debug!("Rate limited {command}, retry in {wait:?}");
+        let refused = CommandLimiter::refusal(&command, wait).to_json();
+        audit_refused_login(audit_log, &command, &req, &refused, remote);
+        return refused;
+    }
+
+    // Locked-out addresses are refused before the password is checked
+    let auth_email = if command == cmd::AUTH {
+        req.data.get("email").and_then(Value::as_str)
+    } else {
+        None
+    };
+    if let Some(remaining) = auth_email.and_then(|email| lockouts.check(email, remote.ip())) {
+        let refused = Lockouts::refusal(&command, remaining).to_json();
+        audit_refused_login(audit_log, &command, &req, &refused, remote);
+        return refused;
+    }
+
+    // Disabled users cannot log in, resume or approve a device link
+    let login_email = auth_email.or_else(|| before.as_ref().and_then(|s| s.email.as_deref()));
+    if let Some(email) = login_email {
+        if let Some(refused) = admin::disabled_refusal(directory, &command, email).await {
+            let refused = refused.to_json();
+            audit_refused_login(audit_log, &command, &req, &refused, remote);
+            return refused;
+        }
+    }
+
+    if command == cmd::INIT {
+        if let Some(version) = req.data.get("client_version").and_then(Value::as_str) {
+            set_client_version(connections, conn_id, version);
+        }
+    }
+
+    let response = match command.as_str() {
         cmd::INIT => init_handler::handle_init(&req, sessions).await,
         cmd::AUTH => auth_handler::handle_auth(&req, sessions, mailbox_repo, users_coll).await,
         cmd::RESUME => resume_handler::handle_resume(&req, sessions).await,
         cmd::LOGOUT => logout_handler::handle_logout(&req, sessions).await,
         cmd::SESSION_INFO => session_info_handler::handle_session_info(&req, sessions).await,
         cmd::SESSION_LIST => session_list_handler::handle_session_list(&req, sessions).await,
-        cmd::CONNECTION_LIST => connection_list_handler::handle_connection_list(&req, connections).await,
+        cmd::CONNECTION_LIST => admission::with_stats(
+            connection_list_handler::handle_connection_list(&req, connections).await,
+            &listener.admission.stats(),
+        ),
         cmd::SESSION_KILL => session_kill_handler::handle_session_kill(&req, sessions).await,
         cmd::SESSION_SUSPEND => session_suspend_handler::handle_session_suspend(&req, sessions).await,
         cmd::SESSION_RESUME_SUSPENDED => session_resume_suspended_handler::handle_session_resume_suspended(&req, sessions).await,
+        wmtp_cmd::LINK_REQUEST => linking::handle_link_request(&lifecycle_token, session_manager, pairings),
+        wmtp_cmd::LINK_APPROVE => {
+            let code = req.data.get("code").and_then(Value::as_str);
+            linking::handle_link_approve(&lifecycle_token, code, session_manager, pairings, index, connections)
+        }
         cmd::PING => make_ping_response(start_time),
+        wmtp_cmd::INFO => status::info(&live.config()).to_json(),
+        wmtp_cmd::STATUS => {
+            // Counters and storage health only for admin_users and on the admin listener
+            let detailed = listener.spec.admin
+                || before
+                    .as_ref()
+                    .filter(|s| s.authenticated)
+                    .and_then(|s| s.email.as_deref())
+                    .is_some_and(|email| live.config().is_admin(email));
+            let sources = StatusSources {
+                started: start_time,
+                sessions: session_manager,
+                metrics,
+                health,
+                admission: &listener.admission,
+                maintenance,
+            };
+            status::status(&sources, detailed).to_json()
+        }
         cmd::LATENCY_PING => make_latency_response(start_time),
         cmd::MB_LIST => mb_list_handler::handle_mb_list(&token, sessions, mailbox_repo).await,
         cmd::MAIL_LIST => mail_list_handler::handle_mail_list(&req, sessions, mailbox_repo).await,
@@ -559,5 +1429,182 @@ async fn process_command(
         cmd::ATTACH_UPLOAD_INIT => attach_upload_init_handler::handle_attach_upload_init(&req, sessions, uploads_coll).await,
         cmd::ATTACH_GET => attach_get_handler::handle_attach_get(&req, sessions, uploads_coll, db).await,
         _ => Response::err("UNKNOWN", &format!("Unknown command: {}", command)).to_json(),
+    };
+
+    let parsed = serde_json::from_str::<Value>(&response).ok();
+    let succeeded = parsed
+        .as_ref()
+        .and_then(|v| v.get("status").and_then(Value::as_str))
+        == Some("OK");
+    if let (Some(email), Some(v)) = (auth_email, &parsed) {
+        if v.get("status").and_then(Value::as_str) == Some("OK") {
+            lockouts.record_success(email, remote.ip());
+        } else if v.get("code").and_then(Value::as_u64) == Some(u64::from(codes::AUTH_FAILED)) {
+            if let Some(locked_for) = lockouts.record_failure(email, remote.ip()) {
+                warn!(
+                    "Locking {} out of {email} for {}s after repeated login failures",
+                    remote.ip(),
+                    locked_for.as_secs()
+                );
+            }
+        }
+    }
+
+    // The suspend handlers only answer the request; the session's flag (and
+    // its Suspended/Resumed event) is set through the manager. LINK_REQUEST
+    // suspends through the manager itself.
+    let cause = SessionCause::command(&command);
+    let suspension_reported = succeeded
+        && match command.as_str() {
+            cmd::SESSION_SUSPEND => session_manager.suspend(&lifecycle_token, cause.clone()),
+            cmd::SESSION_RESUME_SUSPENDED => {
+                session_manager.resume(&lifecycle_token, cause.clone())
+            }
+            wmtp_cmd::LINK_REQUEST => true,
+            _ => false,
+        };
+
+    let after_token = parsed
+        .and_then(|v| v.get("session_token").and_then(Value::as_str).map(String::from))
+        .unwrap_or(lifecycle_token);
+    let after = session_manager.get(&after_token);
+    if let Some(session) = &after {
+        if session.remote_ip.is_none() {
+            session_manager.set_remote_ip(&after_token, remote.ip());
+        }
+        let email = session.email.as_deref().filter(|_| session.authenticated);
+        index.bind(&after_token, conn_id, email);
+        bind_connection_info(connections, conn_id, &after_token, email);
+    }
+
+    // Per-user cap under the evict policy: only a successful login makes room
+    if let Some(email) = auth_email.filter(|_| succeeded) {
+        if let Admission::Evicted(kind, evicted) = session_manager.evict_for_user(email, &after_token) {
+            index.notify_evicted(kind, evicted);
+        }
+    }
+
+    // Sessions removed by this command: SESSION_KILL closes every connection
+    // still holding its target; other removals (LOGOUT, AUTH token swap) just
+    // unbind the caller's old token.
+    let killed = target_before.filter(|t| succeeded && !session_manager.exists(&t.token));
+    if let Some(target) = &killed {
+        close_killed_session(&target.token, index, connections);
+    }
+    if let Some(gone) = before.as_ref().filter(|s| !session_manager.exists(&s.token)) {
+        if command == cmd::SESSION_KILL {
+            close_killed_session(&gone.token, index, connections);
+        } else {
+            index.unbind_session(&gone.token);
+            unbind_connection_info(connections, &gone.token);
+        }
+    }
+    if let Some(audit_log) = audit_log {
+        let actor = before.as_ref().or(after.as_ref()).and_then(|s| s.email.as_deref());
+        if let Some(entry) = audit::entry_for_command(&command, &req.data, &response, actor) {
+            audit_log.record_or_log(entry.with_remote(remote));
+        }
+    }
+    if !suspension_reported {
+        session_manager.record_transition(before, after, cause.clone(), succeeded);
+    }
+    if let Some(target) = killed {
+        session_manager.record_transition(Some(target), None, cause, true);
+    }
+
+    response
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::listener::{ListenerSpec, DEFAULT_LISTENER};
+    use crate::session::{SessionEvent, SessionEventKind, SessionObserver, WmtpSession};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        events: Mutex<Vec<SessionEventKind>>,
+    }
+
+    impl SessionObserver for RecordingObserver {
+        fn on_session_event(&self, event: &SessionEvent) {
+            self.events.lock().unwrap().push(event.kind);
+        }
+    }
+
+    // Storage is never reached by the commands these tests send
+    async fn context(session_manager: Arc<SessionManager>) -> ConnectionContext {
+        let config = Config::default();
+        let options = ClientOptions::parse("mongodb://127.0.0.1:1").await.unwrap();
+        let db = Client::with_options(options).unwrap().database("wmtp_test");
+        let shutdown = create_shutdown();
+        ConnectionContext {
+            sessions: session_manager.store().clone(),
+            session_manager,
+            live: Arc::new(LiveConfig::new(config.clone())),
+            health: create_health(shutdown.clone()),
+            shutdown,
+            metrics: create_metrics(),
+            audit_log: None,
+            limiter: create_command_limiter(),
+            lockouts: create_lockouts(config.lockout_policy()),
+            maintenance: create_maintenance(),
+            pairings: create_pairing_registry(config.link_code_ttl),
+            index: create_connection_index(),
+            connections: create_connection_store(),
+            start_time: SystemTime::now(),
+            mailbox_repo: Arc::new(MailboxRepository::new(&db)),
+            users_coll: Arc::new(db.collection::<UserDoc>("users")),
+            uploads_coll: db.collection::<PendingUpload>("uploads"),
+            messages_coll: db.collection::<Message>("messages"),
+            directory: Arc::new(MongoDirectory::new(&db)),
+            db: Arc::new(db),
+        }
+    }
+
+    fn listener(config: &Config) -> SharedListener {
+        let addr = "127.0.0.1:4433".parse().unwrap();
+        let admission = admission::create_admission(config.admission_limits());
+        create_listener(ListenerSpec::new(DEFAULT_LISTENER, addr), &admission)
+    }
+
+    #[tokio::test]
+    async fn test_session_suspend_emits_events() {
+        let observer = Arc::new(RecordingObserver::default());
+        let session_manager = Arc::new(
+            SessionManager::new(create_session_store(), 3600).with_observer(observer.clone()),
+        );
+        session_manager.insert(WmtpSession::new_authenticated(
+            "tok".to_string(),
+            "u@test.com".to_string(),
+        ));
+        let ctx = context(session_manager.clone()).await;
+        let listener = listener(&ctx.live.config());
+        let remote: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+
+        let suspend = r#"{"cmd":"SESSION_SUSPEND","data":{"session_token":"tok"}}"#;
+        let response = process_command(suspend, &ctx, &listener, 1, remote).await;
+        assert!(response.contains(r#""status":"OK""#), "{response}");
+        assert!(session_manager.get("tok").unwrap().suspended);
+
+        let resume = r#"{"cmd":"SESSION_RESUME_SUSPENDED","data":{"session_token":"tok"}}"#;
+        let response = process_command(resume, &ctx, &listener, 1, remote).await;
+        assert!(response.contains(r#""status":"OK""#), "{response}");
+        assert!(!session_manager.get("tok").unwrap().suspended);
+
+        // Created by the insert above; each transition reported exactly once
+        assert_eq!(
+            *observer.events.lock().unwrap(),
+            vec![
+                SessionEventKind::Created,
+                SessionEventKind::Suspended,
+                SessionEventKind::Resumed,
+            ]
+        );
     }
 }
@@ -4,9 +4,16 @@
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 
+use crate::commands::{cmd, notices, Response};
+use crate::error::{codes, WmtpResult};
+
 /// Represents a WMTP session
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WmtpSession {
@@ -29,6 +36,14 @@ pub struct WmtpSession {
     /// Last activity timestamp
     #[serde(skip)]
     pub last_activity: Option<Instant>,
+
+    /// Whether the session is suspended (kept, but not usable until resumed)
+    #[serde(default)]
+    pub suspended: bool,
+
+    /// Remote IP of the connection that created the session
+    #[serde(default)]
+    pub remote_ip: Option<IpAddr>,
 }
 
 impl WmtpSession {
@@ -42,6 +57,8 @@ impl WmtpSession {
             username: None,
             created_at: Some(now),
             last_activity: Some(now),
+            suspended: false,
+            remote_ip: None,
         }
     }
 
@@ -56,6 +73,8 @@ impl WmtpSession {
             username,
             created_at: Some(now),
             last_activity: Some(now),
+            suspended: false,
+            remote_ip: None,
         }
     }
 
@@ -64,6 +83,11 @@ impl WmtpSession {
         self.last_activity = Some(Instant::now());
     }
 
+    /// Guest = not (yet) authenticated
+    pub fn is_guest(&self) -> bool {
+        !self.authenticated
+    }
+
     /// Check if session has expired
     pub fn is_expired(&self, timeout: Duration) -> bool {
         match self.last_activity {
@@ -95,10 +119,184 @@ pub fn create_session_store() -> SessionStore {
     Arc::new(Mutex::new(HashMap::new()))
 }
 
+// ============================================================================
+// LIFECYCLE EVENTS
+// ============================================================================
+
+/// Session lifecycle transition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEventKind {
+    Created,
+    Authenticated,
+    Resumed,
+    Suspended,
+    Expired,
+    Killed,
+}
+
+/// What triggered a lifecycle transition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionCause {
+    /// A client command (command name, e.g. "AUTH")
+    Command(String),
+
+    /// Session was idle longer than the session timeout
+    IdleTimeout,
+
+    /// Server-side action (admin tool, shutdown, ...)
+    Server(String),
+}
+
+impl SessionCause {
+    /// Cause for a client command
+    pub fn command(name: &str) -> Self {
+        SessionCause::Command(name.to_string())
+    }
+}
+
+/// Event delivered to session observers
+#[derive(Debug, Clone)]
+pub struct SessionEvent {
+    /// Transition that happened
+    pub kind: SessionEventKind,
+
+    /// Session snapshot (state after the transition, or last state if removed)
+    pub session: WmtpSession,
+
+    /// What triggered the transition
+    pub cause: SessionCause,
+}
+
+/// Observer notified on every session lifecycle transition
+///
+/// Observers are called synchronously after the session store lock has been
+/// released, so they may use the `SessionManager` themselves. Long-running
+/// work should be handed off to a task.
+pub trait SessionObserver: Send + Sync {
+    fn on_session_event(&self, event: &SessionEvent);
+}
+
+/// Observer that logs every transition via `tracing`
+pub struct TracingObserver;
+
+impl SessionObserver for TracingObserver {
+    fn on_session_event(&self, event: &SessionEvent) {
+        tracing::info!(
+            session_id = %crate::logging::session_id(&event.session.token),
+            "Session {:?}: user={} cause={:?}",
+            event.kind,
+            event.session.email.as_deref().unwrap_or("-"),
+            event.cause,
+        );
+    }
+}
+
+// ============================================================================
+// CONCURRENT SESSION LIMITS
+// ============================================================================
+
+/// What to do when a new session would exceed a cap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SessionLimitPolicy {
+    /// Refuse the new session
+    #[serde(rename = "reject")]
+    RejectNewest,
+
+    /// Remove the least-recently-active existing session(s) to make room
+    #[serde(rename = "evict")]
+    EvictLeastRecentlyActive,
+}
+
+impl FromStr for SessionLimitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "reject" | "reject_newest" => Ok(SessionLimitPolicy::RejectNewest),
+            "evict" | "evict_lru" => Ok(SessionLimitPolicy::EvictLeastRecentlyActive),
+            other => Err(format!("unknown session limit policy: {other}")),
+        }
+    }
+}
+
+/// Concurrent session caps (0 = unlimited)
+#[derive(Debug, Clone, Copy)]
+pub struct SessionLimits {
+    /// Max authenticated sessions per email
+    pub max_per_user: usize,
+
+    /// Max sessions (any state) per remote IP
+    pub max_per_ip: usize,
+
+    /// Policy applied when a cap is hit
+    pub policy: SessionLimitPolicy,
+}
+
+impl Default for SessionLimits {
+    fn default() -> Self {
+        Self {
+            max_per_user: 0,
+            max_per_ip: 0,
+            policy: SessionLimitPolicy::RejectNewest,
+        }
+    }
+}
+
+/// Which cap was hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionLimitKind {
+    PerUser,
+    PerIp,
+}
+
+impl SessionLimitKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SessionLimitKind::PerUser => "per_user",
+            SessionLimitKind::PerIp => "per_ip",
+        }
+    }
+}
+
+/// Outcome of a limit check
+#[derive(Debug, Clone)]
+pub enum Admission {
+    /// Under the cap
+    Allowed,
+
+    /// Over the cap, new session refused
+    Rejected(SessionLimitKind),
+
+    /// Over the cap, these sessions were removed to make room
+    Evicted(SessionLimitKind, Vec<WmtpSession>),
+}
+
+impl Admission {
+    /// Error response for a rejected session
+    pub fn rejection_response(command: &str, kind: SessionLimitKind) -> Response {
+        Response::err(command, "SESSION_LIMIT_EXCEEDED", codes::SESSION_LIMIT_EXCEEDED)
+            .with_data(serde_json::json!({ "limit": kind.as_str() }))
+    }
+
+    /// Push notice sent to the connection of an evicted session
+    pub fn eviction_notice(kind: SessionLimitKind, session: &WmtpSession) -> Response {
+        notices::session_evicted(&session.token, kind.as_str())
+    }
+}
+
+/// Settings that can be changed while the server runs (config reload)
+#[derive(Debug, Clone, Copy)]
+struct Tunables {
+    session_timeout: Duration,
+    guest_timeout: Duration,
+    limits: SessionLimits,
+}
+
 /// Session manager with helper operations
 pub struct SessionManager {
     store: SessionStore,
-    session_timeout: Duration,
+    observers: Vec<Arc<dyn SessionObserver>>,
+    tunables: RwLock<Tunables>,
 }
 
 impl SessionManager {
@@ -106,14 +304,89 @@ impl SessionManager {
     pub fn new(store: SessionStore, timeout_secs: u64) -> Self {
         Self {
             store,
-            session_timeout: Duration::from_secs(timeout_secs),
+            observers: Vec::new(),
+            tunables: RwLock::new(Tunables {
+                session_timeout: Duration::from_secs(timeout_secs),
+                guest_timeout: Duration::from_secs(timeout_secs),
+                limits: SessionLimits::default(),
+            }),
         }
     }
 
-    /// Insert a new session
+    /// Set a (usually shorter) idle timeout for guest sessions
+    pub fn with_guest_timeout(self, timeout_secs: u64) -> Self {
+        self.tunables.write().unwrap().guest_timeout = Duration::from_secs(timeout_secs);
+        self
+    }
+
+    /// Set concurrent session caps
+    pub fn with_limits(self, limits: SessionLimits) -> Self {
+        self.set_limits(limits);
+        self
+    }
+
+    /// Change the idle timeouts of a running manager (applies to existing sessions too)
+    pub fn set_timeouts(&self, session_secs: u64, guest_secs: u64) {
+        let mut tunables = self.tunables.write().unwrap();
+        tunables.session_timeout = Duration::from_secs(session_secs);
+        tunables.guest_timeout = Duration::from_secs(guest_secs);
+    }
+
+    /// Change the concurrent session caps of a running manager
+    pub fn set_limits(&self, limits: SessionLimits) {
+        self.tunables.write().unwrap().limits = limits;
+    }
+
+    /// Current concurrent session caps
+    pub fn limits(&self) -> SessionLimits {
+        self.tunables.read().unwrap().limits
+    }
+
+    /// Idle timeout that applies to a session
+    fn timeout_for(&self, session: &WmtpSession) -> Duration {
+        let tunables = self.tunables.read().unwrap();
+        if session.is_guest() {
+            tunables.guest_timeout
+        } else {
+            tunables.session_timeout
+        }
+    }
+
+    /// Register a lifecycle observer (call at startup, before sharing the manager)
+    pub fn with_observer(mut self, observer: Arc<dyn SessionObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Get the underlying session store
+    pub fn store(&self) -> &SessionStore {
+        &self.store
+    }
+
+    /// Notify all observers of a transition
+    fn emit(&self, kind: SessionEventKind, session: WmtpSession, cause: SessionCause) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let event = SessionEvent { kind, session, cause };
+        for observer in &self.observers {
+            observer.on_session_event(&event);
+        }
+    }
+
+    /// Insert a new session (emits `Created`, caused by `INIT`)
     pub fn insert(&self, session: WmtpSession) {
-        let mut store = self.store.lock().unwrap();
-        store.insert(session.token.clone(), session);
+        self.insert_with_cause(session, SessionCause::command(cmd::INIT));
+    }
+
+    /// Insert a new session with an explicit cause (emits `Created`)
+    pub fn insert_with_cause(&self, session: WmtpSession, cause: SessionCause) {
+        let snapshot = session.clone();
+        {
+            let mut store = self.store.lock().unwrap();
+            store.insert(session.token.clone(), session);
+        }
+        self.emit(SessionEventKind::Created, snapshot, cause);
     }
 
     /// Get a session by token (cloned)
@@ -139,32 +412,276 @@ impl SessionManager {
         }
     }
 
-    /// Authenticate a session
+    /// Authenticate a session (emits `Authenticated`, caused by `AUTH`)
     pub fn authenticate(&self, token: &str, email: String) -> bool {
+        self.authenticate_with_cause(token, email, SessionCause::command(cmd::AUTH))
+    }
+
+    /// Authenticate a session with an explicit cause (emits `Authenticated`)
+    pub fn authenticate_with_cause(
+        &self,
+        token: &str,
+        email: String,
+        cause: SessionCause,
+    ) -> bool {
+        let snapshot = {
+            let mut store = self.store.lock().unwrap();
+            match store.get_mut(token) {
+                Some(session) => {
+                    session.authenticated = true;
+                    session.username = email.split('@').next().map(String::from);
+                    session.email = Some(email);
+                    session.touch();
+                    session.clone()
+                }
+                None => return false,
+            }
+        };
+        self.emit(SessionEventKind::Authenticated, snapshot, cause);
+        true
+    }
+
+    /// Suspend a session (emits `Suspended`)
+    pub fn suspend(&self, token: &str, cause: SessionCause) -> bool {
+        self.set_suspended(token, true, cause)
+    }
+
+    /// Resume a suspended session (emits `Resumed`)
+    pub fn resume(&self, token: &str, cause: SessionCause) -> bool {
+        self.set_suspended(token, false, cause)
+    }
+
+    fn set_suspended(&self, token: &str, suspended: bool, cause: SessionCause) -> bool {
+        let snapshot = {
+            let mut store = self.store.lock().unwrap();
+            match store.get_mut(token) {
+                Some(session) => {
+                    session.suspended = suspended;
+                    session.touch();
+                    session.clone()
+                }
+                None => return false,
+            }
+        };
+        let kind = if suspended {
+            SessionEventKind::Suspended
+        } else {
+            SessionEventKind::Resumed
+        };
+        self.emit(kind, snapshot, cause);
+        true
+    }
+
+    /// Replace a guest session with an authenticated one under a new token
+    ///
+    /// Keeps creation time and remote IP, clears the suspended flag, and
+    /// emits `Authenticated` for the new session.
+    pub fn promote(
+        &self,
+        old_token: &str,
+        new_token: &str,
+        email: String,
+        cause: SessionCause,
+    ) -> Option<WmtpSession> {
+        let snapshot = {
+            let mut store = self.store.lock().unwrap();
+            let old = store.remove(old_token)?;
+            let mut session = WmtpSession::new_authenticated(new_token.to_string(), email);
+            session.created_at = old.created_at;
+            session.remote_ip = old.remote_ip;
+            store.insert(new_token.to_string(), session.clone());
+            session
+        };
+        self.emit(SessionEventKind::Authenticated, snapshot.clone(), cause);
+        Some(snapshot)
+    }
+
+    /// Remove a session (emits `Killed`, caused by `LOGOUT`)
+    pub fn remove(&self, token: &str) -> Option<WmtpSession> {
+        self.remove_with_cause(token, SessionCause::command(cmd::LOGOUT))
+    }
+
+    /// Remove a session with an explicit cause (emits `Killed`)
+    pub fn remove_with_cause(&self, token: &str, cause: SessionCause) -> Option<WmtpSession> {
+        let removed = {
+            let mut store = self.store.lock().unwrap();
+            store.remove(token)
+        };
+        if let Some(session) = &removed {
+            self.emit(SessionEventKind::Killed, session.clone(), cause);
+        }
+        removed
+    }
+
+    /// Clean up expired sessions (emits `Expired` for each)
+    pub fn cleanup_expired(&self) -> usize {
+        let expired: Vec<WmtpSession> = {
+            let mut store = self.store.lock().unwrap();
+            let tokens: Vec<String> = store
+                .values()
+                .filter(|s| s.is_expired(self.timeout_for(s)))
+                .map(|s| s.token.clone())
+                .collect();
+            tokens.iter().filter_map(|t| store.remove(t)).collect()
+        };
+        let count = expired.len();
+        for session in expired {
+            self.emit(SessionEventKind::Expired, session, SessionCause::IdleTimeout);
+        }
+        count
+    }
+
+    /// Record the remote IP a session was created from
+    pub fn set_remote_ip(&self, token: &str, ip: IpAddr) -> bool {
         let mut store = self.store.lock().unwrap();
         if let Some(session) = store.get_mut(token) {
-            session.authenticated = true;
-            session.username = email.split('@').next().map(String::from);
-            session.email = Some(email);
-            session.touch();
+            session.remote_ip = Some(ip);
             true
         } else {
             false
         }
     }
 
-    /// Remove a session
-    pub fn remove(&self, token: &str) -> Option<WmtpSession> {
-        let mut store = self.store.lock().unwrap();
-        store.remove(token)
+    /// Check the per-IP cap before creating a new session (`INIT`)
+    pub fn admit_ip(&self, ip: IpAddr) -> Admission {
+        let max = self.limits().max_per_ip;
+        self.admit(SessionLimitKind::PerIp, max, true, None, |s| s.remote_ip == Some(ip))
     }
 
-    /// Clean up expired sessions
-    pub fn cleanup_expired(&self) -> usize {
-        let mut store = self.store.lock().unwrap();
-        let before = store.len();
-        store.retain(|_, session| !session.is_expired(self.session_timeout));
-        before - store.len()
+    /// Check the per-user cap before authenticating `token` as `email` (`AUTH`)
+    ///
+    /// The session being authenticated does not count against the cap. Only
+    /// rejects; under the evict policy room is made by `evict_for_user` once
+    /// the credentials have been accepted.
+    pub fn admit_user(&self, email: &str, token: &str) -> Admission {
+        self.admit_user_inner(email, token, false, None)
+    }
+
+    /// Evict `email`'s least recently active sessions after `token` has
+    /// authenticated, if the per-user cap is exceeded and the policy is evict
+    pub fn evict_for_user(&self, email: &str, token: &str) -> Admission {
+        self.admit_user_inner(email, token, true, None)
+    }
+
+    /// `admit_user`, where session `keep` must survive the eviction
+    ///
+    /// Used by `LINK_APPROVE` so the approving session is never evicted:
+    /// rejected when the cap can only be met by evicting `keep`.
+    pub fn admit_user_keeping(&self, email: &str, token: &str, keep: &str) -> Admission {
+        self.admit_user_inner(email, token, false, Some(keep))
+    }
+
+    /// `evict_for_user`, never evicting session `keep`
+    pub fn evict_for_user_keeping(&self, email: &str, token: &str, keep: &str) -> Admission {
+        self.admit_user_inner(email, token, true, Some(keep))
+    }
+
+    fn admit_user_inner(
+        &self,
+        email: &str,
+        token: &str,
+        evict: bool,
+        keep: Option<&str>,
+    ) -> Admission {
+        let email = email.trim().to_lowercase();
+        let max = self.limits().max_per_user;
+        self.admit(SessionLimitKind::PerUser, max, evict, keep, |s| {
+            s.authenticated
+                && s.token != token
+                && s.email.as_deref().map(|e| e.to_lowercase()) == Some(email.clone())
+        })
+    }
+
+    fn admit<F>(
+        &self,
+        kind: SessionLimitKind,
+        max: usize,
+        evict: bool,
+        keep: Option<&str>,
+        matches: F,
+    ) -> Admission
+    where
+        F: Fn(&WmtpSession) -> bool,
+    {
+        if max == 0 {
+            return Admission::Allowed;
+        }
+
+        let evicted: Vec<WmtpSession> = {
+            let mut store = self.store.lock().unwrap();
+            let mut held: Vec<&WmtpSession> = store.values().filter(|s| matches(s)).collect();
+            if held.len() < max {
+                return Admission::Allowed;
+            }
+            if self.limits().policy == SessionLimitPolicy::RejectNewest {
+                return Admission::Rejected(kind);
+            }
+
+            // Oldest activity first; make room for exactly one new session
+            let excess = held.len() + 1 - max;
+            held.retain(|s| Some(s.token.as_str()) != keep);
+            if held.len() < excess {
+                return Admission::Rejected(kind);
+            }
+            if !evict {
+                return Admission::Allowed;
+            }
+            held.sort_by_key(|s| s.last_activity);
+            let victims: Vec<String> = held
+                .iter()
+                .take(excess)
+                .map(|s| s.token.clone())
+                .collect();
+            victims.iter().filter_map(|t| store.remove(t)).collect()
+        };
+
+        for session in &evicted {
+            self.emit(
+                SessionEventKind::Killed,
+                session.clone(),
+                SessionCause::Server(format!("evicted: {} limit", kind.as_str())),
+            );
+        }
+        Admission::Evicted(kind, evicted)
+    }
+
+    /// Emit events for a change made directly on the store
+    ///
+    /// Command handlers operate on the raw `SessionStore`; the dispatcher takes
+    /// a snapshot before and after running one and reports the difference here.
+    /// A successful `RESUME` reports `Resumed` even if the session's state did
+    /// not change.
+    pub fn record_transition(
+        &self,
+        before: Option<WmtpSession>,
+        after: Option<WmtpSession>,
+        cause: SessionCause,
+        succeeded: bool,
+    ) {
+        match (before, after) {
+            (None, Some(after)) => {
+                let authenticated = after.authenticated;
+                self.emit(SessionEventKind::Created, after.clone(), cause.clone());
+                if authenticated {
+                    self.emit(SessionEventKind::Authenticated, after, cause);
+                }
+            }
+            (Some(before), None) => {
+                self.emit(SessionEventKind::Killed, before, cause);
+            }
+            (Some(before), Some(after)) => {
+                if !before.authenticated && after.authenticated {
+                    self.emit(SessionEventKind::Authenticated, after, cause);
+                } else if !before.suspended && after.suspended {
+                    self.emit(SessionEventKind::Suspended, after, cause);
+                } else if (before.suspended && !after.suspended)
+                    || (succeeded && cause == SessionCause::command(cmd::RESUME))
+                {
+                    self.emit(SessionEventKind::Resumed, after, cause);
+                }
+            }
+            (None, None) => {}
+        }
     }
 
     /// Get count of active sessions
@@ -184,6 +701,100 @@ impl SessionManager {
         let store = self.store.lock().unwrap();
         store.values().cloned().collect()
     }
+
+    /// Save every session to `path` (JSON, owner-only) for a restarted server
+    pub fn save_to(&self, path: &Path) -> WmtpResult<usize> {
+        let sessions = self.list_all();
+        let json = serde_json::to_vec(&sessions)?;
+
+        let mut tmp_name = path.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp = PathBuf::from(tmp_name);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp)?;
+        file.write_all(&json)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(sessions.len())
+    }
+
+    /// Restore sessions written by `save_to`, then delete the file
+    ///
+    /// The file is consumed so a later crash cannot bring back sessions
+    /// that were logged out in the meantime. Idle timers restart from now.
+    /// Sessions over the current per-user or per-IP cap are dropped, whatever
+    /// the policy. Each restored session emits `Created` and `Resumed`.
+    /// A missing file restores nothing.
+    pub fn restore_from(&self, path: &Path) -> WmtpResult<usize> {
+        let json = match std::fs::read(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let sessions: Vec<WmtpSession> = serde_json::from_slice(&json)?;
+        std::fs::remove_file(path)?;
+
+        let limits = self.limits();
+        let now = Instant::now();
+        let mut restored = Vec::with_capacity(sessions.len());
+        {
+            let mut store = self.store.lock().unwrap();
+            for mut session in sessions {
+                if let Some(kind) = over_limit(&store, &session, limits) {
+                    tracing::warn!(
+                        session_id = %crate::logging::session_id(&session.token),
+                        "Not restoring session: {} limit reached",
+                        kind.as_str(),
+                    );
+                    continue;
+                }
+                session.created_at = Some(now);
+                session.last_activity = Some(now);
+                store.insert(session.token.clone(), session.clone());
+                restored.push(session);
+            }
+        }
+
+        let count = restored.len();
+        let cause = SessionCause::Server("restored".to_string());
+        for session in restored {
+            self.emit(SessionEventKind::Created, session.clone(), cause.clone());
+            self.emit(SessionEventKind::Resumed, session, cause.clone());
+        }
+        Ok(count)
+    }
+}
+
+// Cap a restored `session` would exceed next to the sessions in `store`
+fn over_limit(
+    store: &HashMap<String, WmtpSession>,
+    session: &WmtpSession,
+    limits: SessionLimits,
+) -> Option<SessionLimitKind> {
+    let full = |max: usize, matches: &dyn Fn(&WmtpSession) -> bool| {
+        max > 0 && store.values().filter(|s| matches(s)).count() >= max
+    };
+    if let Some(ip) = session.remote_ip {
+        if full(limits.max_per_ip, &|s| s.remote_ip == Some(ip)) {
+            return Some(SessionLimitKind::PerIp);
+        }
+    }
+    if let Some(email) = session.email.as_deref().filter(|_| session.authenticated) {
+        let email = email.to_lowercase();
+        let same_user = |s: &WmtpSession| {
+            s.authenticated && s.email.as_deref().map(str::to_lowercase).as_deref() == Some(&email)
+        };
+        if full(limits.max_per_user, &same_user) {
+            return Some(SessionLimitKind::PerUser);
+        }
+    }
+    None
 }
 
 // ============================================================================
@@ -261,4 +872,250 @@ mod tests {
         assert_eq!(manager.active_count(), 2);
         assert_eq!(manager.authenticated_count(), 1);
     }
+
+    struct RecordingObserver {
+        events: Mutex<Vec<(SessionEventKind, SessionCause)>>,
+    }
+
+    impl SessionObserver for RecordingObserver {
+        fn on_session_event(&self, event: &SessionEvent) {
+            self.events.lock().unwrap().push((event.kind, event.cause.clone()));
+        }
+    }
+
+    fn recording_manager(timeout_secs: u64) -> (SessionManager, Arc<RecordingObserver>) {
+        let observer = Arc::new(RecordingObserver { events: Mutex::new(Vec::new()) });
+        let manager = SessionManager::new(create_session_store(), timeout_secs)
+            .with_observer(observer.clone());
+        (manager, observer)
+    }
+
+    #[test]
+    fn test_lifecycle_events() {
+        let (manager, observer) = recording_manager(3600);
+
+        manager.insert(WmtpSession::new_ephemeral("t1".to_string()));
+        manager.authenticate("t1", "user@test.com".to_string());
+        manager.suspend("t1", SessionCause::command("SESSION_SUSPEND"));
+        manager.resume("t1", SessionCause::command("SESSION_RESUME_SUSPENDED"));
+        manager.remove_with_cause("t1", SessionCause::Server("admin".to_string()));
+
+        let kinds: Vec<SessionEventKind> =
+            observer.events.lock().unwrap().iter().map(|(k, _)| *k).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                SessionEventKind::Created,
+                SessionEventKind::Authenticated,
+                SessionEventKind::Suspended,
+                SessionEventKind::Resumed,
+                SessionEventKind::Killed,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expired_events() {
+        let (manager, observer) = recording_manager(0);
+
+        let mut session = WmtpSession::new_ephemeral("t1".to_string());
+        session.last_activity = Some(Instant::now() - Duration::from_secs(5));
+        manager.insert(session);
+
+        assert_eq!(manager.cleanup_expired(), 1);
+        assert!(!manager.exists("t1"));
+
+        let events = observer.events.lock().unwrap();
+        assert_eq!(events.last(), Some(&(SessionEventKind::Expired, SessionCause::IdleTimeout)));
+    }
+
+    #[test]
+    fn test_record_transition() {
+        let (manager, observer) = recording_manager(3600);
+        let ephemeral = WmtpSession::new_ephemeral("t1".to_string());
+        let authed = WmtpSession::new_authenticated("t2".to_string(), "user@test.com".to_string());
+
+        manager.record_transition(None, Some(ephemeral.clone()), SessionCause::command("INIT"), true);
+        manager.record_transition(Some(ephemeral), Some(authed.clone()), SessionCause::command("AUTH"), true);
+        manager.record_transition(Some(authed.clone()), Some(authed.clone()), SessionCause::command("PING"), true);
+        manager.record_transition(Some(authed.clone()), Some(authed.clone()), SessionCause::command("RESUME"), false);
+        manager.record_transition(Some(authed.clone()), Some(authed.clone()), SessionCause::command("RESUME"), true);
+        manager.record_transition(Some(authed), None, SessionCause::command("LOGOUT"), true);
+
+        let kinds: Vec<SessionEventKind> =
+            observer.events.lock().unwrap().iter().map(|(k, _)| *k).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                SessionEventKind::Created,
+                SessionEventKind::Authenticated,
+                SessionEventKind::Resumed,
+                SessionEventKind::Killed,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_guest_timeout() {
+        let manager = SessionManager::new(create_session_store(), 3600).with_guest_timeout(10);
+        let idle = Some(Instant::now() - Duration::from_secs(60));
+
+        let mut guest = WmtpSession::new_ephemeral("guest".to_string());
+        guest.last_activity = idle;
+        let mut user = WmtpSession::new_authenticated("user".to_string(), "u@test.com".to_string());
+        user.last_activity = idle;
+        manager.insert(guest);
+        manager.insert(user);
+
+        assert_eq!(manager.cleanup_expired(), 1);
+        assert!(!manager.exists("guest"));
+        assert!(manager.exists("user"));
+
+        // Timeouts can be tightened on a running manager
+        manager.set_timeouts(30, 10);
+        assert_eq!(manager.cleanup_expired(), 1);
+        assert!(!manager.exists("user"));
+    }
+
+    #[test]
+    fn test_save_and_restore() {
+        let path = std::env::temp_dir().join(format!("wmtp-sessions-{}.json", std::process::id()));
+        let manager = SessionManager::new(create_session_store(), 3600);
+        manager.insert(WmtpSession::new_authenticated("t1".to_string(), "u@test.com".to_string()));
+        manager.insert(WmtpSession::new_ephemeral("t2".to_string()));
+        assert_eq!(manager.save_to(&path).unwrap(), 2);
+
+        let restarted = SessionManager::new(create_session_store(), 3600);
+        assert_eq!(restarted.restore_from(&path).unwrap(), 2);
+        let session = restarted.get("t1").unwrap();
+        assert!(session.authenticated);
+        assert!(!session.is_expired(Duration::from_secs(60)));
+
+        // Consumed: a second restore finds nothing
+        assert!(!path.exists());
+        assert_eq!(restarted.restore_from(&path).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_restore_respects_limits_and_emits_events() {
+        let path = std::env::temp_dir().join(format!("wmtp-limited-{}.json", std::process::id()));
+        let manager = SessionManager::new(create_session_store(), 3600);
+        for token in ["a", "b", "c"] {
+            manager.insert(WmtpSession::new_authenticated(
+                token.to_string(),
+                "u@test.com".to_string(),
+            ));
+        }
+        manager.save_to(&path).unwrap();
+        let mut tmp_name = path.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        assert!(!PathBuf::from(tmp_name).exists());
+
+        let (restarted, observer) = recording_manager(3600);
+        let restarted = restarted.with_limits(SessionLimits {
+            max_per_user: 2,
+            ..SessionLimits::default()
+        });
+        assert_eq!(restarted.restore_from(&path).unwrap(), 2);
+        assert_eq!(restarted.active_count(), 2);
+
+        let restored = SessionCause::Server("restored".to_string());
+        let events = observer.events.lock().unwrap();
+        assert_eq!(
+            *events,
+            vec![
+                (SessionEventKind::Created, restored.clone()),
+                (SessionEventKind::Resumed, restored.clone()),
+                (SessionEventKind::Created, restored.clone()),
+                (SessionEventKind::Resumed, restored),
+            ]
+        );
+    }
+
+    fn limited_manager(policy: SessionLimitPolicy) -> SessionManager {
+        SessionManager::new(create_session_store(), 3600).with_limits(SessionLimits {
+            max_per_user: 2,
+            max_per_ip: 2,
+            policy,
+        })
+    }
+
+    #[test]
+    fn test_limit_policy_parse() {
+        assert_eq!("reject".parse(), Ok(SessionLimitPolicy::RejectNewest));
+        assert_eq!("EVICT".parse(), Ok(SessionLimitPolicy::EvictLeastRecentlyActive));
+        assert!("drop".parse::<SessionLimitPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_per_user_limit_reject() {
+        let manager = limited_manager(SessionLimitPolicy::RejectNewest);
+        manager.insert(WmtpSession::new_authenticated("a".to_string(), "u@test.com".to_string()));
+        assert!(matches!(manager.admit_user("U@test.com", "b"), Admission::Allowed));
+
+        manager.insert(WmtpSession::new_authenticated("b".to_string(), "u@test.com".to_string()));
+        assert!(matches!(
+            manager.admit_user("u@test.com", "c"),
+            Admission::Rejected(SessionLimitKind::PerUser)
+        ));
+        // Re-authenticating an existing session is not a new session
+        assert!(matches!(manager.admit_user("u@test.com", "b"), Admission::Allowed));
+        assert_eq!(manager.active_count(), 2);
+    }
+
+    #[test]
+    fn test_per_ip_limit_evicts_least_recently_active() {
+        let manager = limited_manager(SessionLimitPolicy::EvictLeastRecentlyActive);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let mut old = WmtpSession::new_ephemeral("old".to_string());
+        old.last_activity = Some(Instant::now() - Duration::from_secs(30));
+        old.remote_ip = Some(ip);
+        manager.insert(old);
+
+        let mut recent = WmtpSession::new_ephemeral("recent".to_string());
+        recent.remote_ip = Some(ip);
+        manager.insert(recent);
+
+        match manager.admit_ip(ip) {
+            Admission::Evicted(SessionLimitKind::PerIp, evicted) => {
+                assert_eq!(evicted.len(), 1);
+                assert_eq!(evicted[0].token, "old");
+            }
+            other => panic!("expected eviction, got {:?}", other),
+        }
+        assert!(!manager.exists("old"));
+        assert!(manager.exists("recent"));
+        assert!(matches!(manager.admit_ip("10.0.0.2".parse().unwrap()), Admission::Allowed));
+    }
+
+    #[test]
+    fn test_per_user_limit_evicts_only_after_auth() {
+        let manager = limited_manager(SessionLimitPolicy::EvictLeastRecentlyActive);
+        let mut old = WmtpSession::new_authenticated("old".to_string(), "u@test.com".to_string());
+        old.last_activity = Some(Instant::now() - Duration::from_secs(30));
+        manager.insert(old);
+        manager.insert(WmtpSession::new_authenticated(
+            "recent".to_string(),
+            "u@test.com".to_string(),
+        ));
+
+        // The check before AUTH runs never removes anything
+        assert!(matches!(manager.admit_user("u@test.com", "new"), Admission::Allowed));
+        assert_eq!(manager.active_count(), 2);
+
+        manager.insert_with_cause(
+            WmtpSession::new_authenticated("new".to_string(), "u@test.com".to_string()),
+            SessionCause::command("AUTH"),
+        );
+        match manager.evict_for_user("u@test.com", "new") {
+            Admission::Evicted(SessionLimitKind::PerUser, evicted) => {
+                assert_eq!(evicted.len(), 1);
+                assert_eq!(evicted[0].token, "old");
+            }
+            other => panic!("expected eviction, got {:?}", other),
+        }
+        assert!(manager.exists("recent"));
+        assert!(manager.exists("new"));
+    }
 }
@@ -0,0 +1,132 @@
+//! Graceful shutdown
+//!
+//! On `SIGTERM` the server stops accepting connections, tells every client
+//! it is going away (with a reconnect hint), refuses new commands, and waits
+//! up to a deadline for in-flight commands and attachment uploads before
+//! saving session state and exiting.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+use crate::commands::Response;
+use crate::error::codes;
+
+/// Seconds clients are told to wait before reconnecting
+pub const RECONNECT_AFTER_SECS: u64 = 5;
+
+/// Drain state and in-flight work of the server
+#[derive(Default)]
+pub struct Shutdown {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+/// Thread-safe shutdown handle type
+pub type SharedShutdown = Arc<Shutdown>;
+
+/// Create a shutdown handle
+pub fn create_shutdown() -> SharedShutdown {
+    Arc::new(Shutdown::default())
+}
+
+/// Marks one command or upload as in flight until dropped
+pub struct InFlight {
+    shutdown: SharedShutdown,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.shutdown.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shutdown.idle.notify_waiters();
+        }
+    }
+}
+
+impl Shutdown {
+    /// Start draining; returns false if already draining
+    pub fn begin(&self) -> bool {
+        !self.draining.swap(true, Ordering::SeqCst)
+    }
+
+    /// Whether the server is shutting down
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Track a unit of work (command, upload) until the guard is dropped
+    pub fn track(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight { shutdown: self.clone() }
+    }
+
+    /// Number of commands and uploads still running
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Wait until no work is in flight, at most `deadline`
+    ///
+    /// Returns true if everything finished in time.
+    pub async fn wait_idle(&self, deadline: Duration) -> bool {
+        let wait = async {
+            loop {
+                let idle = self.idle.notified();
+                if self.in_flight() == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        };
+        tokio::time::timeout(deadline, wait).await.is_ok()
+    }
+
+    /// Error response for a command received while draining
+    pub fn refusal(command: &str) -> Response {
+        Response::err(command, "SERVER_SHUTTING_DOWN", codes::SERVICE_UNAVAILABLE)
+            .with_data(serde_json::json!({ "retry_after": RECONNECT_AFTER_SECS }))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain_waits_for_in_flight_work() {
+        let shutdown = create_shutdown();
+        assert!(!shutdown.is_draining());
+
+        let work = shutdown.track();
+        let upload = shutdown.track();
+        assert_eq!(shutdown.in_flight(), 2);
+
+        assert!(shutdown.begin());
+        assert!(!shutdown.begin());
+        assert!(shutdown.is_draining());
+
+        drop(work);
+        assert!(!shutdown.wait_idle(Duration::from_millis(20)).await);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(upload);
+        });
+        assert!(shutdown.wait_idle(Duration::from_secs(5)).await);
+        assert_eq!(shutdown.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_refusal() {
+        let resp = Shutdown::refusal("MSG_SEND");
+        assert_eq!(resp.status, "ERR");
+        assert_eq!(resp.code, Some(codes::SERVICE_UNAVAILABLE));
+    }
+}
@@ -0,0 +1,195 @@
+//! `INFO` and `STATUS`
+//!
+//! `INFO` describes the server: version, build, protocol version, enabled
+//! features and the limits clients should respect. It is static for a given
+//! configuration and open to guests. `STATUS` reports how the running server
+//! is doing: uptime, session and connection counts and maintenance state for
+//! everyone, plus per-command counters, admission counters and storage health
+//! for `admin_users`.
+
+use std::time::SystemTime;
+
+use serde_json::{json, Value};
+
+use crate::admission::AdmissionControl;
+use crate::commands::{cmd, Response};
+use crate::config::Config;
+use crate::health::Health;
+use crate::maintenance::Maintenance;
+use crate::metrics::Metrics;
+use crate::session::SessionManager;
+
+/// Server name reported by `INFO` and `STATUS`
+pub const SERVER_NAME: &str = "wmtp-server";
+
+/// Version of the protocol described in `docs/PROTOCOL.md`
+pub const PROTOCOL_VERSION: &str = "0.1.0";
+
+/// How the binary was built
+pub fn build_info() -> Value {
+    json!({
+        // Set by the release build (e.g. WMTP_BUILD_COMMIT=$(git rev-parse HEAD))
+        "commit": option_env!("WMTP_BUILD_COMMIT"),
+        "profile": if cfg!(debug_assertions) { "debug" } else { "release" },
+        "target": format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS),
+    })
+}
+
+/// Optional features enabled by `config`
+pub fn features(config: &Config) -> Vec<&'static str> {
+    let mut features = vec![
+        "push_notices",
+        "device_linking",
+        "attachments",
+        "guest_sessions",
+        "session_resume",
+    ];
+    if config.contact_mailbox.is_some() {
+        features.push("contact_mailbox");
+    }
+    if config.session_state_file.is_some() {
+        features.push("sessions_survive_restart");
+    }
+    if config.session_rate > 0 || config.user_rate > 0 {
+        features.push("rate_limits");
+    }
+    if config.auth_max_failures > 0 {
+        features.push("login_lockout");
+    }
+    features
+}
+
+/// `INFO` response
+pub fn info(config: &Config) -> Response {
+    Response::ok(cmd::INFO).with_data(json!({
+        "server": SERVER_NAME,
+        "version": env!("CARGO_PKG_VERSION"),
+        "protocol_version": PROTOCOL_VERSION,
+        "build": build_info(),
+        "features": features(config),
+        "limits": {
+            "max_sessions_per_user": config.max_sessions_per_user,
+            "max_sessions_per_ip": config.max_sessions_per_ip,
+            "max_streams_per_connection": config.max_streams_per_connection,
+            "session_timeout": config.session_timeout,
+            "guest_session_timeout": config.guest_session_timeout,
+            "heartbeat_interval": config.heartbeat_interval,
+            "link_code_ttl": config.link_code_ttl,
+            "session_rate": config.session_rate,
+            "session_burst": config.session_burst,
+            "user_rate": config.user_rate,
+            "user_burst": config.user_burst,
+        },
+        "guest_commands": config.guest_commands,
+    }))
+}
+
+/// What `STATUS` reports on
+pub struct StatusSources<'a> {
+    pub started: SystemTime,
+    pub sessions: &'a SessionManager,
+    pub metrics: &'a Metrics,
+    pub health: &'a Health,
+    pub admission: &'a AdmissionControl,
+    pub maintenance: &'a Maintenance,
+}
+
+/// `STATUS` response; `detailed` adds the admin-only fields
+pub fn status(sources: &StatusSources<'_>, detailed: bool) -> Response {
+    let uptime = sources.started.elapsed().map(|d| d.as_secs()).unwrap_or(0);
+    let window = sources.maintenance.current();
+    let mut data = json!({
+        "server": SERVER_NAME,
+        "version": env!("CARGO_PKG_VERSION"),
+        "state": if window.is_some() { "maintenance" } else { "ok" },
+        "uptime_secs": uptime,
+        "active_sessions": sources.sessions.active_count(),
+        "connections": sources.metrics.connections_open(),
+    });
+    if let Some(window) = &window {
+        data["maintenance"] = window.to_json();
+    }
+    if detailed {
+        let started: chrono::DateTime<chrono::Utc> = sources.started.into();
+        data["detail"] = json!({
+            "started_at": started.to_rfc3339(),
+            "sessions": {
+                "active": sources.sessions.active_count(),
+                "authenticated": sources.sessions.authenticated_count(),
+            },
+            "streams": sources.metrics.streams_open(),
+            "admission": sources.admission.stats(),
+            "commands": sources.metrics.command_counts(),
+            "storage": sources.health.storage().to_json(),
+        });
+    }
+    Response::ok(cmd::STATUS).with_data(data)
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::Check;
+    use crate::session::{create_session_store, WmtpSession};
+    use crate::shutdown::create_shutdown;
+    use std::time::Duration;
+
+    #[test]
+    fn test_info() {
+        let config = Config {
+            contact_mailbox: Some("hello@example.com".to_string()),
+            auth_max_failures: 0,
+            ..Config::default()
+        };
+        let data = info(&config).data.unwrap();
+        assert_eq!(data["protocol_version"], PROTOCOL_VERSION);
+        assert_eq!(data["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            data["limits"]["heartbeat_interval"],
+            config.heartbeat_interval
+        );
+        let features = data["features"].as_array().unwrap();
+        assert!(features.contains(&json!("contact_mailbox")));
+        assert!(!features.contains(&json!("login_lockout")));
+    }
+
+    #[test]
+    fn test_status_detail_is_gated() {
+        let sessions = SessionManager::new(create_session_store(), 3600);
+        sessions.insert(WmtpSession::new_authenticated("t".to_string(), "u@test.com".to_string()));
+        let metrics = Metrics::default();
+        metrics.connection_opened();
+        metrics.observe_command("PING", r#"{"status":"OK"}"#, Duration::ZERO);
+        let health = Health::new(create_shutdown());
+        health.record_storage(Check::pass("ping ok"));
+        let admission = AdmissionControl::new(Config::default().admission_limits());
+        let maintenance = Maintenance::default();
+        let sources = StatusSources {
+            started: SystemTime::now() - Duration::from_secs(90),
+            sessions: &sessions,
+            metrics: &metrics,
+            health: &health,
+            admission: &admission,
+            maintenance: &maintenance,
+        };
+
+        let public = status(&sources, false).data.unwrap();
+        assert_eq!(public["state"], "ok");
+        assert_eq!(public["active_sessions"], 1);
+        assert_eq!(public["connections"], 1);
+        assert!(public["uptime_secs"].as_u64().unwrap() >= 90);
+        assert!(public.get("detail").is_none());
+
+        maintenance.enable("upgrade", None);
+        let detailed = status(&sources, true).data.unwrap();
+        assert_eq!(detailed["state"], "maintenance");
+        assert_eq!(detailed["maintenance"]["reason"], "upgrade");
+        assert_eq!(detailed["detail"]["sessions"]["authenticated"], 1);
+        assert_eq!(detailed["detail"]["commands"][0]["command"], "PING");
+        assert_eq!(detailed["detail"]["storage"]["ok"], true);
+    }
+}
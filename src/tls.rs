@@ -0,0 +1,409 @@
+//! TLS identity loading and hot reload
+//!
+//! The server identity is re-read from `cert_path`/`key_path` when the files
+//! change on disk (polled every `tls_watch_interval` seconds) or on `SIGHUP`.
+//! A new identity only affects new handshakes; established QUIC connections
+//! keep the keys they negotiated. If the new files do not load, the old
+//! identity stays in use.
+//!
+//! For development, with `dev_cert` enabled and no certificate on disk, the
+//! server makes its own short-lived self-signed ECDSA certificate, which
+//! browsers accept through `serverCertificateHashes`, and rotates it before
+//! it expires.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use base64::Engine;
+use chrono::{DateTime, Datelike, Days, Utc};
+use wtransport::config::Ipv6DualStackConfig;
+use wtransport::tls::{Certificate, CertificateChain, PrivateKey, Sha256DigestFmt};
+use wtransport::{Identity, ServerConfig};
+
+use crate::config::Config;
+use crate::error::{WmtpError, WmtpResult};
+use crate::listener::ListenerSpec;
+
+/// Size and modification time of a file, to notice replacements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    /// Stamp of the file at `path` (`None` if it cannot be read)
+    pub fn of(path: &Path) -> Option<Self> {
+        let meta = std::fs::metadata(path).ok()?;
+        Some(Self {
+            len: meta.len(),
+            modified: meta.modified().ok(),
+        })
+    }
+}
+
+/// Load and check an identity from PEM files
+pub async fn load_identity(cert_path: &Path, key_path: &Path) -> WmtpResult<Identity> {
+    let identity = Identity::load_pemfiles(cert_path, key_path).await.map_err(|e| {
+        WmtpError::Tls(format!(
+            "cannot load {} / {}: {e}",
+            cert_path.display(),
+            key_path.display()
+        ))
+    })?;
+    if identity.certificate_chain().as_slice().is_empty() {
+        return Err(WmtpError::Tls(format!(
+            "no certificate in {}",
+            cert_path.display()
+        )));
+    }
+    Ok(identity)
+}
+
+/// SHA-256 fingerprint of the leaf certificate
+pub fn fingerprint(identity: &Identity) -> String {
+    identity
+        .certificate_chain()
+        .as_slice()
+        .first()
+        .map(|cert| cert.hash().fmt(Sha256DigestFmt::DottedHex))
+        .unwrap_or_default()
+}
+
+/// SHA-256 of the leaf certificate, base64 (as `serverCertificateHashes` wants it)
+pub fn hash_base64(identity: &Identity) -> String {
+    identity
+        .certificate_chain()
+        .as_slice()
+        .first()
+        .map(|cert| base64::engine::general_purpose::STANDARD.encode(cert.hash().as_ref()))
+        .unwrap_or_default()
+}
+
+/// WebTransport server configuration for one listener and an identity
+///
+/// The TLS stack panics on keys it does not support; that is reported as an
+/// error here so a bad key on reload cannot take the server down.
+pub fn server_config(
+    config: &Config,
+    listener: &ListenerSpec,
+    identity: &Identity,
+) -> WmtpResult<ServerConfig> {
+    let idle = config.quic_idle_timeout();
+    let keep_alive = config.quic_keep_alive();
+
+    std::panic::catch_unwind(|| {
+        let builder = ServerConfig::builder();
+        let builder = match listener.addr {
+            SocketAddr::V6(addr) if listener.v6_only => {
+                builder.with_bind_address_v6(addr, Ipv6DualStackConfig::Deny)
+            }
+            addr => builder.with_bind_address(addr),
+        };
+        builder
+            .with_identity(identity)
+            .max_idle_timeout(Some(idle))
+            .map(|builder| builder.keep_alive_interval(Some(keep_alive)).build())
+    })
+    .map_err(|_| WmtpError::Tls("private key is not supported or does not parse".to_string()))?
+    .map_err(|e| WmtpError::Config(format!("quic_idle_timeout: {e}")))
+}
+
+/// One server configuration per endpoint: every accept shard of every
+/// listener, in `Config::listener_specs` order
+pub fn server_configs(config: &Config, identity: &Identity) -> WmtpResult<Vec<ServerConfig>> {
+    let mut configs = Vec::new();
+    for listener in config.listener_specs()? {
+        let addrs = listener
+            .shard_addrs(config.accept_shards)
+            .map_err(|e| WmtpError::Config(format!("accept_shards: {e}")))?;
+        for addr in addrs {
+            let shard = ListenerSpec {
+                addr,
+                ..listener.clone()
+            };
+            configs.push(server_config(config, &shard, identity)?);
+        }
+    }
+    Ok(configs)
+}
+
+/// The identity files currently in use
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Loaded {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    stamps: (Option<FileStamp>, Option<FileStamp>),
+    fingerprint: String,
+}
+
+/// Tracks the identity files and reloads them when they change
+pub struct CertReloader {
+    loaded: Mutex<Loaded>,
+}
+
+impl CertReloader {
+    /// Start tracking the files the initial identity was loaded from
+    pub fn new(cert_path: &Path, key_path: &Path, identity: &Identity) -> Self {
+        Self {
+            loaded: Mutex::new(Loaded {
+                cert_path: cert_path.to_path_buf(),
+                key_path: key_path.to_path_buf(),
+                stamps: (FileStamp::of(cert_path), FileStamp::of(key_path)),
+                fingerprint: fingerprint(identity),
+            }),
+        }
+    }
+
+    /// Fingerprint of the identity in use
+    pub fn fingerprint(&self) -> String {
+        self.loaded.lock().unwrap().fingerprint.clone()
+    }
+
+    /// Whether the configured files differ from the ones in use
+    pub fn changed(&self, config: &Config) -> bool {
+        let loaded = self.loaded.lock().unwrap();
+        loaded.cert_path != config.cert_path
+            || loaded.key_path != config.key_path
+            || loaded.stamps != (FileStamp::of(&config.cert_path), FileStamp::of(&config.key_path))
+    }
+
+    /// Load the configured identity if its files changed (or if `force`)
+    ///
+    /// Returns the new identity and a server configuration per listener,
+    /// `None` if nothing changed. On error the identity in use is kept and the failed files
+    /// are not retried until they change again.
+    pub async fn reload(
+        &self,
+        config: &Config,
+        force: bool,
+    ) -> WmtpResult<Option<(Identity, Vec<ServerConfig>)>> {
+        if !force && !self.changed(config) {
+            return Ok(None);
+        }
+        let stamps = (FileStamp::of(&config.cert_path), FileStamp::of(&config.key_path));
+
+        let result = match load_identity(&config.cert_path, &config.key_path).await {
+            Ok(identity) => server_configs(config, &identity).map(|sc| (identity, sc)),
+            Err(e) => Err(e),
+        };
+
+        let mut loaded = self.loaded.lock().unwrap();
+        loaded.cert_path = config.cert_path.clone();
+        loaded.key_path = config.key_path.clone();
+        loaded.stamps = stamps;
+        let (identity, server_configs) = result?;
+        loaded.fingerprint = fingerprint(&identity);
+        Ok(Some((identity, server_configs)))
+    }
+}
+
+// ============================================================================
+// DEVELOPMENT CERTIFICATE
+// ============================================================================
+
+/// Validity of a development certificate (browsers refuse hashes of
+/// certificates valid for more than 14 days)
+pub const DEV_CERT_VALIDITY_DAYS: u64 = 13;
+
+/// Age at which a development certificate is replaced
+pub const DEV_CERT_ROTATE_AFTER: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Names a development certificate is issued for
+pub fn dev_subject_alt_names(config: &Config) -> Vec<String> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()];
+    for name in [&config.domain, &config.host] {
+        let unspecified = name.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_unspecified());
+        if !unspecified && !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    names
+}
+
+/// Generate a self-signed ECDSA P-256 identity valid from today (UTC
+/// midnight) for `DEV_CERT_VALIDITY_DAYS`; returns it with its expiry
+pub fn generate_dev_identity(config: &Config) -> WmtpResult<(Identity, DateTime<Utc>)> {
+    let tls_err = |e: rcgen::Error| WmtpError::Tls(format!("cannot generate certificate: {e}"));
+
+    let today = Utc::now().date_naive();
+    let expiry = today
+        .checked_add_days(Days::new(DEV_CERT_VALIDITY_DAYS))
+        .ok_or_else(|| WmtpError::Tls("certificate expiry out of range".to_string()))?;
+    let ymd = |d: chrono::NaiveDate| rcgen::date_time_ymd(d.year(), d.month() as u8, d.day() as u8);
+
+    let key_pair = rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256).map_err(tls_err)?;
+    let mut params = rcgen::CertificateParams::new(dev_subject_alt_names(config)).map_err(tls_err)?;
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "WMTP development certificate");
+    params.not_before = ymd(today);
+    params.not_after = ymd(expiry);
+    let cert = params.self_signed(&key_pair).map_err(tls_err)?;
+
+    let certificate = Certificate::from_der(cert.der().to_vec())
+        .map_err(|e| WmtpError::Tls(format!("generated certificate is invalid: {e}")))?;
+    let identity = Identity::new(
+        CertificateChain::single(certificate),
+        PrivateKey::from_der_pkcs8(key_pair.serialize_der()),
+    );
+    Ok((identity, expiry.and_time(chrono::NaiveTime::MIN).and_utc()))
+}
+
+struct DevState {
+    hash: String,
+    expires_at: DateTime<Utc>,
+    issued: Instant,
+}
+
+/// The development certificate in use, and when to replace it
+pub struct DevCert {
+    state: Mutex<DevState>,
+}
+
+impl DevCert {
+    /// Generate the first development identity
+    pub fn generate(config: &Config) -> WmtpResult<(Self, Identity)> {
+        let (identity, expires_at) = generate_dev_identity(config)?;
+        let dev = Self {
+            state: Mutex::new(DevState {
+                hash: hash_base64(&identity),
+                expires_at,
+                issued: Instant::now(),
+            }),
+        };
+        Ok((dev, identity))
+    }
+
+    /// Base64 SHA-256 of the certificate in use
+    pub fn hash(&self) -> String {
+        self.state.lock().unwrap().hash.clone()
+    }
+
+    /// Whether the certificate is old enough to be replaced
+    pub fn due(&self) -> bool {
+        self.state.lock().unwrap().issued.elapsed() >= DEV_CERT_ROTATE_AFTER
+    }
+
+    /// Replace the certificate; returns the new identity and a server
+    /// configuration per listener
+    pub fn rotate(&self, config: &Config) -> WmtpResult<(Identity, Vec<ServerConfig>)> {
+        let (identity, expires_at) = generate_dev_identity(config)?;
+        let server_configs = server_configs(config, &identity)?;
+        *self.state.lock().unwrap() = DevState {
+            hash: hash_base64(&identity),
+            expires_at,
+            issued: Instant::now(),
+        };
+        Ok((identity, server_configs))
+    }
+
+    /// Body of the local `/cert-hash` endpoint
+    pub fn hash_info(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        serde_json::json!({
+            "algorithm": "sha-256",
+            "value": state.hash,
+            "expires_at": state.expires_at.to_rfc3339(),
+        })
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_identity(dir: &Path, identity: &Identity) -> (PathBuf, PathBuf) {
+        let cert = dir.join("cert.pem");
+        let key = dir.join("key.pem");
+        std::fs::write(&cert, identity.certificate_chain().as_slice()[0].to_pem()).unwrap();
+        std::fs::write(&key, identity.private_key().to_secret_pem()).unwrap();
+        (cert, key)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wmtp-tls-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_reload_on_change_and_keep_old_on_error() {
+        let dir = temp_dir("reload");
+        let first = Identity::self_signed(["localhost"]).unwrap();
+        let (cert, key) = write_identity(&dir, &first);
+        let config = Config {
+            host: "127.0.0.1".to_string(),
+            cert_path: cert.clone(),
+            key_path: key.clone(),
+            ..Config::default()
+        };
+
+        let loaded = load_identity(&cert, &key).await.unwrap();
+        let reloader = CertReloader::new(&cert, &key, &loaded);
+        assert_eq!(reloader.fingerprint(), fingerprint(&first));
+        assert!(reloader.reload(&config, false).await.unwrap().is_none());
+
+        // Renewed certificate (different length, so the stamp changes even
+        // on filesystems with coarse timestamps)
+        let second = Identity::self_signed(["localhost", "renewed.example"]).unwrap();
+        write_identity(&dir, &second);
+        let (identity, _) = reloader.reload(&config, false).await.unwrap().unwrap();
+        assert_eq!(fingerprint(&identity), fingerprint(&second));
+        assert_eq!(reloader.fingerprint(), fingerprint(&second));
+
+        // Broken key: error, old identity kept, not retried until changed
+        std::fs::write(&key, "not a key").unwrap();
+        assert!(matches!(reloader.reload(&config, false).await, Err(WmtpError::Tls(_))));
+        assert_eq!(reloader.fingerprint(), fingerprint(&second));
+        assert!(reloader.reload(&config, false).await.unwrap().is_none());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_dev_identity() {
+        let config = Config {
+            domain: "mail.example".to_string(),
+            ..Config::default()
+        };
+        assert_eq!(
+            dev_subject_alt_names(&config),
+            vec!["localhost", "127.0.0.1", "::1", "mail.example"]
+        );
+
+        let (dev, identity) = DevCert::generate(&config).unwrap();
+        let hash = dev.hash();
+        assert_eq!(hash, hash_base64(&identity));
+        assert_eq!(
+            base64::engine::general_purpose::STANDARD.decode(&hash).unwrap().len(),
+            32
+        );
+        assert!(!dev.due());
+
+        let info = dev.hash_info();
+        assert_eq!(info["algorithm"], "sha-256");
+        let expires: DateTime<Utc> = info["expires_at"].as_str().unwrap().parse().unwrap();
+        assert!(expires - Utc::now() < chrono::Duration::days(14));
+        assert!(expires - Utc::now() > chrono::Duration::days(12));
+
+        let config = Config {
+            host: "127.0.0.1".to_string(),
+            ..config
+        };
+        dev.rotate(&config).unwrap();
+        assert_ne!(dev.hash(), hash);
+    }
+
+    #[tokio::test]
+    async fn test_missing_files() {
+        let missing = Path::new("/nonexistent/cert.pem");
+        assert!(matches!(load_identity(missing, missing).await, Err(WmtpError::Tls(_))));
+    }
+}
@@ -54,6 +54,13 @@ pub fn generate_ephemeral_token() -> String {
     format!("WMTP-{}", uuid::Uuid::new_v4())
 }
 
+/// Generate a per-device session token for a linked device (non-deterministic)
+/// 
+/// Unlike identity tokens, each linked device gets its own token.
+pub fn generate_device_token() -> String {
+    format!("DEV-{}", uuid::Uuid::new_v4().simple())
+}
+
 /// Check if a token is ephemeral (temporary)
 pub fn is_ephemeral_token(token: &str) -> bool {
     token.starts_with("WMTP-")
@@ -133,4 +140,12 @@ mod tests {
         assert!(!is_ephemeral_token("regular-token"));
         assert!(token.starts_with("WMTP-"));
     }
+
+    #[test]
+    fn test_device_token() {
+        let token1 = generate_device_token();
+        let token2 = generate_device_token();
+        assert_ne!(token1, token2);
+        assert!(!is_ephemeral_token(&token1));
+    }
 }